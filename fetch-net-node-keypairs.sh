@@ -0,0 +1,72 @@
+#!/usr/bin/env bash
+#
+# Fetches the keypairs of the net/ nodes that ramp-tps needs to replace their
+# bootstrap stake
+#
+# The keypair of the replacement stake account is kept on each node, so that a
+# node is never staked twice, even by ramp-tps runs on different machines. A
+# node without one is given a new keypair before it is fetched.
+#
+
+netdir=$1
+keypairdir=${2:-.destake}
+
+if [[ -z $netdir ]]; then
+  echo "Usage: $0 net-dir [keypair-dir]"
+  exit 1
+fi
+
+if [[ ! -r "$netdir"/gce.sh ]]; then
+  echo "Error: invalid netdir: $netdir"
+  exit 1
+fi
+
+eval $("$netdir"/gce.sh info --eval)
+
+scp="$netdir"/scp.sh
+ssh="$netdir"/ssh.sh
+
+# Fetches the replacement stake keypair of node $2 at $1, recording a new one on
+# the node first if it has none
+fetch_tds_stake_keypair() {
+  declare ip=$1
+  declare i=$2
+  declare status=0
+  $ssh solana@"$ip" test -f '~/solana/config/tds-stake-keypair.json' || status=$?
+  case $status in
+  0)
+    $scp solana@"$ip":~/solana/config/tds-stake-keypair.json $i-tds-stake-keypair.json
+    ;;
+  1)
+    if [[ ! -f $i-tds-stake-keypair.json ]]; then
+      solana-keygen new -f -o $i-tds-stake-keypair.json
+    fi
+    $scp $i-tds-stake-keypair.json solana@"$ip":~/solana/config/tds-stake-keypair.json
+    ;;
+  *)
+    echo "Error: unable to reach node $i at $ip"
+    exit 1
+    ;;
+  esac
+}
+
+set -e
+mkdir -p "$keypairdir"
+cd "$keypairdir"
+
+echo Fetching bootstrap leader keys
+$scp solana@"$NET_VALIDATOR0_IP":~/solana/config/bootstrap-leader/stake-keypair.json 0-stake-keypair.json
+$scp solana@"$NET_VALIDATOR0_IP":~/solana/config/bootstrap-leader/vote-keypair.json 0-vote-keypair.json
+$scp solana@"$NET_VALIDATOR0_IP":~/solana/config/bootstrap-leader/identity-keypair.json 0-identity-keypair.json
+fetch_tds_stake_keypair "$NET_VALIDATOR0_IP" 0
+
+for i in $(seq 1 $((NET_NUM_VALIDATORS - 1))); do
+  v="NET_VALIDATOR${i}_IP"
+  echo "Fetching $v keys"
+  $scp solana@"${!v}":~/solana/config/validator/stake-keypair.json $i-stake-keypair.json
+  $scp solana@"${!v}":~/solana/config/validator/vote-keypair.json $i-vote-keypair.json
+  $scp solana@"${!v}":~/solana/config/validator-identity.json $i-identity-keypair.json
+  fetch_tds_stake_keypair "${!v}" $i
+done
+
+ls -l
@@ -0,0 +1,296 @@
+//! Generates transaction load from the local machine, in place of running solana-bench-tps on
+//! remote client hosts
+
+use crate::rpc::{CallCategory, RpcPool};
+use log::*;
+use solana_client::thin_client::{create_client, ThinClient};
+use solana_sdk::{
+    client::{AsyncClient, SyncClient},
+    native_token::lamports_to_sol,
+    pubkey::Pubkey,
+    signature::{Keypair, KeypairUtil, Signature},
+    system_transaction,
+};
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, sleep, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Local ports used by the bench clients for sending transactions
+const CLIENT_PORT_RANGE: (u16, u16) = (12_000, 13_000);
+
+#[derive(Clone, Debug)]
+pub struct BenchConfig {
+    pub num_clients: usize,
+    pub threads_per_client: usize,
+    /// Cap on the combined rate of all clients, in transactions per second
+    pub target_tps: Option<u64>,
+    pub thread_batch_sleep: Duration,
+    /// Each thread pays for its transactions from an account funded with this many lamports,
+    /// and whatever is left is returned when the bench stops
+    pub lamports_per_thread: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct BenchCounters {
+    pub submitted: AtomicU64,
+    /// Estimated by checking the status of the last transaction in each batch
+    pub confirmed: AtomicU64,
+    pub failed: AtomicU64,
+}
+
+impl BenchCounters {
+    pub fn submitted(&self) -> u64 {
+        self.submitted.load(Ordering::Relaxed)
+    }
+
+    pub fn confirmed(&self) -> u64 {
+        self.confirmed.load(Ordering::Relaxed)
+    }
+
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+}
+
+pub struct Bench {
+    exit: Arc<AtomicBool>,
+    /// Every thread hands its payer back once it exits
+    threads: Vec<JoinHandle<Keypair>>,
+    counters: Arc<BenchCounters>,
+    funding_pubkey: Pubkey,
+}
+
+/// Returns the (rpc, tpu) addresses of every cluster node which exposes both
+fn fetch_client_addrs(rpc: &RpcPool) -> Result<Vec<(SocketAddr, SocketAddr)>, String> {
+    let nodes = rpc.call(CallCategory::Query, "get_cluster_nodes", |rpc_client| {
+        rpc_client.get_cluster_nodes()
+    })?;
+    let client_addrs: Vec<_> = nodes
+        .into_iter()
+        .filter_map(|node| match (node.rpc, node.tpu) {
+            (Some(rpc), Some(tpu)) => Some((rpc, tpu)),
+            _ => None,
+        })
+        .collect();
+    if client_addrs.is_empty() {
+        return Err("No cluster nodes with an RPC and TPU address".to_string());
+    }
+    Ok(client_addrs)
+}
+
+fn run_client_thread(
+    client: ThinClient,
+    payer: Keypair,
+    batch_size: u64,
+    batch_interval: Duration,
+    exit: Arc<AtomicBool>,
+    counters: Arc<BenchCounters>,
+) -> Keypair {
+    let mut last_batch: Option<(Signature, u64)> = None;
+    while !exit.load(Ordering::Relaxed) {
+        let batch_start = Instant::now();
+        if let Some((signature, batch_len)) = last_batch.take() {
+            if let Ok(Some(Ok(()))) = client.get_signature_status(&signature) {
+                counters.confirmed.fetch_add(batch_len, Ordering::Relaxed);
+            }
+        }
+
+        let recent_blockhash = match client.get_recent_blockhash() {
+            Ok((recent_blockhash, _fee_calculator)) => recent_blockhash,
+            Err(err) => {
+                warn!("Bench client failed to get a recent blockhash: {}", err);
+                sleep(batch_interval);
+                continue;
+            }
+        };
+
+        let mut sent = 0;
+        let mut last_signature = None;
+        for _ in 0..batch_size {
+            if exit.load(Ordering::Relaxed) {
+                break;
+            }
+            // A fresh recipient keeps every transaction signature unique
+            let transaction =
+                system_transaction::transfer(&payer, &Pubkey::new_rand(), 1, recent_blockhash);
+            match client.async_send_transaction(transaction) {
+                Ok(signature) => {
+                    sent += 1;
+                    last_signature = Some(signature);
+                }
+                Err(err) => {
+                    debug!("Bench client failed to send transaction: {}", err);
+                    counters.failed.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        counters.submitted.fetch_add(sent, Ordering::Relaxed);
+        last_batch = last_signature.map(|signature| (signature, sent));
+
+        let elapsed = batch_start.elapsed();
+        if elapsed < batch_interval {
+            sleep(batch_interval - elapsed);
+        }
+    }
+    payer
+}
+
+/// Transfer the balance of `payer`, less the fee, back to `recipient`. Returns the lamports
+/// transferred
+fn drain_payer(rpc: &RpcPool, payer: &Keypair, recipient: &Pubkey) -> Result<u64, String> {
+    rpc.call(
+        CallCategory::Transaction,
+        "drain bench payer",
+        |rpc_client| -> Result<u64, String> {
+            let balance = rpc_client
+                .get_balance(&payer.pubkey())
+                .map_err(|err| err.to_string())?;
+            let (recent_blockhash, fee_calculator) = rpc_client
+                .get_recent_blockhash()
+                .map_err(|err| err.to_string())?;
+            if balance <= fee_calculator.lamports_per_signature {
+                return Ok(0);
+            }
+            let lamports = balance - fee_calculator.lamports_per_signature;
+            let mut transaction =
+                system_transaction::transfer(payer, recipient, lamports, recent_blockhash);
+            rpc_client
+                .send_and_confirm_transaction(&mut transaction, &[payer])
+                .map_err(|err| err.to_string())?;
+            Ok(lamports)
+        },
+    )
+}
+
+impl Bench {
+    /// Fund a payer for every bench thread from `funding_keypair`, then submit batches of
+    /// `tx_count` transactions, split evenly across all threads, until stopped
+    pub fn start(
+        rpc: &RpcPool,
+        funding_keypair: &Keypair,
+        config: &BenchConfig,
+        tx_count: u64,
+    ) -> Result<Self, String> {
+        let mut bench = Bench {
+            exit: Arc::new(AtomicBool::new(false)),
+            threads: vec![],
+            counters: Arc::new(BenchCounters::default()),
+            funding_pubkey: funding_keypair.pubkey(),
+        };
+        let num_threads = (config.num_clients * config.threads_per_client) as u64;
+        if num_threads == 0 || tx_count == 0 {
+            info!("No bench clients to start");
+            return Ok(bench);
+        }
+
+        let client_addrs = fetch_client_addrs(rpc)?;
+        let batch_size = (tx_count / num_threads).max(1);
+        let batch_interval = match config.target_tps {
+            Some(target_tps) => {
+                let thread_tps = (target_tps / num_threads).max(1);
+                config
+                    .thread_batch_sleep
+                    .max(Duration::from_millis(batch_size * 1000 / thread_tps))
+            }
+            None => config.thread_batch_sleep,
+        };
+        info!(
+            "Starting {} bench clients with {} threads each: batch size={}, batch interval={:?}",
+            config.num_clients, config.threads_per_client, batch_size, batch_interval
+        );
+
+        for client_id in 0..config.num_clients {
+            let client_addr = client_addrs[client_id % client_addrs.len()];
+            for _ in 0..config.threads_per_client {
+                let payer = Keypair::new();
+                // Funding many payers can outlast a blockhash, so every attempt fetches its own
+                let funded = rpc.call(
+                    CallCategory::Transaction,
+                    "fund bench payer",
+                    |rpc_client| -> Result<String, String> {
+                        let (recent_blockhash, _fee_calculator) = rpc_client
+                            .get_recent_blockhash()
+                            .map_err(|err| err.to_string())?;
+                        let mut transaction = system_transaction::transfer(
+                            funding_keypair,
+                            &payer.pubkey(),
+                            config.lamports_per_thread,
+                            recent_blockhash,
+                        );
+                        rpc_client
+                            .send_and_confirm_transaction(&mut transaction, &[funding_keypair])
+                            .map_err(|err| err.to_string())
+                    },
+                );
+                if let Err(err) = funded {
+                    // Return the funds of the payers which are already running
+                    bench.stop(rpc);
+                    return Err(format!(
+                        "Unable to fund bench payer {}: {}",
+                        payer.pubkey(),
+                        err
+                    ));
+                }
+
+                let exit = bench.exit.clone();
+                let counters = bench.counters.clone();
+                bench.threads.push(
+                    thread::Builder::new()
+                        .name(format!("bench-client-{}", client_id))
+                        .spawn(move || {
+                            let client = create_client(client_addr, CLIENT_PORT_RANGE);
+                            run_client_thread(
+                                client,
+                                payer,
+                                batch_size,
+                                batch_interval,
+                                exit,
+                                counters,
+                            )
+                        })
+                        .unwrap(),
+                );
+            }
+        }
+
+        Ok(bench)
+    }
+
+    pub fn counters(&self) -> &BenchCounters {
+        &self.counters
+    }
+
+    /// Signal every bench thread to exit, wait for them to finish and return what is left in
+    /// their payers to the funding account
+    pub fn stop(self, rpc: &RpcPool) -> Arc<BenchCounters> {
+        self.exit.store(true, Ordering::Relaxed);
+        let mut drained = 0;
+        for thread in self.threads {
+            let payer = match thread.join() {
+                Ok(payer) => payer,
+                Err(_) => {
+                    warn!("Bench client thread panicked");
+                    continue;
+                }
+            };
+            match drain_payer(rpc, &payer, &self.funding_pubkey) {
+                Ok(lamports) => drained += lamports,
+                Err(err) => warn!("Unable to drain bench payer {}: {}", payer.pubkey(), err),
+            }
+        }
+        if drained > 0 {
+            info!(
+                "Returned {} SOL from the bench payers to {}",
+                lamports_to_sol(drained),
+                self.funding_pubkey
+            );
+        }
+        self.counters
+    }
+}
@@ -0,0 +1,227 @@
+//! Checks that the mint can pay for the stake gifts before they are sent
+
+use crate::{
+    bench::BenchConfig,
+    gift::{GiftCandidate, GiftPolicy},
+};
+use serde_derive::{Deserialize, Serialize};
+use solana_sdk::{native_token::lamports_to_sol, pubkey::Pubkey};
+use std::{fmt, str::FromStr};
+
+/// The mint and the new stake account both sign each gift transaction
+pub const SIGNATURES_PER_GIFT: u64 = 2;
+
+/// What to do when the mint balance does not cover the planned gifts
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BudgetPolicy {
+    /// Post a warning and send the gifts anyway
+    Warn,
+    /// Scale every gift down so that the gifts and their fees fit in the mint balance
+    Cap,
+    /// Stop ramp-tps before any gift is sent
+    Bail,
+}
+
+impl fmt::Display for BudgetPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BudgetPolicy::Warn => write!(f, "warn"),
+            BudgetPolicy::Cap => write!(f, "cap"),
+            BudgetPolicy::Bail => write!(f, "bail"),
+        }
+    }
+}
+
+impl FromStr for BudgetPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(BudgetPolicy::Warn),
+            "cap" => Ok(BudgetPolicy::Cap),
+            "bail" => Ok(BudgetPolicy::Bail),
+            _ => Err(format!(
+                "unknown budget policy {}, expected warn, cap or bail",
+                s
+            )),
+        }
+    }
+}
+
+fn gift_fees(num_gifts: usize, lamports_per_signature: u64) -> u64 {
+    lamports_per_signature
+        .saturating_mul(SIGNATURES_PER_GIFT)
+        .saturating_mul(num_gifts as u64)
+}
+
+/// Lamports the mint needs to send `gifts`, including transaction fees
+pub fn required_lamports(gifts: &[u64], lamports_per_signature: u64) -> u64 {
+    gifts.iter().fold(
+        gift_fees(gifts.len(), lamports_per_signature),
+        |total, lamports| total.saturating_add(*lamports),
+    )
+}
+
+/// Scales `gifts` down in proportion so that they and their fees fit in `balance`
+pub fn cap_gifts(gifts: &mut [u64], balance: u64, lamports_per_signature: u64) {
+    let fees = gift_fees(gifts.len(), lamports_per_signature);
+    let available = u128::from(balance.saturating_sub(fees));
+    let total: u128 = gifts.iter().map(|lamports| u128::from(*lamports)).sum();
+    if total <= available {
+        return;
+    }
+    for lamports in gifts.iter_mut() {
+        *lamports = (u128::from(*lamports) * available / total) as u64;
+    }
+}
+
+/// Lamports needed to gift every one of `num_candidates` validators in each of `num_rounds`
+/// rounds from `first_round` on, assuming that none of them drop out
+pub fn schedule_lamports(
+    gift_policy: &GiftPolicy,
+    first_round: u32,
+    num_rounds: u32,
+    num_candidates: usize,
+    lamports_per_signature: u64,
+) -> u64 {
+    let candidates = vec![
+        GiftCandidate {
+            name: String::new(),
+            identity: Pubkey::default(),
+            vote_pubkey: Pubkey::default(),
+            round_credits: 0,
+        };
+        num_candidates
+    ];
+    (first_round..first_round.saturating_add(num_rounds))
+        .map(|round| {
+            required_lamports(
+                &gift_policy.plan(round, &candidates),
+                lamports_per_signature,
+            )
+        })
+        .fold(0, u64::saturating_add)
+}
+
+/// Lamports that the bench payers are funded with at the start of a round. What they do not
+/// spend is returned when the round ends, but they may spend all of it
+pub fn bench_lamports(bench_config: &BenchConfig) -> u64 {
+    bench_config.lamports_per_thread.saturating_mul(
+        bench_config
+            .num_clients
+            .saturating_mul(bench_config.threads_per_client) as u64,
+    )
+}
+
+/// Everything the mint pays for over the next rounds
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Budget {
+    /// Stake gifts and their fees
+    pub gift_lamports: u64,
+    /// Funding of the bench payers in every round
+    pub bench_lamports: u64,
+    /// Replacement stake of the net nodes, while they are still to be destaked
+    pub destake_lamports: u64,
+}
+
+impl Budget {
+    pub fn total(&self) -> u64 {
+        self.gift_lamports
+            .saturating_add(self.bench_lamports)
+            .saturating_add(self.destake_lamports)
+    }
+}
+
+impl fmt::Display for Budget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} SOL: {} SOL of stake gifts, {} SOL of bench funding and {} SOL to restake the \
+             net nodes",
+            lamports_to_sol(self.total()),
+            lamports_to_sol(self.gift_lamports),
+            lamports_to_sol(self.bench_lamports),
+            lamports_to_sol(self.destake_lamports)
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use solana_sdk::native_token::sol_to_lamports;
+    use std::time::Duration;
+
+    #[test]
+    fn test_required_lamports() {
+        assert_eq!(required_lamports(&[], 5), 0);
+        assert_eq!(required_lamports(&[100, 200], 5), 320);
+        assert_eq!(
+            required_lamports(&[u64::max_value(), 1], 0),
+            u64::max_value()
+        );
+    }
+
+    #[test]
+    fn test_cap_gifts() {
+        let mut gifts = vec![100, 300];
+        cap_gifts(&mut gifts, 1000, 5);
+        assert_eq!(gifts, vec![100, 300]);
+        cap_gifts(&mut gifts, 220, 5);
+        assert_eq!(gifts, vec![50, 150]);
+        cap_gifts(&mut gifts, 10, 5);
+        assert_eq!(gifts, vec![0, 0]);
+    }
+
+    #[test]
+    fn test_schedule_lamports() {
+        let doubling = GiftPolicy::Doubling { initial_sol: 1.0 };
+        assert_eq!(
+            schedule_lamports(&doubling, 1, 3, 2, 0),
+            sol_to_lamports(14.0)
+        );
+        assert_eq!(
+            schedule_lamports(&doubling, 2, 1, 2, 10),
+            sol_to_lamports(4.0) + 40
+        );
+        assert_eq!(schedule_lamports(&doubling, 1, 0, 2, 10), 0);
+    }
+
+    #[test]
+    fn test_budget() {
+        let bench_config = BenchConfig {
+            num_clients: 2,
+            threads_per_client: 4,
+            target_tps: None,
+            thread_batch_sleep: Duration::from_millis(250),
+            lamports_per_thread: sol_to_lamports(1000.0),
+        };
+        assert_eq!(bench_lamports(&bench_config), sol_to_lamports(8000.0));
+
+        let budget = Budget {
+            gift_lamports: schedule_lamports(
+                &GiftPolicy::Doubling { initial_sol: 1.0 },
+                1,
+                3,
+                2,
+                0,
+            ),
+            bench_lamports: bench_lamports(&bench_config) * 3,
+            destake_lamports: sol_to_lamports(2.0),
+        };
+        assert_eq!(budget.total(), sol_to_lamports(24_016.0));
+        assert_eq!(
+            budget.to_string(),
+            "24016 SOL: 14 SOL of stake gifts, 24000 SOL of bench funding and 2 SOL to restake \
+             the net nodes"
+        );
+    }
+
+    #[test]
+    fn test_budget_policy_from_str() {
+        assert_eq!("cap".parse(), Ok(BudgetPolicy::Cap));
+        assert!("ignore".parse::<BudgetPolicy>().is_err());
+        assert_eq!(BudgetPolicy::Bail.to_string(), "bail");
+    }
+}
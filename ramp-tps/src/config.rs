@@ -0,0 +1,737 @@
+//! Settings of a ramp-tps run
+//!
+//! Every setting has a default which can be overridden by a YAML or TOML config file
+//! (`--config`), which in turn is overridden by any command line flag that is given.
+
+use crate::{
+    budget::BudgetPolicy,
+    gift::GiftPolicy,
+    health::{HealthGate, MAX_SKIP_RATE_SLOTS},
+    rpc::RetryPolicies,
+    schedule::Schedule,
+    utils,
+};
+use clap::ArgMatches;
+use serde_derive::{Deserialize, Serialize};
+use solana_sdk::hash::Hash;
+use std::{fmt::Display, fs, net::SocketAddr, path::Path, str::FromStr, time::Duration};
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Host used for RPC calls
+    pub entrypoint: String,
+    pub mint_keypair_path: String,
+    /// The directory used for running commands on the cluster
+    pub net_dir: Option<String>,
+    pub pubkey_map_file: String,
+    pub results_file: String,
+    pub state_file: String,
+    /// Notification backends are configured from the environment if this is not set
+    pub notifier_config: Option<String>,
+    /// Unix socket which accepts operator commands, see the `control` module
+    pub control_socket: Option<String>,
+    /// `HOST:PORT` to serve the progress of the ramp on, see the `status` module
+    pub status_addr: Option<String>,
+    /// Where genesis blocks are downloaded to and cached, by hash
+    pub tmp_ledger_path: String,
+    /// Refuse to run against a cluster with any other genesis block
+    pub expected_genesis_hash: Option<String>,
+    /// Timeout of every read while downloading the genesis block
+    pub genesis_timeout_secs: u64,
+    /// Start over from this round, ignoring any progress saved in `state_file`
+    pub round: Option<u32>,
+    pub stake_activation_epoch: Option<u64>,
+    pub destake_net_nodes_epoch: u64,
+    /// Idle time between stopping transactions and awarding stake
+    pub cooldown_secs: u64,
+    /// How long to wait for the slot to advance before starting a round
+    pub slot_advance_check_secs: u64,
+    /// How long the slot may stop advancing while waiting for an epoch or slot
+    pub slot_stall_timeout_secs: u64,
+    /// Run the schedule without starting bench clients, delegating stake or saving progress
+    pub dry_run: bool,
+    pub schedule: ScheduleSettings,
+    pub bench: BenchSettings,
+    pub gift: GiftSettings,
+    pub survivors: SurvivorSettings,
+    pub rpc: RpcSettings,
+    pub destake: DestakeSettings,
+    pub health: HealthSettings,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScheduleSettings {
+    /// Schedule of every round, which overrides the linear schedule below
+    pub file: Option<String>,
+    pub round_minutes: u64,
+    pub tx_count_baseline: u64,
+    pub tx_count_increment: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BenchSettings {
+    pub clients: usize,
+    pub threads: usize,
+    pub target_tps: Option<u64>,
+    pub thread_batch_sleep_ms: u64,
+    pub fund_sol: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GiftSettings {
+    /// SOL that each participant started with, used by the default doubling policy
+    pub initial_balance: f64,
+    pub policy_file: Option<String>,
+    pub dry_run: bool,
+    pub ledger_file: String,
+    pub keypair_dir: String,
+    pub workers: usize,
+    /// What to do when the mint balance does not cover the gifts
+    pub budget_policy: BudgetPolicy,
+    /// Number of rounds that the mint balance is checked against at startup, 0 to skip the check
+    pub budget_rounds: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RpcSettings {
+    /// Fallback RPC endpoints (`HOST:PORT`), in the order that they are failed over to after
+    /// the entrypoint
+    pub endpoints: Vec<String>,
+    pub timeout_secs: u64,
+    pub retry: RetryPolicies,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DestakeSettings {
+    /// Where the keypairs of the net nodes are fetched to, and their new stake keypairs saved
+    pub keypair_dir: String,
+    /// SOL delegated to each net node in place of its bootstrap stake
+    pub stake_sol: f64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthSettings {
+    /// Check the health of the cluster before each round, and delay the round until it passes
+    pub enabled: bool,
+    /// The cluster is sampled at the start and the end of this window
+    pub window_secs: u64,
+    pub require_root_advance: bool,
+    pub min_voting_stake_percent: f64,
+    /// At most 31, the number of votes in a tower
+    pub skip_rate_slots: u64,
+    pub max_skip_rate_percent: f64,
+    pub min_gossip_nodes: usize,
+    /// 0 to skip the check
+    pub max_rpc_latency_ms: u64,
+    pub retry_secs: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SurvivorSettings {
+    pub max_vote_lag_slots: u64,
+    pub max_root_lag_slots: u64,
+    /// In SOL
+    pub min_activated_stake: f64,
+    pub samples: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            entrypoint: "tds.solana.com".to_string(),
+            mint_keypair_path: "mint-keypair.json".to_string(),
+            net_dir: None,
+            pubkey_map_file: "validators/all-username.yml".to_string(),
+            results_file: "results.yml".to_string(),
+            state_file: "ramp-tps-state.yml".to_string(),
+            notifier_config: None,
+            control_socket: Some("ramp-tps.sock".to_string()),
+            status_addr: None,
+            tmp_ledger_path: ".tmp/ledger".to_string(),
+            expected_genesis_hash: None,
+            genesis_timeout_secs: 60,
+            round: None,
+            stake_activation_epoch: None,
+            destake_net_nodes_epoch: 9,
+            cooldown_secs: 5 * 60,
+            slot_advance_check_secs: 5,
+            slot_stall_timeout_secs: 300,
+            dry_run: false,
+            schedule: ScheduleSettings::default(),
+            bench: BenchSettings::default(),
+            gift: GiftSettings::default(),
+            survivors: SurvivorSettings::default(),
+            rpc: RpcSettings::default(),
+            destake: DestakeSettings::default(),
+            health: HealthSettings::default(),
+        }
+    }
+}
+
+impl Default for ScheduleSettings {
+    fn default() -> Self {
+        ScheduleSettings {
+            file: None,
+            round_minutes: 60,
+            tx_count_baseline: 5000,
+            tx_count_increment: 5000,
+        }
+    }
+}
+
+impl Default for BenchSettings {
+    fn default() -> Self {
+        BenchSettings {
+            clients: 2,
+            threads: 4,
+            target_tps: None,
+            thread_batch_sleep_ms: 250,
+            fund_sol: 1000.0,
+        }
+    }
+}
+
+impl Default for GiftSettings {
+    fn default() -> Self {
+        GiftSettings {
+            initial_balance: 1.0,
+            policy_file: None,
+            dry_run: false,
+            ledger_file: "gift-ledger.yml".to_string(),
+            keypair_dir: "gift-keypairs".to_string(),
+            workers: 8,
+            budget_policy: BudgetPolicy::Warn,
+            budget_rounds: 10,
+        }
+    }
+}
+
+impl Default for RpcSettings {
+    fn default() -> Self {
+        RpcSettings {
+            endpoints: vec![],
+            timeout_secs: 10,
+            retry: RetryPolicies::default(),
+        }
+    }
+}
+
+impl Default for DestakeSettings {
+    fn default() -> Self {
+        DestakeSettings {
+            keypair_dir: ".destake".to_string(),
+            stake_sol: 1.0,
+        }
+    }
+}
+
+impl Default for HealthSettings {
+    fn default() -> Self {
+        HealthSettings {
+            enabled: true,
+            window_secs: 10,
+            require_root_advance: true,
+            min_voting_stake_percent: 67.0,
+            skip_rate_slots: MAX_SKIP_RATE_SLOTS,
+            max_skip_rate_percent: 50.0,
+            min_gossip_nodes: 1,
+            max_rpc_latency_ms: 2000,
+            retry_secs: 60,
+        }
+    }
+}
+
+impl Default for SurvivorSettings {
+    fn default() -> Self {
+        SurvivorSettings {
+            max_vote_lag_slots: 128,
+            max_root_lag_slots: 256,
+            min_activated_stake: 0.0,
+            samples: 3,
+        }
+    }
+}
+
+/// Parses the value of the `name` flag, if it was given
+pub fn arg_value<T>(matches: &ArgMatches, name: &str) -> Result<Option<T>, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match matches.value_of(name) {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|err| format!("Invalid --{} {}: {}", name.replace('_', "-"), value, err)),
+        None => Ok(None),
+    }
+}
+
+fn override_value<T>(value: &mut T, matches: &ArgMatches, name: &str) -> Result<(), String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Some(arg) = arg_value(matches, name)? {
+        *value = arg;
+    }
+    Ok(())
+}
+
+fn override_option<T>(value: &mut Option<T>, matches: &ArgMatches, name: &str) -> Result<(), String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Some(arg) = arg_value(matches, name)? {
+        *value = Some(arg);
+    }
+    Ok(())
+}
+
+impl Config {
+    /// Loads a TOML file if `path` ends in `.toml`, YAML otherwise
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents =
+            fs::read_to_string(path).map_err(|err| format!("Unable to read {}: {}", path, err))?;
+        let is_toml = Path::new(path)
+            .extension()
+            .map_or(false, |extension| extension == "toml");
+        Self::parse(&contents, is_toml).map_err(|err| format!("Unable to parse {}: {}", path, err))
+    }
+
+    fn parse(contents: &str, is_toml: bool) -> Result<Self, String> {
+        if is_toml {
+            toml::from_str(contents).map_err(|err| err.to_string())
+        } else if contents.trim().is_empty() {
+            Ok(Config::default())
+        } else {
+            serde_yaml::from_str(contents).map_err(|err| err.to_string())
+        }
+    }
+
+    /// Overrides settings with the command line flags that were given
+    pub fn apply_args(&mut self, matches: &ArgMatches) -> Result<(), String> {
+        override_value(&mut self.entrypoint, matches, "entrypoint")?;
+        override_value(&mut self.mint_keypair_path, matches, "mint_keypair_path")?;
+        override_option(&mut self.net_dir, matches, "net_dir")?;
+        override_value(&mut self.pubkey_map_file, matches, "pubkey_map_file")?;
+        override_value(&mut self.results_file, matches, "results_file")?;
+        override_value(&mut self.state_file, matches, "state_file")?;
+        override_option(&mut self.notifier_config, matches, "notifier_config")?;
+        override_option(&mut self.control_socket, matches, "control_socket")?;
+        if matches.is_present("no_control_socket") {
+            self.control_socket = None;
+        }
+        override_option(&mut self.status_addr, matches, "status_addr")?;
+        override_value(&mut self.tmp_ledger_path, matches, "tmp_ledger_path")?;
+        override_option(
+            &mut self.expected_genesis_hash,
+            matches,
+            "expected_genesis_hash",
+        )?;
+        override_value(
+            &mut self.genesis_timeout_secs,
+            matches,
+            "genesis_timeout_secs",
+        )?;
+        override_option(&mut self.round, matches, "round")?;
+        override_option(
+            &mut self.stake_activation_epoch,
+            matches,
+            "stake_activation_epoch",
+        )?;
+        override_value(
+            &mut self.destake_net_nodes_epoch,
+            matches,
+            "destake_net_nodes_epoch",
+        )?;
+        override_value(&mut self.cooldown_secs, matches, "cooldown_secs")?;
+        override_value(
+            &mut self.slot_advance_check_secs,
+            matches,
+            "slot_advance_check_secs",
+        )?;
+        override_value(
+            &mut self.slot_stall_timeout_secs,
+            matches,
+            "slot_stall_timeout_secs",
+        )?;
+
+        let schedule = &mut self.schedule;
+        override_option(&mut schedule.file, matches, "schedule_file")?;
+        override_value(&mut schedule.round_minutes, matches, "round_minutes")?;
+        override_value(
+            &mut schedule.tx_count_baseline,
+            matches,
+            "tx_count_baseline",
+        )?;
+        override_value(
+            &mut schedule.tx_count_increment,
+            matches,
+            "tx_count_increment",
+        )?;
+
+        let bench = &mut self.bench;
+        override_value(&mut bench.clients, matches, "bench_clients")?;
+        override_value(&mut bench.threads, matches, "bench_threads")?;
+        override_option(&mut bench.target_tps, matches, "bench_target_tps")?;
+        override_value(
+            &mut bench.thread_batch_sleep_ms,
+            matches,
+            "thread_batch_sleep_ms",
+        )?;
+        override_value(&mut bench.fund_sol, matches, "bench_fund_sol")?;
+
+        if matches.is_present("dry_run") {
+            self.dry_run = true;
+        }
+
+        let gift = &mut self.gift;
+        override_value(&mut gift.initial_balance, matches, "initial_balance")?;
+        override_option(&mut gift.policy_file, matches, "gift_policy_file")?;
+        if matches.is_present("gift_dry_run") {
+            gift.dry_run = true;
+        }
+        override_value(&mut gift.ledger_file, matches, "gift_ledger_file")?;
+        override_value(&mut gift.keypair_dir, matches, "gift_keypair_dir")?;
+        override_value(&mut gift.workers, matches, "gift_workers")?;
+        override_value(&mut gift.budget_policy, matches, "gift_budget_policy")?;
+        override_value(&mut gift.budget_rounds, matches, "gift_budget_rounds")?;
+
+        let survivors = &mut self.survivors;
+        override_value(
+            &mut survivors.max_vote_lag_slots,
+            matches,
+            "max_vote_lag_slots",
+        )?;
+        override_value(
+            &mut survivors.max_root_lag_slots,
+            matches,
+            "max_root_lag_slots",
+        )?;
+        override_value(
+            &mut survivors.min_activated_stake,
+            matches,
+            "min_activated_stake",
+        )?;
+        override_value(&mut survivors.samples, matches, "survivor_samples")?;
+
+        let rpc = &mut self.rpc;
+        if let Some(endpoints) = matches.values_of("rpc_endpoint") {
+            rpc.endpoints = endpoints.map(str::to_string).collect();
+        }
+        override_value(&mut rpc.timeout_secs, matches, "rpc_timeout_secs")?;
+
+        let destake = &mut self.destake;
+        override_value(&mut destake.keypair_dir, matches, "destake_keypair_dir")?;
+        override_value(&mut destake.stake_sol, matches, "destake_stake_sol")?;
+
+        let health = &mut self.health;
+        if matches.is_present("skip_health_gate") {
+            health.enabled = false;
+        }
+        override_value(&mut health.retry_secs, matches, "health_retry_secs")?;
+        Ok(())
+    }
+
+    /// Checks every setting, including the schedule and gift policy files, before the run starts
+    pub fn validate(&self) -> Result<(), String> {
+        utils::is_host(self.entrypoint.clone())
+            .map_err(|err| format!("invalid entrypoint {}: {}", self.entrypoint, err))?;
+        if self.net_dir.is_none() && !self.dry_run {
+            return Err("net_dir is required".to_string());
+        }
+        if self.round == Some(0) {
+            return Err("round must be at least 1".to_string());
+        }
+        if self.slot_advance_check_secs == 0 {
+            return Err("slot_advance_check_secs must be at least 1".to_string());
+        }
+        if self.slot_stall_timeout_secs == 0 {
+            return Err("slot_stall_timeout_secs must be at least 1".to_string());
+        }
+        if !self.bench.fund_sol.is_finite() || self.bench.fund_sol < 0.0 {
+            return Err(format!("invalid bench fund_sol {}", self.bench.fund_sol));
+        }
+        if self.bench.target_tps == Some(0) {
+            return Err("bench target_tps must be at least 1".to_string());
+        }
+        if self.gift.workers == 0 {
+            return Err("gift workers must be at least 1".to_string());
+        }
+        let min_activated_stake = self.survivors.min_activated_stake;
+        if !min_activated_stake.is_finite() || min_activated_stake < 0.0 {
+            return Err(format!(
+                "invalid survivors min_activated_stake {}",
+                min_activated_stake
+            ));
+        }
+        self.status_addr()?;
+        self.expected_genesis_hash()?;
+        if self.genesis_timeout_secs == 0 {
+            return Err("genesis_timeout_secs must be at least 1".to_string());
+        }
+        self.rpc_endpoints()?;
+        if self.rpc.timeout_secs == 0 {
+            return Err("rpc timeout_secs must be at least 1".to_string());
+        }
+        self.rpc.retry.validate()?;
+        if !self.destake.stake_sol.is_finite() || self.destake.stake_sol <= 0.0 {
+            return Err(format!(
+                "invalid destake stake_sol {}",
+                self.destake.stake_sol
+            ));
+        }
+        self.validate_health()?;
+        self.schedule()?;
+        self.gift_policy()?;
+        Ok(())
+    }
+
+    fn validate_health(&self) -> Result<(), String> {
+        let health = &self.health;
+        if health.window_secs == 0 {
+            return Err("health window_secs must be at least 1".to_string());
+        }
+        if health.retry_secs == 0 {
+            return Err("health retry_secs must be at least 1".to_string());
+        }
+        if health.skip_rate_slots == 0 || health.skip_rate_slots > MAX_SKIP_RATE_SLOTS {
+            return Err(format!(
+                "health skip_rate_slots must be between 1 and {}",
+                MAX_SKIP_RATE_SLOTS
+            ));
+        }
+        for (name, percent) in &[
+            ("min_voting_stake_percent", health.min_voting_stake_percent),
+            ("max_skip_rate_percent", health.max_skip_rate_percent),
+        ] {
+            if !percent.is_finite() || *percent < 0.0 || *percent > 100.0 {
+                return Err(format!("invalid health {} {}", name, percent));
+            }
+        }
+        Ok(())
+    }
+
+    /// The health gate checked before each round, if it is enabled
+    pub fn health_gate(&self) -> Option<HealthGate> {
+        let health = &self.health;
+        if !health.enabled {
+            return None;
+        }
+        Some(HealthGate {
+            window: Duration::from_secs(health.window_secs),
+            require_root_advance: health.require_root_advance,
+            min_voting_stake_percent: health.min_voting_stake_percent,
+            skip_rate_slots: health.skip_rate_slots,
+            max_skip_rate_percent: health.max_skip_rate_percent,
+            min_gossip_nodes: health.min_gossip_nodes,
+            max_rpc_latency: match health.max_rpc_latency_ms {
+                0 => None,
+                max_rpc_latency_ms => Some(Duration::from_millis(max_rpc_latency_ms)),
+            },
+            retry_interval: Duration::from_secs(health.retry_secs),
+        })
+    }
+
+    /// The RPC service of the entrypoint, followed by the fallback endpoints
+    pub fn rpc_endpoints(&self) -> Result<Vec<SocketAddr>, String> {
+        let entrypoint = format!("{}:8899", self.entrypoint);
+        std::iter::once(&entrypoint)
+            .chain(&self.rpc.endpoints)
+            .map(|endpoint| {
+                solana_netutil::parse_host_port(endpoint)
+                    .map_err(|err| format!("invalid RPC endpoint {}: {}", endpoint, err))
+            })
+            .collect()
+    }
+
+    pub fn expected_genesis_hash(&self) -> Result<Option<Hash>, String> {
+        self.expected_genesis_hash
+            .as_ref()
+            .map(|genesis_hash| {
+                Hash::from_str(genesis_hash).map_err(|err| {
+                    format!("invalid expected_genesis_hash {}: {:?}", genesis_hash, err)
+                })
+            })
+            .transpose()
+    }
+
+    pub fn status_addr(&self) -> Result<Option<SocketAddr>, String> {
+        self.status_addr
+            .as_ref()
+            .map(|status_addr| {
+                solana_netutil::parse_host_port(status_addr)
+                    .map_err(|err| format!("invalid status_addr {}: {}", status_addr, err))
+            })
+            .transpose()
+    }
+
+    pub fn schedule(&self) -> Result<Schedule, String> {
+        match &self.schedule.file {
+            Some(schedule_file) => Schedule::load(schedule_file),
+            None => {
+                let schedule = Schedule::linear(
+                    self.schedule.tx_count_baseline,
+                    self.schedule.tx_count_increment,
+                    self.schedule.round_minutes,
+                );
+                schedule.validate()?;
+                Ok(schedule)
+            }
+        }
+    }
+
+    pub fn gift_policy(&self) -> Result<GiftPolicy, String> {
+        match &self.gift.policy_file {
+            Some(policy_file) => GiftPolicy::load(policy_file),
+            None => {
+                let policy = GiftPolicy::Doubling {
+                    initial_sol: self.gift.initial_balance,
+                };
+                policy.validate()?;
+                Ok(policy)
+            }
+        }
+    }
+
+    pub fn cooldown(&self) -> Duration {
+        Duration::from_secs(self.cooldown_secs)
+    }
+
+    pub fn slot_advance_check(&self) -> Duration {
+        Duration::from_secs(self.slot_advance_check_secs)
+    }
+
+    pub fn slot_stall_timeout(&self) -> Duration {
+        Duration::from_secs(self.slot_stall_timeout_secs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::{App, Arg};
+
+    #[test]
+    fn test_parse_config_file() {
+        let yaml = Config::parse(
+            "
+net_dir: net
+cooldown_secs: 60
+bench:
+  clients: 4
+survivors:
+  samples: 5
+",
+            false,
+        )
+        .unwrap();
+        let toml = Config::parse(
+            "
+net_dir = \"net\"
+cooldown_secs = 60
+
+[bench]
+clients = 4
+
+[survivors]
+samples = 5
+",
+            true,
+        )
+        .unwrap();
+        assert_eq!(yaml, toml);
+        assert_eq!(yaml.bench.clients, 4);
+        assert_eq!(yaml.bench.threads, BenchSettings::default().threads);
+        assert_eq!(yaml.cooldown(), Duration::from_secs(60));
+        assert_eq!(Config::parse("", false), Ok(Config::default()));
+        assert!(Config::parse("cool_down_secs: 60", false).is_err());
+    }
+
+    #[test]
+    fn test_args_override_config_file() {
+        let app = App::new("test")
+            .arg(
+                Arg::with_name("round_minutes")
+                    .long("round-minutes")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("bench_clients")
+                    .long("bench-clients")
+                    .takes_value(true),
+            )
+            .arg(Arg::with_name("gift_dry_run").long("gift-dry-run"))
+            .arg(Arg::with_name("no_control_socket").long("no-control-socket"));
+        let mut config = Config::parse(
+            "schedule:\n  round_minutes: 20\nbench:\n  clients: 4\n",
+            false,
+        )
+        .unwrap();
+        assert!(config.control_socket.is_some());
+        let matches = app.clone().get_matches_from(vec![
+            "test",
+            "--round-minutes",
+            "30",
+            "--gift-dry-run",
+            "--no-control-socket",
+        ]);
+        config.apply_args(&matches).unwrap();
+        assert_eq!(config.schedule.round_minutes, 30);
+        assert_eq!(config.bench.clients, 4);
+        assert!(config.gift.dry_run);
+        assert_eq!(config.control_socket, None);
+
+        let matches = app.get_matches_from(vec!["test", "--bench-clients", "many"]);
+        assert!(config.apply_args(&matches).is_err());
+    }
+
+    #[test]
+    fn test_validate_config() {
+        let mut config = Config {
+            entrypoint: "127.0.0.1".to_string(),
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+        config.dry_run = true;
+        assert_eq!(config.validate(), Ok(()));
+        config.dry_run = false;
+        config.net_dir = Some("net".to_string());
+        assert_eq!(config.validate(), Ok(()));
+        config.schedule.round_minutes = 0;
+        assert!(config.validate().is_err());
+        config.schedule.round_minutes = 1;
+        config.gift.initial_balance = -1.0;
+        assert!(config.validate().is_err());
+        config.gift.initial_balance = 1.0;
+        config.status_addr = Some("127.0.0.1".to_string());
+        assert!(config.validate().is_err());
+        config.status_addr = Some("127.0.0.1:9090".to_string());
+        assert_eq!(config.validate(), Ok(()));
+        config.rpc.endpoints = vec!["127.0.0.2".to_string()];
+        assert!(config.validate().is_err());
+        config.rpc.endpoints = vec!["127.0.0.2:8899".to_string()];
+        assert_eq!(
+            config.rpc_endpoints(),
+            Ok(vec![
+                "127.0.0.1:8899".parse().unwrap(),
+                "127.0.0.2:8899".parse().unwrap()
+            ])
+        );
+        config.rpc.retry.voters.attempts = 0;
+        assert!(config.validate().is_err());
+        config.rpc.retry.voters.attempts = 1;
+        config.expected_genesis_hash = Some("genesis".to_string());
+        assert!(config.validate().is_err());
+    }
+}
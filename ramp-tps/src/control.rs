@@ -0,0 +1,252 @@
+//! Local control socket for steering a running ramp-tps
+//!
+//! Each connection sends one command on a single line and receives a reply, for example
+//! `echo pause | nc -U ramp-tps.sock`. The commands are:
+//!
+//! * `status`: the current round and phase, and any pending requests
+//! * `pause`: wait before starting the next phase, until `resume`
+//! * `resume`
+//! * `extend <minutes>`: run transactions for longer in the current round
+//! * `skip-cooldown`: award stake without waiting for the rest of the cooldown
+//! * `abort`: stop ramp-tps once the current round is over, or right away while paused
+
+use crate::state::{Phase, RoundState};
+use log::*;
+use serde_derive::Serialize;
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    os::unix::{
+        fs::PermissionsExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// A client which does not send its command in time is disconnected
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The state of the ramp and the requests which have not been acted upon yet
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ControlStatus {
+    pub round: u32,
+    pub phase: Option<Phase>,
+    pub tx_count: u64,
+    /// Time added to the current round so far
+    pub round_extension_secs: u64,
+    pub paused: bool,
+    /// Time to add to the current round once transactions are running
+    pub pending_extension_secs: u64,
+    pub skip_cooldown: bool,
+    pub abort_after_round: bool,
+}
+
+/// Operator requests, shared between the control socket and the ramp
+#[derive(Clone, Debug, Default)]
+pub struct Control {
+    status: Arc<Mutex<ControlStatus>>,
+}
+
+impl Control {
+    /// Serve commands on a Unix socket at `path` from a background thread
+    pub fn listen(path: &str) -> Result<Self, String> {
+        if Path::new(path).exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(format!("{} is in use, is another ramp-tps running?", path));
+            }
+            // Left behind by an earlier run
+            fs::remove_file(path).map_err(|err| format!("Unable to remove {}: {}", path, err))?;
+        }
+        let listener =
+            UnixListener::bind(path).map_err(|err| format!("Unable to bind {}: {}", path, err))?;
+        // Only the user running ramp-tps may steer it
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+            .map_err(|err| format!("Unable to restrict access to {}: {}", path, err))?;
+
+        let control = Control::default();
+        let server = control.clone();
+        thread::Builder::new()
+            .name("control-socket".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => {
+                            if let Err(err) = server.serve(stream) {
+                                warn!("Control socket client failed: {}", err);
+                            }
+                        }
+                        Err(err) => warn!("Control socket accept failed: {}", err),
+                    }
+                }
+            })
+            .map_err(|err| format!("Unable to start the control socket thread: {}", err))?;
+        info!("Listening for control commands on {}", path);
+        Ok(control)
+    }
+
+    fn serve(&self, mut stream: UnixStream) -> Result<(), String> {
+        stream
+            .set_read_timeout(Some(CLIENT_TIMEOUT))
+            .map_err(|err| err.to_string())?;
+        let mut command = String::new();
+        BufReader::new(&stream)
+            .read_line(&mut command)
+            .map_err(|err| err.to_string())?;
+        let reply = match self.handle(command.trim()) {
+            Ok(reply) => reply,
+            Err(err) => format!("Error: {}", err),
+        };
+        info!("Control command {:?}: {}", command.trim(), reply.trim_end());
+        writeln!(stream, "{}", reply.trim_end()).map_err(|err| err.to_string())
+    }
+
+    /// Apply `command` and return the reply for the operator
+    pub fn handle(&self, command: &str) -> Result<String, String> {
+        let mut words = command.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let arg = words.next();
+        let mut status = self.status.lock().unwrap();
+        match (name, arg) {
+            ("status", None) => serde_yaml::to_string(&*status).map_err(|err| err.to_string()),
+            ("pause", None) => {
+                status.paused = true;
+                Ok("Pausing before the next phase".to_string())
+            }
+            ("resume", None) => {
+                status.paused = false;
+                Ok("Resuming".to_string())
+            }
+            ("extend", Some(minutes)) => {
+                let minutes: u64 = minutes
+                    .parse()
+                    .map_err(|err| format!("invalid minutes {}: {}", minutes, err))?;
+                if status.phase > Some(Phase::StartTransactions) {
+                    return Err("transactions have already stopped this round".to_string());
+                }
+                status.pending_extension_secs = status
+                    .pending_extension_secs
+                    .saturating_add(minutes.saturating_mul(60));
+                Ok(format!(
+                    "Extending round {} by {} minutes",
+                    status.round, minutes
+                ))
+            }
+            ("skip-cooldown", None) => {
+                if status.phase > Some(Phase::Cooldown) {
+                    return Err("the cooldown is already over this round".to_string());
+                }
+                status.skip_cooldown = true;
+                Ok(format!("Skipping the cooldown of round {}", status.round))
+            }
+            ("abort", None) => {
+                status.abort_after_round = true;
+                Ok(format!("Stopping after round {}", status.round))
+            }
+            _ => Err(format!(
+                "unknown command {:?}, expected status, pause, resume, extend <minutes>, \
+                 skip-cooldown or abort",
+                command
+            )),
+        }
+    }
+
+    /// Publish the progress of the ramp. Requests which only apply to a single round are
+    /// dropped once it is over
+    pub fn update(&self, state: &RoundState) {
+        let mut status = self.status.lock().unwrap();
+        if status.round != state.round {
+            status.pending_extension_secs = 0;
+            status.skip_cooldown = false;
+        }
+        status.round = state.round;
+        status.phase = Some(state.phase);
+        status.tx_count = state.tx_count;
+        status.round_extension_secs = state.round_extension_secs;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.status.lock().unwrap().paused
+    }
+
+    /// Returns the time to add to the current round and clears the request
+    pub fn take_extension(&self) -> Duration {
+        let mut status = self.status.lock().unwrap();
+        let extension = Duration::from_secs(status.pending_extension_secs);
+        status.pending_extension_secs = 0;
+        extension
+    }
+
+    /// Returns whether the rest of the cooldown should be skipped and clears the request
+    pub fn take_skip_cooldown(&self) -> bool {
+        let mut status = self.status.lock().unwrap();
+        let skip_cooldown = status.skip_cooldown;
+        status.skip_cooldown = false;
+        skip_cooldown
+    }
+
+    pub fn abort_requested(&self) -> bool {
+        self.status.lock().unwrap().abort_after_round
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_handle_commands() {
+        let control = Control::default();
+        let mut state = RoundState::new(3, 0);
+        state.transition(Phase::StartTransactions);
+        control.update(&state);
+
+        assert!(control.handle("pause").is_ok());
+        assert!(control.is_paused());
+        assert!(control.handle("resume").is_ok());
+        assert!(!control.is_paused());
+
+        assert!(control.handle("extend ten").is_err());
+        assert!(control.handle("extend 10").is_ok());
+        assert!(control.handle("skip-cooldown").is_ok());
+        let status = control.handle("status").unwrap();
+        assert!(status.contains("pending_extension_secs: 600"));
+        assert_eq!(control.take_extension(), Duration::from_secs(600));
+        assert_eq!(control.take_extension(), Duration::from_secs(0));
+
+        // Per-round requests do not carry over to the next round
+        state.next_round(0);
+        control.update(&state);
+        assert!(!control.take_skip_cooldown());
+
+        state.transition(Phase::Gifting);
+        control.update(&state);
+        assert!(control.handle("extend 10").is_err());
+        assert!(control.handle("skip-cooldown").is_err());
+        assert!(control.handle("abort").is_ok());
+        assert!(control.abort_requested());
+        assert!(control.handle("restart").is_err());
+    }
+
+    #[test]
+    fn test_control_socket() {
+        let path = std::env::temp_dir().join("ramp-tps-test-control.sock");
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+        let control = Control::listen(path).unwrap();
+        assert!(Control::listen(path).is_err());
+        let mode = fs::metadata(path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let mut stream = UnixStream::connect(path).unwrap();
+        stream.write_all(b"pause\n").unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).unwrap();
+        assert_eq!(reply, "Pausing before the next phase\n");
+        assert!(control.is_paused());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,293 @@
+//! Replaces the large bootstrap stake of the internal cluster nodes with a small stake, so that
+//! the stake of the participants decides the ramp
+//!
+//! The keypair directory holds the keypairs of every node `N`, as fetched by
+//! `fetch-net-node-keypairs.sh`: `N-identity-keypair.json`, `N-stake-keypair.json`,
+//! `N-vote-keypair.json` and `N-tds-stake-keypair.json`. The last one is the replacement stake
+//! account, which the script records on the node itself before fetching it, so that a node is
+//! never staked twice.
+
+use crate::{
+    notifier::Notifications,
+    rpc::{CallCategory, RpcPool},
+    voters,
+};
+use log::*;
+use solana_sdk::{
+    native_token::lamports_to_sol,
+    pubkey::Pubkey,
+    signature::{read_keypair_file, Keypair, KeypairUtil},
+    transaction::Transaction,
+};
+use solana_stake_api::{stake_instruction, stake_state::StakeState};
+use std::{fmt, fs, path::Path};
+
+/// An internal node whose bootstrap stake is replaced
+pub struct NetNode {
+    pub index: u32,
+    pub identity_keypair: Keypair,
+    /// The bootstrap stake account, which the identity is authorized to deactivate
+    pub stake_keypair: Keypair,
+    pub vote_pubkey: Pubkey,
+    /// The replacement stake account
+    pub tds_stake_keypair: Keypair,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DestakeOutcome {
+    /// The bootstrap stake was deactivated and the replacement stake delegated
+    Restaked {
+        stake_account: Pubkey,
+    },
+    /// The replacement stake was delegated by an earlier run
+    AlreadyRestaked {
+        stake_account: Pubkey,
+    },
+    Failed(String),
+}
+
+impl fmt::Display for DestakeOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DestakeOutcome::Restaked { stake_account } => {
+                write!(f, "restaked with stake account {}", stake_account)
+            }
+            DestakeOutcome::AlreadyRestaked { stake_account } => {
+                write!(f, "already restaked with stake account {}", stake_account)
+            }
+            DestakeOutcome::Failed(err) => write!(f, "failed: {}", err),
+        }
+    }
+}
+
+fn read_keypair(path: &Path) -> Result<Keypair, String> {
+    let path_str = path
+        .to_str()
+        .ok_or_else(|| format!("Invalid path {:?}", path))?;
+    read_keypair_file(path_str).map_err(|err| format!("Unable to read {:?}: {}", path, err))
+}
+
+/// Returns every node with keypairs in `keypair_dir`, by index
+pub fn load_nodes(keypair_dir: &Path) -> Result<Vec<NetNode>, String> {
+    let entries = fs::read_dir(keypair_dir)
+        .map_err(|err| format!("Unable to read {:?}: {}", keypair_dir, err))?;
+    let mut indexes = vec![];
+    for entry in entries {
+        let entry = entry.map_err(|err| format!("Unable to read {:?}: {}", keypair_dir, err))?;
+        let file_name = entry.file_name();
+        let index = file_name
+            .to_str()
+            .and_then(|file_name| file_name.split('-').next())
+            .and_then(|index| index.parse::<u32>().ok());
+        if let Some(index) = index {
+            if file_name.to_str() == Some(&format!("{}-identity-keypair.json", index)) {
+                indexes.push(index);
+            }
+        }
+    }
+    indexes.sort();
+
+    indexes
+        .into_iter()
+        .map(|index| {
+            let keypair_path = |name: &str| keypair_dir.join(format!("{}-{}.json", index, name));
+            Ok(NetNode {
+                index,
+                identity_keypair: read_keypair(&keypair_path("identity-keypair"))?,
+                stake_keypair: read_keypair(&keypair_path("stake-keypair"))?,
+                vote_pubkey: read_keypair(&keypair_path("vote-keypair"))?.pubkey(),
+                tds_stake_keypair: read_keypair(&keypair_path("tds-stake-keypair"))?,
+            })
+        })
+        .collect()
+}
+
+/// Returns whether the stake account of `stake_pubkey` has already been deactivated
+fn is_deactivated(rpc: &RpcPool, stake_pubkey: &Pubkey) -> bool {
+    let account = rpc.call(CallCategory::Query, "get_account", |rpc_client| {
+        rpc_client.get_account(stake_pubkey)
+    });
+    match account.ok().and_then(|account| StakeState::from(&account)) {
+        Some(StakeState::Stake(_meta, stake)) => stake.deactivation_epoch != std::u64::MAX,
+        _ => false,
+    }
+}
+
+fn restake_node(
+    rpc: &RpcPool,
+    mint_keypair: &Keypair,
+    node: &NetNode,
+    lamports: u64,
+) -> Result<DestakeOutcome, String> {
+    let stake_account = node.tds_stake_keypair.pubkey();
+    let balance = rpc.call(CallCategory::Query, "get_balance", |rpc_client| {
+        rpc_client.get_balance(&stake_account)
+    })?;
+    if balance > 0 {
+        return Ok(DestakeOutcome::AlreadyRestaked { stake_account });
+    }
+
+    let stake_pubkey = node.stake_keypair.pubkey();
+    if is_deactivated(rpc, &stake_pubkey) {
+        info!(
+            "The bootstrap stake {} of node {} is already deactivated",
+            stake_pubkey, node.index
+        );
+    } else {
+        let signature = rpc.call(
+            CallCategory::Transaction,
+            "deactivate stake",
+            |rpc_client| {
+                let (recent_blockhash, _fee_calculator) = rpc_client
+                    .get_recent_blockhash()
+                    .map_err(|err| err.to_string())?;
+                let mut transaction = Transaction::new_signed_instructions(
+                    &[&node.identity_keypair],
+                    vec![stake_instruction::deactivate_stake(
+                        &stake_pubkey,
+                        &node.identity_keypair.pubkey(),
+                    )],
+                    recent_blockhash,
+                );
+                rpc_client
+                    .send_and_confirm_transaction(&mut transaction, &[&node.identity_keypair])
+                    .map_err(|err| err.to_string())
+            },
+        )?;
+        info!(
+            "Deactivated the bootstrap stake {} of node {}: {}",
+            stake_pubkey, node.index, signature
+        );
+    }
+
+    let signature = rpc.call(CallCategory::Transaction, "delegate stake", |rpc_client| {
+        // An earlier attempt may have landed without being confirmed
+        if rpc_client.get_balance(&stake_account).unwrap_or(0) > 0 {
+            return Ok(None);
+        }
+        let (recent_blockhash, _fee_calculator) = rpc_client
+            .get_recent_blockhash()
+            .map_err(|err| err.to_string())?;
+        voters::award_stake(
+            rpc_client,
+            mint_keypair,
+            &node.tds_stake_keypair,
+            &node.vote_pubkey,
+            lamports,
+            recent_blockhash,
+        )
+        .map(Some)
+    })?;
+    info!(
+        "Delegated {} SOL from {} to the vote account {} of node {}: {}",
+        lamports_to_sol(lamports),
+        stake_account,
+        node.vote_pubkey,
+        node.index,
+        signature.unwrap_or_else(|| "confirmed by an earlier attempt".to_string())
+    );
+    Ok(DestakeOutcome::Restaked { stake_account })
+}
+
+/// Deactivate the bootstrap stake of every node in `keypair_dir` and delegate `lamports` from the
+/// mint to each of them instead. Every node is attempted, and the outcome of each one is
+/// reported before returning an error if any of them failed
+pub fn destake_net_nodes(
+    rpc: &RpcPool,
+    mint_keypair: &Keypair,
+    keypair_dir: &Path,
+    lamports: u64,
+    notifier: &Notifications,
+) -> Result<(), String> {
+    let nodes = load_nodes(keypair_dir)?;
+    if nodes.is_empty() {
+        return Err(format!("No node keypairs found in {:?}", keypair_dir));
+    }
+
+    let outcomes: Vec<_> = nodes
+        .iter()
+        .map(|node| {
+            restake_node(rpc, mint_keypair, node, lamports).unwrap_or_else(DestakeOutcome::Failed)
+        })
+        .collect();
+    let num_failed = outcomes
+        .iter()
+        .filter(|outcome| match outcome {
+            DestakeOutcome::Failed(_) => true,
+            _ => false,
+        })
+        .count();
+    let mut report = format!(
+        "Replaced the bootstrap stake of {} of {} net nodes with {} SOL:",
+        nodes.len() - num_failed,
+        nodes.len(),
+        lamports_to_sol(lamports)
+    );
+    for (node, outcome) in nodes.iter().zip(&outcomes) {
+        report.push_str(&format!("\nNode {}: {}", node.index, outcome));
+    }
+    notifier.notify(&report);
+
+    if num_failed > 0 {
+        return Err(format!(
+            "Unable to destake {} net nodes, see {:?}",
+            num_failed, keypair_dir
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{mock_cluster::MockCluster, notifier::NotifierConfig};
+    use solana_sdk::signature::write_keypair_file;
+
+    fn write_keypair(path: &Path) {
+        write_keypair_file(&Keypair::new(), path.to_str().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_destake_net_nodes() {
+        let cluster = MockCluster::start(vec![]);
+        let rpc = cluster.rpc_pool();
+        let keypair_dir = std::env::temp_dir().join("ramp-tps-test-destake-net-nodes");
+        let _ = fs::remove_dir_all(&keypair_dir);
+        fs::create_dir_all(&keypair_dir).unwrap();
+        let notifier = Notifications::new(NotifierConfig::default());
+        let mint_keypair = Keypair::new();
+
+        assert!(destake_net_nodes(&rpc, &mint_keypair, &keypair_dir, 1, &notifier).is_err());
+
+        for index in 0..2 {
+            for name in &["identity", "stake", "vote", "tds-stake"] {
+                write_keypair(&keypair_dir.join(format!("{}-{}-keypair.json", index, name)));
+            }
+        }
+        // A node without a vote keypair cannot be destaked
+        write_keypair(&keypair_dir.join("10-identity-keypair.json"));
+        write_keypair(&keypair_dir.join("10-stake-keypair.json"));
+        assert!(load_nodes(&keypair_dir).is_err());
+        fs::remove_file(keypair_dir.join("10-identity-keypair.json")).unwrap();
+        fs::remove_file(keypair_dir.join("10-stake-keypair.json")).unwrap();
+
+        // Nor can a node whose replacement stake keypair was not fetched
+        let tds_stake_path = keypair_dir.join("1-tds-stake-keypair.json");
+        fs::remove_file(&tds_stake_path).unwrap();
+        assert!(load_nodes(&keypair_dir).is_err());
+        write_keypair(&tds_stake_path);
+
+        let nodes = load_nodes(&keypair_dir).unwrap();
+        assert_eq!(
+            nodes.iter().map(|node| node.index).collect::<Vec<_>>(),
+            vec![0, 1]
+        );
+        assert_eq!(
+            destake_net_nodes(&rpc, &mint_keypair, &keypair_dir, 1, &notifier),
+            Ok(())
+        );
+        // One transaction deactivates the bootstrap stake and another delegates the new stake
+        assert_eq!(cluster.transactions_sent(), 4);
+        fs::remove_dir_all(&keypair_dir).unwrap();
+    }
+}
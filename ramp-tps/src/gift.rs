@@ -0,0 +1,167 @@
+//! Policies that decide how much stake each survivor of a round is gifted
+
+use serde_derive::{Deserialize, Serialize};
+use solana_sdk::{native_token::sol_to_lamports, pubkey::Pubkey};
+use std::fs::File;
+
+/// How much stake each surviving validator is awarded at the end of a round
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum GiftPolicy {
+    /// The same gift to every survivor, every round
+    Fixed { sol: f64 },
+    /// `initial_sol` after the first round, doubling every round after that
+    Doubling { initial_sol: f64 },
+    /// `pool_sol` split evenly between the survivors of each round
+    Pool { pool_sol: f64 },
+    /// `base_sol` to every survivor plus a share of `bonus_pool_sol` proportional to the vote
+    /// credits they earned during the round
+    PerformanceBonus { base_sol: f64, bonus_pool_sol: f64 },
+}
+
+/// A validator which survived a round
+#[derive(Clone, Debug, PartialEq)]
+pub struct GiftCandidate {
+    pub name: String,
+    pub identity: Pubkey,
+    pub vote_pubkey: Pubkey,
+    /// Vote credits earned during the round
+    pub round_credits: u64,
+}
+
+impl GiftPolicy {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let file = File::open(path).map_err(|err| format!("Unable to open {}: {}", path, err))?;
+        let policy: GiftPolicy = serde_yaml::from_reader(file)
+            .map_err(|err| format!("Unable to parse {}: {}", path, err))?;
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let amounts = match self {
+            GiftPolicy::Fixed { sol } => vec![sol],
+            GiftPolicy::Doubling { initial_sol } => vec![initial_sol],
+            GiftPolicy::Pool { pool_sol } => vec![pool_sol],
+            GiftPolicy::PerformanceBonus {
+                base_sol,
+                bonus_pool_sol,
+            } => vec![base_sol, bonus_pool_sol],
+        };
+        if amounts.iter().any(|sol| !sol.is_finite() || **sol < 0.0) {
+            return Err(format!("invalid gift amount in {:?}", self));
+        }
+        Ok(())
+    }
+
+    /// Whether `plan` uses the vote credits that candidates earned during the round
+    pub fn needs_round_credits(&self) -> bool {
+        match self {
+            GiftPolicy::PerformanceBonus { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the lamports to award each of the survivors of `round`, in the same order
+    pub fn plan(&self, round: u32, candidates: &[GiftCandidate]) -> Vec<u64> {
+        if candidates.is_empty() {
+            return vec![];
+        }
+        let num_candidates = candidates.len() as u64;
+        match self {
+            GiftPolicy::Fixed { sol } => vec![sol_to_lamports(*sol); candidates.len()],
+            GiftPolicy::Doubling { initial_sol } => {
+                let lamports = sol_to_lamports(*initial_sol)
+                    .saturating_mul(2u64.saturating_pow(round.max(1) - 1));
+                vec![lamports; candidates.len()]
+            }
+            GiftPolicy::Pool { pool_sol } => {
+                vec![sol_to_lamports(*pool_sol) / num_candidates; candidates.len()]
+            }
+            GiftPolicy::PerformanceBonus {
+                base_sol,
+                bonus_pool_sol,
+            } => {
+                let base = sol_to_lamports(*base_sol);
+                let bonus_pool = u128::from(sol_to_lamports(*bonus_pool_sol));
+                let total_credits: u128 = candidates
+                    .iter()
+                    .map(|candidate| u128::from(candidate.round_credits))
+                    .sum();
+                candidates
+                    .iter()
+                    .map(|candidate| {
+                        // Split the bonus pool evenly if no vote credits were earned at all
+                        let bonus = (bonus_pool * u128::from(candidate.round_credits))
+                            .checked_div(total_credits)
+                            .unwrap_or(bonus_pool / u128::from(num_candidates));
+                        base + bonus as u64
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn candidates(round_credits: &[u64]) -> Vec<GiftCandidate> {
+        round_credits
+            .iter()
+            .map(|round_credits| GiftCandidate {
+                name: String::new(),
+                identity: Pubkey::default(),
+                vote_pubkey: Pubkey::default(),
+                round_credits: *round_credits,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_fixed_and_doubling_gifts() {
+        let candidates = candidates(&[0, 0]);
+        let fixed = GiftPolicy::Fixed { sol: 2.0 };
+        assert_eq!(fixed.plan(3, &candidates), vec![sol_to_lamports(2.0); 2]);
+
+        // Matches the original gift_for_round(round + 1, initial_balance)
+        let doubling = GiftPolicy::Doubling { initial_sol: 1.0 };
+        assert_eq!(doubling.plan(1, &candidates)[0], sol_to_lamports(1.0));
+        assert_eq!(doubling.plan(2, &candidates)[0], sol_to_lamports(2.0));
+        assert_eq!(doubling.plan(4, &candidates)[0], sol_to_lamports(8.0));
+    }
+
+    #[test]
+    fn test_pool_gifts() {
+        let pool = GiftPolicy::Pool { pool_sol: 9.0 };
+        assert_eq!(
+            pool.plan(1, &candidates(&[0, 0, 0])),
+            vec![sol_to_lamports(3.0); 3]
+        );
+        assert!(pool.plan(1, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_performance_bonus_gifts() {
+        let policy = GiftPolicy::PerformanceBonus {
+            base_sol: 1.0,
+            bonus_pool_sol: 4.0,
+        };
+        assert!(policy.needs_round_credits());
+        assert_eq!(
+            policy.plan(1, &candidates(&[300, 100])),
+            vec![sol_to_lamports(4.0), sol_to_lamports(2.0)]
+        );
+        assert_eq!(
+            policy.plan(1, &candidates(&[0, 0])),
+            vec![sol_to_lamports(3.0); 2]
+        );
+    }
+
+    #[test]
+    fn test_validate_gift_policy() {
+        assert_eq!(GiftPolicy::Fixed { sol: 1.0 }.validate(), Ok(()));
+        assert!(GiftPolicy::Pool { pool_sol: -1.0 }.validate().is_err());
+    }
+}
@@ -0,0 +1,209 @@
+//! Submits stake gifts concurrently from a bounded pool of worker threads
+
+use crate::{
+    rpc::{CallCategory, RpcPool},
+    shutdown::Shutdown,
+    state::GiftRecipient,
+    voters,
+};
+use log::*;
+use solana_sdk::{
+    hash::Hash,
+    pubkey::Pubkey,
+    signature::{Keypair, KeypairUtil},
+};
+use std::{
+    collections::VecDeque,
+    sync::{mpsc::channel, Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Refresh the blockhash well before it expires
+const BLOCKHASH_MAX_AGE: Duration = Duration::from_secs(30);
+
+pub struct GiftJob {
+    pub recipient: GiftRecipient,
+    pub vote_account_pubkey: Pubkey,
+    pub stake_account_keypair: Keypair,
+}
+
+pub struct GiftOutcome {
+    pub recipient: GiftRecipient,
+    pub attempts: u32,
+    /// The transaction signature, or `None` if the stake account was created by an earlier run
+    pub result: Result<Option<String>, String>,
+}
+
+/// Recent blockhash shared by all gift workers
+struct BlockhashCache {
+    rpc: Arc<RpcPool>,
+    latest: Mutex<Option<(Hash, Instant)>>,
+}
+
+impl BlockhashCache {
+    fn get(&self) -> Result<Hash, String> {
+        let mut latest = self.latest.lock().unwrap();
+        if let Some((blockhash, fetched_at)) = *latest {
+            if fetched_at.elapsed() < BLOCKHASH_MAX_AGE {
+                return Ok(blockhash);
+            }
+        }
+        let (blockhash, _fee_calculator) =
+            self.rpc
+                .call(CallCategory::Query, "get_recent_blockhash", |rpc_client| {
+                    rpc_client.get_recent_blockhash()
+                })?;
+        *latest = Some((blockhash, Instant::now()));
+        Ok(blockhash)
+    }
+
+    fn invalidate(&self) {
+        *self.latest.lock().unwrap() = None;
+    }
+}
+
+/// Returns the transaction signature and the number of attempts made. Failed attempts are
+/// retried according to the transaction retry policy
+fn send_gift(
+    rpc: &RpcPool,
+    mint_keypair: &Keypair,
+    blockhash_cache: &BlockhashCache,
+    job: &GiftJob,
+) -> (Result<Option<String>, String>, u32) {
+    let stake_account_pubkey = job.stake_account_keypair.pubkey();
+    let mut attempts = 0;
+    let result = rpc.call(CallCategory::Transaction, "gift", |rpc_client| {
+        if attempts > 0 {
+            // The failure may have been caused by an expired blockhash
+            blockhash_cache.invalidate();
+        }
+
+        // The stake account only exists if an earlier attempt landed without being confirmed
+        if rpc_client.get_balance(&stake_account_pubkey).unwrap_or(0) > 0 {
+            info!(
+                "Stake account {} of {} already exists",
+                stake_account_pubkey, job.recipient.name
+            );
+            return Ok(None);
+        }
+
+        attempts += 1;
+        let recent_blockhash = blockhash_cache.get()?;
+        voters::award_stake(
+            rpc_client,
+            mint_keypair,
+            &job.stake_account_keypair,
+            &job.vote_account_pubkey,
+            job.recipient.lamports,
+            recent_blockhash,
+        )
+        .map(Some)
+    });
+    (result, attempts)
+}
+
+/// Send every gift in `jobs` from `num_workers` threads, calling `on_outcome` from the current
+/// thread as each gift completes. Once a shutdown is requested, gifts in flight are completed but
+/// no new ones are started
+pub fn deliver<F>(
+    rpc: &Arc<RpcPool>,
+    mint_keypair: &Arc<Keypair>,
+    jobs: Vec<GiftJob>,
+    num_workers: usize,
+    shutdown: &Shutdown,
+    mut on_outcome: F,
+) where
+    F: FnMut(GiftOutcome),
+{
+    let num_workers = num_workers.max(1).min(jobs.len());
+    let queue = Arc::new(Mutex::new(VecDeque::from(jobs)));
+    let blockhash_cache = Arc::new(BlockhashCache {
+        rpc: rpc.clone(),
+        latest: Mutex::new(None),
+    });
+    let (sender, receiver) = channel();
+
+    let workers: Vec<_> = (0..num_workers)
+        .map(|i| {
+            let rpc = rpc.clone();
+            let mint_keypair = mint_keypair.clone();
+            let queue = queue.clone();
+            let blockhash_cache = blockhash_cache.clone();
+            let sender = sender.clone();
+            let shutdown = shutdown.clone();
+            thread::Builder::new()
+                .name(format!("gift-worker-{}", i))
+                .spawn(move || loop {
+                    if shutdown.is_requested() {
+                        break;
+                    }
+                    let job = match queue.lock().unwrap().pop_front() {
+                        Some(job) => job,
+                        None => break,
+                    };
+                    let (result, attempts) = send_gift(&rpc, &mint_keypair, &blockhash_cache, &job);
+                    let outcome = GiftOutcome {
+                        recipient: job.recipient,
+                        attempts,
+                        result,
+                    };
+                    if sender.send(outcome).is_err() {
+                        break;
+                    }
+                })
+                .unwrap()
+        })
+        .collect();
+    drop(sender);
+
+    for outcome in receiver {
+        on_outcome(outcome);
+    }
+    for worker in workers {
+        if worker.join().is_err() {
+            warn!("Gift worker thread panicked");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock_cluster::{MockCluster, MockValidator};
+
+    #[test]
+    fn test_deliver_gifts_to_mock_cluster() {
+        let validators = vec![MockValidator::new(1, 100), MockValidator::new(2, 100)];
+        let cluster = MockCluster::start(validators.clone());
+        let jobs: Vec<_> = validators
+            .iter()
+            .map(|validator| GiftJob {
+                recipient: GiftRecipient {
+                    name: validator.node_pubkey.to_string(),
+                    identity: validator.node_pubkey.to_string(),
+                    vote_pubkey: validator.vote_pubkey.to_string(),
+                    lamports: 42,
+                },
+                vote_account_pubkey: validator.vote_pubkey,
+                stake_account_keypair: Keypair::new(),
+            })
+            .collect();
+
+        let mut outcomes = vec![];
+        deliver(
+            &Arc::new(cluster.rpc_pool()),
+            &Arc::new(Keypair::new()),
+            jobs,
+            4,
+            &Shutdown::default(),
+            |outcome| outcomes.push(outcome),
+        );
+        assert_eq!(outcomes.len(), 2);
+        for outcome in outcomes {
+            assert_eq!(outcome.attempts, 1);
+            assert!(outcome.result.unwrap().is_some());
+        }
+        assert_eq!(cluster.transactions_sent(), 2);
+    }
+}
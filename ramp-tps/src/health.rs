@@ -0,0 +1,250 @@
+//! Checks that the cluster is healthy enough to put load on before each round starts
+
+use crate::{
+    rpc::{CallCategory, RpcPool},
+    shutdown::{Shutdown, Stop},
+    voters::{self, VoterSample},
+};
+use std::{
+    collections::BTreeSet,
+    time::{Duration, Instant},
+};
+
+/// Number of votes kept in the tower of each vote account, which bounds the slots that the skip
+/// rate can be measured over
+pub const MAX_SKIP_RATE_SLOTS: u64 = 31;
+
+/// Thresholds that the cluster must meet before a round starts
+#[derive(Clone, Debug)]
+pub struct HealthGate {
+    /// The cluster is sampled at the start and the end of this window
+    pub window: Duration,
+    /// The highest root of the vote accounts must advance within the window
+    pub require_root_advance: bool,
+    /// Minimum share of the activated stake which is voting, in percent
+    pub min_voting_stake_percent: f64,
+    /// Slots that the skip rate is measured over, up to the latest vote
+    pub skip_rate_slots: u64,
+    /// Maximum share of those slots which no vote account voted on, in percent
+    pub max_skip_rate_percent: f64,
+    pub min_gossip_nodes: usize,
+    pub max_rpc_latency: Option<Duration>,
+    /// Delay before the gate is checked again once it fails
+    pub retry_interval: Duration,
+}
+
+/// Health of the cluster at one point in time
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HealthSample {
+    /// Highest root slot of the current vote accounts
+    pub root_slot: Option<u64>,
+    pub voting_stake: u64,
+    pub total_stake: u64,
+    /// Slots in the towers of the current vote accounts
+    pub voted_slots: BTreeSet<u64>,
+    pub gossip_nodes: usize,
+    /// Time taken by a `get_slot` RPC call, including any retries
+    pub rpc_latency: Duration,
+}
+
+impl HealthSample {
+    pub fn fetch(rpc: &RpcPool) -> Result<Self, String> {
+        let started = Instant::now();
+        rpc.call(CallCategory::Query, "get_slot", |rpc_client| {
+            rpc_client.get_slot()
+        })?;
+        let rpc_latency = started.elapsed();
+
+        let mut sample = HealthSample::from_voters(&voters::fetch_voters(rpc)?);
+        sample.rpc_latency = rpc_latency;
+        sample.gossip_nodes = rpc
+            .call(CallCategory::Query, "get_cluster_nodes", |rpc_client| {
+                rpc_client.get_cluster_nodes()
+            })?
+            .len();
+        Ok(sample)
+    }
+
+    /// The stake, roots and votes of `voters`, without the gossip nodes or the RPC latency
+    pub fn from_voters(voters: &VoterSample) -> Self {
+        let mut sample = HealthSample::default();
+        for status in &voters.statuses {
+            sample.total_stake += status.activated_stake;
+            if status.delinquent {
+                continue;
+            }
+            sample.voting_stake += status.activated_stake;
+            sample.root_slot = sample.root_slot.max(status.root_slot);
+            sample
+                .voted_slots
+                .extend(status.voted_slots.iter().cloned());
+        }
+        sample
+    }
+
+    pub fn voting_stake_percent(&self) -> f64 {
+        if self.total_stake == 0 {
+            return 0.;
+        }
+        self.voting_stake as f64 * 100. / self.total_stake as f64
+    }
+
+    /// Share of the `slots` slots up to the latest vote which no vote account voted on, in
+    /// percent. `None` if there are no votes
+    pub fn skip_rate_percent(&self, slots: u64) -> Option<f64> {
+        let last_voted_slot = *self.voted_slots.iter().next_back()?;
+        let first_slot = last_voted_slot.saturating_sub(slots.max(1) - 1);
+        let num_slots = last_voted_slot - first_slot + 1;
+        let num_voted = self.voted_slots.range(first_slot..=last_voted_slot).count() as u64;
+        Some((num_slots - num_voted) as f64 * 100. / num_slots as f64)
+    }
+}
+
+impl HealthGate {
+    /// Sample the cluster over the window and return why it is unhealthy, if it is
+    pub fn check(&self, rpc: &RpcPool, shutdown: &Shutdown) -> Result<Vec<String>, Stop> {
+        let before = match HealthSample::fetch(rpc) {
+            Ok(sample) => sample,
+            Err(err) => return Ok(vec![format!("unable to sample the cluster: {}", err)]),
+        };
+        shutdown.sleep(self.window)?;
+        match HealthSample::fetch(rpc) {
+            Ok(after) => Ok(self.unhealthy_reasons(&before, &after)),
+            Err(err) => Ok(vec![format!("unable to sample the cluster: {}", err)]),
+        }
+    }
+
+    /// Returns every threshold which the samples taken at the start and the end of the window
+    /// do not meet
+    pub fn unhealthy_reasons(&self, before: &HealthSample, after: &HealthSample) -> Vec<String> {
+        let mut reasons = vec![];
+        if self.require_root_advance && after.root_slot <= before.root_slot {
+            reasons.push(match after.root_slot {
+                Some(root_slot) => format!(
+                    "the root did not advance from slot {} within {:?}",
+                    root_slot, self.window
+                ),
+                None => "no vote account has a root".to_string(),
+            });
+        }
+        let voting_stake_percent = after.voting_stake_percent();
+        if voting_stake_percent < self.min_voting_stake_percent {
+            reasons.push(format!(
+                "only {:.1}% of the stake is voting, below {}%",
+                voting_stake_percent, self.min_voting_stake_percent
+            ));
+        }
+        match after.skip_rate_percent(self.skip_rate_slots) {
+            Some(skip_rate_percent) if skip_rate_percent > self.max_skip_rate_percent => reasons
+                .push(format!(
+                    "{:.1}% of the last {} slots were skipped, above {}%",
+                    skip_rate_percent, self.skip_rate_slots, self.max_skip_rate_percent
+                )),
+            Some(_) => {}
+            None => reasons.push("no recent votes".to_string()),
+        }
+        if after.gossip_nodes < self.min_gossip_nodes {
+            reasons.push(format!(
+                "only {} nodes in gossip, below {}",
+                after.gossip_nodes, self.min_gossip_nodes
+            ));
+        }
+        if let Some(max_rpc_latency) = self.max_rpc_latency {
+            let rpc_latency = before.rpc_latency.max(after.rpc_latency);
+            if rpc_latency > max_rpc_latency {
+                reasons.push(format!(
+                    "RPC calls took {}ms, above {}ms",
+                    rpc_latency.as_millis(),
+                    max_rpc_latency.as_millis()
+                ));
+            }
+        }
+        reasons
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock_cluster::{MockCluster, MockValidator};
+
+    fn gate() -> HealthGate {
+        HealthGate {
+            window: Duration::from_millis(100),
+            require_root_advance: true,
+            min_voting_stake_percent: 67.,
+            skip_rate_slots: 10,
+            max_skip_rate_percent: 20.,
+            min_gossip_nodes: 2,
+            max_rpc_latency: Some(Duration::from_secs(1)),
+            retry_interval: Duration::from_secs(60),
+        }
+    }
+
+    #[test]
+    fn test_unhealthy_reasons() {
+        let before = HealthSample {
+            root_slot: Some(100),
+            voting_stake: 80,
+            total_stake: 100,
+            voted_slots: (120..=140).collect(),
+            gossip_nodes: 2,
+            rpc_latency: Duration::from_millis(10),
+        };
+        let after = HealthSample {
+            root_slot: Some(110),
+            voted_slots: (130..=150).collect(),
+            ..before.clone()
+        };
+        let gate = gate();
+        assert_eq!(
+            gate.unhealthy_reasons(&before, &after),
+            Vec::<String>::new()
+        );
+
+        // Skip 3 of the last 10 slots
+        let mut voted_slots = after.voted_slots.clone();
+        for slot in &[143, 145, 147] {
+            voted_slots.remove(slot);
+        }
+        let unhealthy = HealthSample {
+            root_slot: Some(100),
+            voting_stake: 50,
+            voted_slots,
+            gossip_nodes: 1,
+            rpc_latency: Duration::from_secs(2),
+            ..after.clone()
+        };
+        assert_eq!(unhealthy.skip_rate_percent(10), Some(30.));
+        assert_eq!(
+            gate.unhealthy_reasons(&before, &unhealthy),
+            vec![
+                "the root did not advance from slot 100 within 100ms".to_string(),
+                "only 50.0% of the stake is voting, below 67%".to_string(),
+                "30.0% of the last 10 slots were skipped, above 20%".to_string(),
+                "only 1 nodes in gossip, below 2".to_string(),
+                "RPC calls took 2000ms, above 1000ms".to_string(),
+            ]
+        );
+        assert_eq!(HealthSample::default().skip_rate_percent(10), None);
+    }
+
+    #[test]
+    fn test_check_mock_cluster() {
+        let cluster =
+            MockCluster::start(vec![MockValidator::new(1, 100), MockValidator::new(2, 100)]);
+        cluster.wait_for_slot(40);
+        let rpc = cluster.rpc_pool();
+        let shutdown = Shutdown::default();
+        assert_eq!(gate().check(&rpc, &shutdown), Ok(vec![]));
+
+        cluster.stall();
+        let reasons = gate().check(&rpc, &shutdown).unwrap();
+        assert_eq!(reasons.len(), 1);
+        assert!(
+            reasons[0].starts_with("the root did not advance"),
+            "{}",
+            reasons[0]
+        );
+    }
+}
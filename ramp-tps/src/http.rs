@@ -0,0 +1,253 @@
+//! Minimal HTTP/1.1 server, one request per connection
+//!
+//! Serves the status endpoint of ramp-tps and the JSON-RPC service of the mock cluster used by
+//! tests.
+
+use log::*;
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// A client which does not send its request in time is disconnected. Connections are handled one
+/// at a time, so this bounds how long a slow client holds up the others
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(1);
+/// Requests with a larger body are refused without reading it
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+/// Most bytes read for the request line and headers together
+const MAX_HEADER_SIZE: u64 = 16 * 1024;
+/// Requests with a longer request line or header line are refused
+const MAX_LINE_LENGTH: usize = 4 * 1024;
+/// Requests with more headers are refused
+const MAX_HEADERS: usize = 64;
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// Only read by the mock cluster
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub body: Vec<u8>,
+}
+
+pub struct Response {
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn ok(content_type: &'static str, body: Vec<u8>) -> Self {
+        Response {
+            status: "200 OK",
+            content_type,
+            body,
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Response {
+            status: "404 Not Found",
+            content_type: "text/plain",
+            body: vec![],
+        }
+    }
+
+    pub fn payload_too_large() -> Self {
+        Response {
+            status: "413 Payload Too Large",
+            content_type: "text/plain",
+            body: vec![],
+        }
+    }
+
+    pub fn header_fields_too_large() -> Self {
+        Response {
+            status: "431 Request Header Fields Too Large",
+            content_type: "text/plain",
+            body: vec![],
+        }
+    }
+}
+
+/// Returns `None` if the line is longer than `MAX_LINE_LENGTH` or runs past the end of `reader`
+fn read_line<R: BufRead>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line.len() > MAX_LINE_LENGTH || !line.ends_with('\n') {
+        return Ok(None);
+    }
+    Ok(Some(line))
+}
+
+/// Returns the response to refuse the request with if its head or body is too large
+fn read_request(stream: &TcpStream) -> io::Result<Result<Request, Response>> {
+    let mut reader = BufReader::new(stream);
+    let mut head = reader.by_ref().take(MAX_HEADER_SIZE);
+    let request_line = match read_line(&mut head)? {
+        Some(request_line) => request_line,
+        None => return Ok(Err(Response::header_fields_too_large())),
+    };
+    let mut content_length = 0;
+    let mut header_count = 0;
+    loop {
+        let header = match read_line(&mut head)? {
+            Some(header) => header,
+            None => return Ok(Err(Response::header_fields_too_large())),
+        };
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        header_count += 1;
+        if header_count > MAX_HEADERS {
+            return Ok(Err(Response::header_fields_too_large()));
+        }
+        let mut parts = header.splitn(2, ':');
+        if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    if content_length > MAX_BODY_SIZE {
+        return Ok(Err(Response::payload_too_large()));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let mut request_line = request_line.split_whitespace();
+    Ok(Ok(Request {
+        method: request_line.next().unwrap_or_default().to_string(),
+        path: request_line.next().unwrap_or_default().to_string(),
+        body,
+    }))
+}
+
+fn handle_connection<F>(mut stream: TcpStream, handler: &F) -> io::Result<()>
+where
+    F: Fn(&Request) -> Response,
+{
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    let response = match read_request(&stream)? {
+        Ok(request) => handler(&request),
+        Err(response) => response,
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    )?;
+    stream.write_all(&response.body)?;
+    stream.flush()
+}
+
+/// Answer every request on `listener` with `handler`, from a background thread named `name`,
+/// until `exit` is set, or for the life of the process if there is no `exit`.
+///
+/// The thread blocks while accepting connections and only checks `exit` after each one, so
+/// whoever sets `exit` must then connect to the listener to wake the thread up.
+pub fn serve<F>(
+    name: &str,
+    listener: TcpListener,
+    exit: Option<Arc<AtomicBool>>,
+    handler: F,
+) -> io::Result<JoinHandle<()>>
+where
+    F: Fn(&Request) -> Response + Send + 'static,
+{
+    let name = name.to_string();
+    thread::Builder::new().name(name.clone()).spawn(move || {
+        for stream in listener.incoming() {
+            if exit
+                .as_ref()
+                .map_or(false, |exit| exit.load(Ordering::Relaxed))
+            {
+                break;
+            }
+            match stream {
+                Ok(stream) => {
+                    if let Err(err) = handle_connection(stream, &handler) {
+                        debug!("{} connection failed: {}", name, err);
+                    }
+                }
+                Err(err) => warn!("{} accept failed: {}", name, err),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request(addr: &std::net::SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_serve() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let exit = Arc::new(AtomicBool::new(false));
+        let server = serve("test-server", listener, Some(exit.clone()), |request| {
+            Response::ok("text/plain", request.body.clone())
+        })
+        .unwrap();
+
+        let response = request(&addr, "POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\nping");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\nping"), "{}", response);
+
+        let response = request(
+            &addr,
+            &format!(
+                "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+                MAX_BODY_SIZE + 1
+            ),
+        );
+        assert!(
+            response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"),
+            "{}",
+            response
+        );
+
+        let response = request(
+            &addr,
+            &format!("GET /{} HTTP/1.1\r\n", "a".repeat(MAX_LINE_LENGTH)),
+        );
+        assert!(
+            response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"),
+            "{}",
+            response
+        );
+
+        let response = request(
+            &addr,
+            &format!(
+                "GET / HTTP/1.1\r\n{}",
+                "X-Padding: 0\r\n".repeat(MAX_HEADERS + 1)
+            ),
+        );
+        assert!(
+            response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"),
+            "{}",
+            response
+        );
+
+        exit.store(true, Ordering::Relaxed);
+        TcpStream::connect(addr).unwrap();
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+//! Audit trail of every stake gift awarded by ramp-tps
+//!
+//! Each gift gets its own stake account keypair, which is saved before the gift is signed so that
+//! a retried gift always targets the same stake account and can never be delivered twice.
+
+use crate::utils;
+use serde_derive::{Deserialize, Serialize};
+use solana_sdk::signature::{read_keypair_file, write_keypair_file, Keypair};
+use std::{
+    fs::{self, File},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GiftStatus {
+    /// The gift transaction was signed but its outcome is unknown
+    Pending,
+    Confirmed,
+    Failed,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GiftLedgerEntry {
+    pub round: u32,
+    /// Validator identity pubkey
+    pub identity: String,
+    pub vote_pubkey: String,
+    pub stake_pubkey: String,
+    pub lamports: u64,
+    pub signature: Option<String>,
+    pub status: GiftStatus,
+    pub error: Option<String>,
+    pub attempts: u32,
+    /// Unix timestamp of the latest attempt
+    pub updated_at: u64,
+}
+
+pub struct GiftLedger {
+    path: PathBuf,
+    keypair_dir: PathBuf,
+    entries: Vec<GiftLedgerEntry>,
+}
+
+impl GiftLedger {
+    /// Stake account keypairs are kept in `keypair_dir`
+    pub fn load<P: Into<PathBuf>>(path: P, keypair_dir: P) -> Result<Self, String> {
+        let path = path.into();
+        let entries = match File::open(&path) {
+            Ok(file) => serde_yaml::from_reader(file)
+                .map_err(|err| format!("Unable to parse {:?}: {}", path, err))?,
+            Err(ref err) if err.kind() == ErrorKind::NotFound => vec![],
+            Err(err) => return Err(format!("Unable to open {:?}: {}", path, err)),
+        };
+        Ok(GiftLedger {
+            path,
+            keypair_dir: keypair_dir.into(),
+            entries,
+        })
+    }
+
+    pub fn entry(&self, round: u32, vote_pubkey: &str) -> Option<&GiftLedgerEntry> {
+        self.entries
+            .iter()
+            .find(|entry| entry.round == round && entry.vote_pubkey == vote_pubkey)
+    }
+
+    pub fn is_confirmed(&self, round: u32, vote_pubkey: &str) -> bool {
+        self.entry(round, vote_pubkey)
+            .map_or(false, |entry| entry.status == GiftStatus::Confirmed)
+    }
+
+    /// Add `entry` to the ledger, replacing any previous entry for the same gift, and save it
+    pub fn record(&mut self, mut entry: GiftLedgerEntry) -> Result<(), String> {
+        entry.updated_at = utils::unix_timestamp();
+        match self
+            .entries
+            .iter_mut()
+            .find(|e| e.round == entry.round && e.vote_pubkey == entry.vote_pubkey)
+        {
+            Some(existing) => *existing = entry,
+            None => self.entries.push(entry),
+        }
+        utils::save_yaml(&self.path, &self.entries)
+    }
+
+    fn keypair_path(&self, round: u32, vote_pubkey: &str) -> PathBuf {
+        self.keypair_dir
+            .join(format!("round-{}-{}.json", round, vote_pubkey))
+    }
+
+    /// Returns the stake account keypair of a gift, creating and saving a new one on first use
+    pub fn stake_keypair(&self, round: u32, vote_pubkey: &str) -> Result<Keypair, String> {
+        let keypair_path = self.keypair_path(round, vote_pubkey);
+        if keypair_path.exists() {
+            return read_keypair_file(&path_str(&keypair_path)?)
+                .map_err(|err| format!("Unable to read {:?}: {}", keypair_path, err));
+        }
+        fs::create_dir_all(&self.keypair_dir)
+            .map_err(|err| format!("Unable to create {:?}: {}", self.keypair_dir, err))?;
+        let keypair = Keypair::new();
+        write_keypair_file(&keypair, &path_str(&keypair_path)?)
+            .map_err(|err| format!("Unable to write {:?}: {}", keypair_path, err))?;
+        Ok(keypair)
+    }
+}
+
+fn path_str(path: &Path) -> Result<String, String> {
+    path.to_str()
+        .map(|path| path.to_string())
+        .ok_or_else(|| format!("Invalid path {:?}", path))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(round: u32, vote_pubkey: &str, status: GiftStatus) -> GiftLedgerEntry {
+        GiftLedgerEntry {
+            round,
+            identity: "identity".to_string(),
+            vote_pubkey: vote_pubkey.to_string(),
+            stake_pubkey: "stake".to_string(),
+            lamports: 42,
+            signature: None,
+            status,
+            error: None,
+            attempts: 1,
+            updated_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_gift_ledger_record() {
+        let path = std::env::temp_dir().join("ramp-tps-test-gift-ledger-record.yml");
+        let keypair_dir = std::env::temp_dir();
+        let _ = fs::remove_file(&path);
+        let mut ledger = GiftLedger::load(&path, &keypair_dir).unwrap();
+        assert_eq!(ledger.entry(1, "vote"), None);
+
+        ledger.record(entry(1, "vote", GiftStatus::Failed)).unwrap();
+        ledger
+            .record(entry(2, "vote", GiftStatus::Confirmed))
+            .unwrap();
+        assert!(!ledger.is_confirmed(1, "vote"));
+        assert!(ledger.is_confirmed(2, "vote"));
+
+        let mut retry = entry(1, "vote", GiftStatus::Confirmed);
+        retry.attempts = 2;
+        ledger.record(retry).unwrap();
+
+        let ledger = GiftLedger::load(&path, &keypair_dir).unwrap();
+        assert_eq!(ledger.entries.len(), 2);
+        assert!(ledger.is_confirmed(1, "vote"));
+        assert_eq!(ledger.entry(1, "vote").unwrap().attempts, 2);
+        fs::remove_file(&path).unwrap();
+    }
+}
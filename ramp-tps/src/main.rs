@@ -1,69 +1,98 @@
 //! Ramp up TPS for Tour de SOL until all validators drop out
 
+mod bench;
+mod budget;
+mod config;
+mod control;
+mod destake;
+mod gift;
+mod gifting;
+mod health;
+mod http;
+mod ledger;
+#[cfg(test)]
+mod mock_cluster;
 mod notifier;
+mod ramp;
 mod results;
+mod rpc;
+mod schedule;
+mod shutdown;
 mod stake;
+mod state;
+mod status;
+mod throughput;
 mod utils;
 mod voters;
-
-use clap::{crate_description, crate_name, crate_version, value_t, value_t_or_exit, App, Arg};
+mod wait;
+
+use bench::BenchConfig;
+use budget::{Budget, BudgetPolicy};
+use clap::{crate_description, crate_name, crate_version, App, Arg, ArgMatches, SubCommand};
+use config::Config;
+use control::Control;
+use ledger::GiftLedger;
 use log::*;
+use notifier::{Notifications, NotifierConfig};
+use ramp::{Ramp, RampConfig};
 use results::Results;
-use solana_client::rpc_client::RpcClient;
+use rpc::{CallCategory, RpcPool};
+use shutdown::{Shutdown, Stop};
 use solana_metrics::datapoint_info;
-use solana_sdk::{genesis_block::GenesisBlock, signature::read_keypair_file};
+use solana_sdk::{
+    native_token::{lamports_to_sol, sol_to_lamports},
+    signature::{read_keypair_file, KeypairUtil},
+};
 use solana_stake_api::config::{id as stake_config_id, Config as StakeConfig};
+use state::{RoundState, StateFile};
+use status::Status;
 use std::{
     collections::HashMap,
     fs,
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::{exit, Command},
-    thread::sleep,
+    sync::Arc,
     time::Duration,
 };
-
-const NUM_BENCH_CLIENTS: usize = 2;
-const TDS_ENTRYPOINT: &str = "tds.solana.com";
-const TMP_LEDGER_PATH: &str = ".tmp/ledger";
-const MINT_KEYPAIR_PATH: &str = "mint-keypair.json";
-const PUBKEY_MAP_FILE: &str = "validators/all-username.yml";
-const RESULTS_FILE: &str = "results.yml";
-const DEFAULT_TX_COUNT_BASELINE: &str = "5000";
-const DEFAULT_TX_COUNT_INCREMENT: &str = "5000";
-const DEFAULT_TPS_ROUND_MINUTES: &str = "60";
-const THREAD_BATCH_SLEEP_MS: &str = "250";
-const DEFAULT_INITIAL_SOL_BALANCE: &str = "1";
-
-// Transaction count increments linearly each round
-fn tx_count_for_round(tps_round: u32, base: u64, incr: u64) -> u64 {
-    base + u64::from(tps_round - 1) * incr
-}
-
-// Gift will double the staked lamports each round.
-fn gift_for_round(tps_round: u32, initial_balance: u64) -> u64 {
-    if tps_round > 1 {
-        initial_balance * 2u64.pow(tps_round - 2)
-    } else {
-        0
-    }
-}
+use voters::SurvivorCriteria;
+use wait::SlotWaiter;
 
 #[allow(clippy::cognitive_complexity)]
 fn main() {
     solana_logger::setup_with_filter("solana=debug");
     solana_metrics::set_panic_hook("ramp-tps");
-    let mut notifier = notifier::Notifier::new();
 
     let matches = App::new(crate_name!())
         .about(crate_description!())
         .version(crate_version!())
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .short("c")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("YAML or TOML file with the settings of the run. \
+                       Command line flags override the settings in this file"),
+        )
+        .arg(
+            Arg::with_name("print_config")
+                .long("print-config")
+                .takes_value(false)
+                .help("Print the effective settings of the run, including defaults, and exit"),
+        )
+        .arg(
+            Arg::with_name("dry_run")
+                .long("dry-run")
+                .takes_value(false)
+                .help("Follow the schedule against the cluster without starting bench clients, \
+                       destaking net nodes, delegating stake or writing any results"),
+        )
         .arg(
             Arg::with_name("mint_keypair_path")
                 .long("mint-keypair-path")
                 .short("k")
                 .value_name("PATH")
                 .takes_value(true)
-                .default_value(MINT_KEYPAIR_PATH)
                 .help("Path to the mint keypair for stake award distribution"),
         )
         .arg(
@@ -77,7 +106,6 @@ fn main() {
             Arg::with_name("pubkey_map_file")
                 .long("pubkey-map-file")
                 .value_name("FILE")
-                .default_value(PUBKEY_MAP_FILE)
                 .takes_value(true)
                 .help("YAML file that maps validator identity pubkeys to keybase user id"),
         )
@@ -85,32 +113,74 @@ fn main() {
             Arg::with_name("results_file")
                 .long("results-file")
                 .value_name("FILE")
-                .default_value(RESULTS_FILE)
                 .takes_value(true)
                 .help("YAML file that lists the results for each round"),
         )
+        .arg(
+            Arg::with_name("state_file")
+                .long("state-file")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("YAML file that tracks the progress of the ramp so it can be resumed"),
+        )
+        .arg(
+            Arg::with_name("notifier_config")
+                .long("notifier-config")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("YAML file that configures the notification backends. \
+                       [default: Discord and Slack from the DISCORD_WEBHOOK and SLACK_WEBHOOK env vars]"),
+        )
+        .arg(
+            Arg::with_name("control_socket")
+                .long("control-socket")
+                .value_name("PATH")
+                .takes_value(true)
+                .help("Unix socket which accepts the status, pause, resume, extend <minutes>, \
+                       skip-cooldown and abort commands [default: ramp-tps.sock]"),
+        )
+        .arg(
+            Arg::with_name("no_control_socket")
+                .long("no-control-socket")
+                .takes_value(false)
+                .conflicts_with("control_socket")
+                .help("Do not accept operator commands on a control socket"),
+        )
+        .arg(
+            Arg::with_name("status_addr")
+                .long("status-addr")
+                .value_name("HOST:PORT")
+                .takes_value(true)
+                .help("Serve the progress of the ramp as JSON on /status and in the Prometheus \
+                       text format on /metrics"),
+        )
         .arg(
             Arg::with_name("round")
                 .long("round")
                 .value_name("NUM")
                 .takes_value(true)
-                .default_value("1")
-                .help("The starting round of TPS ramp up"),
+                .help("The starting round of TPS ramp up. Ignores any progress saved in --state-file"),
         )
         .arg(
             Arg::with_name("round_minutes")
                 .long("round-minutes")
                 .value_name("NUM")
                 .takes_value(true)
-                .default_value(DEFAULT_TPS_ROUND_MINUTES)
                 .help("The duration in minutes of a TPS round"),
         )
+        .arg(
+            Arg::with_name("schedule_file")
+                .long("schedule-file")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("YAML file that schedules the tx-count and duration of each round. \
+                       Overrides --round-minutes, --tx-count-baseline and --tx-count-increment"),
+        )
         .arg(
             Arg::with_name("tx_count_baseline")
                 .long("tx-count-baseline")
                 .value_name("NUM")
                 .takes_value(true)
-                .default_value(DEFAULT_TX_COUNT_BASELINE)
                 .help("The tx-count of round 1"),
         )
         .arg(
@@ -118,27 +188,158 @@ fn main() {
                 .long("tx-count-increment")
                 .value_name("NUM")
                 .takes_value(true)
-                .default_value(DEFAULT_TX_COUNT_INCREMENT)
                 .help("The tx-count increment for the next round"),
         )
+        .arg(
+            Arg::with_name("bench_clients")
+                .long("bench-clients")
+                .value_name("NUM")
+                .takes_value(true)
+                .help("The number of bench clients, each sending to a different cluster node"),
+        )
+        .arg(
+            Arg::with_name("bench_threads")
+                .long("bench-threads")
+                .value_name("NUM")
+                .takes_value(true)
+                .help("The number of sending threads in each bench client"),
+        )
+        .arg(
+            Arg::with_name("bench_target_tps")
+                .long("bench-target-tps")
+                .value_name("NUM")
+                .takes_value(true)
+                .help("Limit the combined rate of all bench clients to this many transactions per second"),
+        )
+        .arg(
+            Arg::with_name("thread_batch_sleep_ms")
+                .long("thread-batch-sleep-ms")
+                .value_name("MS")
+                .takes_value(true)
+                .help("The minimum time between two batches of a bench thread"),
+        )
+        .arg(
+            Arg::with_name("bench_fund_sol")
+                .long("bench-fund-sol")
+                .value_name("SOL")
+                .takes_value(true)
+                .help("The number of SOL to fund each bench thread with, every round"),
+        )
         .arg(
             Arg::with_name("initial_balance")
                 .long("initial-balance")
                 .value_name("SOL")
                 .takes_value(true)
-                .default_value(DEFAULT_INITIAL_SOL_BALANCE)
                 .help("The number of SOL that each partipant started with"),
         )
+        .arg(
+            Arg::with_name("gift_policy_file")
+                .long("gift-policy-file")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("YAML file that configures the stake gifted to survivors of each round. \
+                       [default: --initial-balance after round 1, doubling every round]"),
+        )
+        .arg(
+            Arg::with_name("gift_ledger_file")
+                .long("gift-ledger-file")
+                .value_name("FILE")
+                .takes_value(true)
+                .help("YAML file that records every stake gift and whether it was confirmed"),
+        )
+        .arg(
+            Arg::with_name("gift_keypair_dir")
+                .long("gift-keypair-dir")
+                .value_name("DIR")
+                .takes_value(true)
+                .help("The directory where the stake account keypair of each gift is saved"),
+        )
+        .arg(
+            Arg::with_name("gift_workers")
+                .long("gift-workers")
+                .value_name("NUM")
+                .takes_value(true)
+                .help("The maximum number of stake gift transactions to send at once"),
+        )
+        .arg(
+            Arg::with_name("gift_budget_policy")
+                .long("gift-budget-policy")
+                .value_name("POLICY")
+                .takes_value(true)
+                .possible_values(&["warn", "cap", "bail"])
+                .help("What to do when the mint balance does not cover the stake gifts: \
+                       post a warning, scale the gifts down or stop"),
+        )
+        .arg(
+            Arg::with_name("gift_budget_rounds")
+                .long("gift-budget-rounds")
+                .value_name("NUM")
+                .takes_value(true)
+                .help("The number of rounds of stake gifts that the mint balance is checked \
+                       against at startup"),
+        )
+        .arg(
+            Arg::with_name("gift_dry_run")
+                .long("gift-dry-run")
+                .takes_value(false)
+                .help("Print the planned stake gifts of each round without delegating any stake"),
+        )
+        .arg(
+            Arg::with_name("max_vote_lag_slots")
+                .long("max-vote-lag-slots")
+                .value_name("NUM")
+                .takes_value(true)
+                .help("A validator whose last vote is more slots behind the current slot is delinquent"),
+        )
+        .arg(
+            Arg::with_name("max_root_lag_slots")
+                .long("max-root-lag-slots")
+                .value_name("NUM")
+                .takes_value(true)
+                .help("A validator whose root slot is more slots behind the current slot is delinquent"),
+        )
+        .arg(
+            Arg::with_name("min_activated_stake")
+                .long("min-activated-stake")
+                .value_name("SOL")
+                .takes_value(true)
+                .help("The activated stake that a validator needs to remain in the ramp. \
+                       Validators without any activated stake never remain"),
+        )
+        .arg(
+            Arg::with_name("survivor_samples")
+                .long("survivor-samples")
+                .value_name("NUM")
+                .takes_value(true)
+                .help("The number of times to check validator health while transactions are running. \
+                       Validators must be healthy in every check to remain"),
+        )
         .arg(
             Arg::with_name("entrypoint")
                 .short("n")
                 .long("entrypoint")
                 .value_name("HOST")
                 .takes_value(true)
-                .default_value(TDS_ENTRYPOINT)
                 .validator(utils::is_host)
                 .help("The entrypoint used for RPC calls"),
         )
+        .arg(
+            Arg::with_name("rpc_endpoint")
+                .long("rpc-endpoint")
+                .value_name("HOST:PORT")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Fallback RPC endpoint, used when the entrypoint fails. \
+                       May be given more than once"),
+        )
+        .arg(
+            Arg::with_name("rpc_timeout_secs")
+                .long("rpc-timeout-secs")
+                .value_name("SECS")
+                .takes_value(true)
+                .help("Timeout of each RPC call [default: 10]"),
+        )
         .arg(
             Arg::with_name("stake_activation_epoch")
                 .long("stake-activation-epoch")
@@ -151,286 +352,605 @@ fn main() {
                 .long("destake-net-nodes-epoch")
                 .value_name("NUM")
                 .takes_value(true)
-                .default_value("9")
-                .help("The epoch for which to run destake-net-nodes.sh at"),
+                .help("The epoch at which the bootstrap stake of the net nodes is replaced"),
+        )
+        .arg(
+            Arg::with_name("destake_keypair_dir")
+                .long("destake-keypair-dir")
+                .value_name("DIR")
+                .takes_value(true)
+                .help("Where the keypairs of the net nodes are fetched to [default: .destake]"),
+        )
+        .arg(
+            Arg::with_name("destake_stake_sol")
+                .long("destake-stake-sol")
+                .value_name("SOL")
+                .takes_value(true)
+                .help("SOL delegated to each net node in place of its bootstrap stake [default: 1]"),
+        )
+        .arg(
+            Arg::with_name("tmp_ledger_path")
+                .long("tmp-ledger-path")
+                .value_name("DIR")
+                .takes_value(true)
+                .help("The directory that genesis blocks are downloaded to and cached in, by hash"),
+        )
+        .arg(
+            Arg::with_name("expected_genesis_hash")
+                .long("expected-genesis-hash")
+                .value_name("HASH")
+                .takes_value(true)
+                .help("Refuse to run against a cluster with any other genesis block"),
+        )
+        .arg(
+            Arg::with_name("genesis_timeout_secs")
+                .long("genesis-timeout-secs")
+                .value_name("SECS")
+                .takes_value(true)
+                .help("Timeout of every read while downloading the genesis block [default: 60]"),
+        )
+        .arg(
+            Arg::with_name("cooldown_secs")
+                .long("cooldown-secs")
+                .value_name("SECS")
+                .takes_value(true)
+                .help("How long to idle after stopping transactions, before awarding stake"),
+        )
+        .arg(
+            Arg::with_name("slot_advance_check_secs")
+                .long("slot-advance-check-secs")
+                .value_name("SECS")
+                .takes_value(true)
+                .help("How long to wait for the slot to advance before each round starts"),
+        )
+        .arg(
+            Arg::with_name("slot_stall_timeout_secs")
+                .long("slot-stall-timeout-secs")
+                .value_name("SECS")
+                .takes_value(true)
+                .help("Stop if the slot does not advance for this long while waiting for an \
+                       epoch [default: 300]"),
+        )
+        .arg(
+            Arg::with_name("skip_health_gate")
+                .long("skip-health-gate")
+                .takes_value(false)
+                .help("Start each round without checking the health of the cluster first"),
+        )
+        .arg(
+            Arg::with_name("health_retry_secs")
+                .long("health-retry-secs")
+                .value_name("SECS")
+                .takes_value(true)
+                .help("How long to delay a round for when the cluster is unhealthy \
+                       [default: 60]"),
+        )
+        .subcommand(
+            SubCommand::with_name("plan-warmup")
+                .about("Project the stake warmup of the cluster epoch by epoch, optionally with \
+                        hypothetical stake gifts, and exit")
+                .arg(
+                    Arg::with_name("gift_sol")
+                        .long("gift-sol")
+                        .value_name("SOL")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Gift this much SOL to each validator. May be given more than once \
+                               to compare gift sizes"),
+                )
+                .arg(
+                    Arg::with_name("gift_round")
+                        .long("gift-round")
+                        .value_name("NUM")
+                        .takes_value(true)
+                        .help("Gift what the gift policy awards in this round"),
+                )
+                .arg(
+                    Arg::with_name("validators")
+                        .long("validators")
+                        .value_name("NUM")
+                        .takes_value(true)
+                        .help("Number of validators gifted [default: the current validators]"),
+                )
+                .arg(
+                    Arg::with_name("max_epochs")
+                        .long("max-epochs")
+                        .value_name("NUM")
+                        .takes_value(true)
+                        .default_value("50")
+                        .help("Stop projecting after this many epochs"),
+                ),
         )
         .get_matches();
 
-    let pubkey_map_file = value_t_or_exit!(matches, "pubkey_map_file", String);
-    let pubkey_map: HashMap<String, String> =
-        serde_yaml::from_reader(fs::File::open(&pubkey_map_file).unwrap_or_else(|err| {
-            eprintln!(
-                "Error: Unable to open --pubkey-map-file {}: {}",
-                pubkey_map_file, err
-            );
+    let mut config = match matches.value_of("config") {
+        Some(config_file) => Config::load(config_file).unwrap_or_else(|err| {
+            eprintln!("Error: Invalid --config: {}", err);
             exit(1);
-        }))
-        .unwrap_or_else(|err| {
+        }),
+        None => Config::default(),
+    };
+    config.apply_args(&matches).unwrap_or_else(|err| {
+        eprintln!("Error: {}", err);
+        exit(1);
+    });
+    if let Some(plan_matches) = matches.subcommand_matches("plan-warmup") {
+        plan_warmup(&config, plan_matches).unwrap_or_else(|err| {
+            eprintln!("Error: {}", err);
+            exit(1);
+        });
+        return;
+    }
+    config.validate().unwrap_or_else(|err| {
+        eprintln!("Error: {}", err);
+        exit(1);
+    });
+    if matches.is_present("print_config") {
+        print!("{}", serde_yaml::to_string(&config).unwrap());
+        println!();
+        return;
+    }
+
+    let pubkey_map: HashMap<String, String> = serde_yaml::from_reader(
+        fs::File::open(&config.pubkey_map_file).unwrap_or_else(|err| {
             eprintln!(
-                "Error: Unable to parse --pubkey-map-file {}: {}",
-                pubkey_map_file, err
+                "Error: Unable to open pubkey map file {}: {}",
+                config.pubkey_map_file, err
             );
             exit(1);
-        });
-    let pubkey_to_keybase = |pubkey: &solana_sdk::pubkey::Pubkey| -> String {
-        let pubkey = pubkey.to_string();
-        match pubkey_map.get(&pubkey) {
-            Some(keybase) => format!("{} ({})", keybase, pubkey),
-            None => pubkey,
+        }),
+    )
+    .unwrap_or_else(|err| {
+        eprintln!(
+            "Error: Unable to parse pubkey map file {}: {}",
+            config.pubkey_map_file, err
+        );
+        exit(1);
+    });
+    let notifier_config = match &config.notifier_config {
+        // Messages of a dry run are only logged
+        _ if config.dry_run => NotifierConfig::default(),
+        Some(notifier_config_file) => {
+            NotifierConfig::load(notifier_config_file).unwrap_or_else(|err| {
+                eprintln!("Error: Unable to load notifier config: {}", err);
+                exit(1);
+            })
         }
+        None => NotifierConfig::from_env(),
     };
+    let notifier = Notifications::new(notifier_config);
 
-    let net_dir = value_t_or_exit!(matches, "net_dir", String);
-    let mint_keypair_path = value_t_or_exit!(matches, "mint_keypair_path", String);
-    let mint_keypair = read_keypair_file(&mint_keypair_path)
-        .unwrap_or_else(|err| panic!("Unable to read {}: {}", mint_keypair_path, err));
-    let mut tps_round = value_t_or_exit!(matches, "round", u32).max(1);
-    let results_file_name = value_t_or_exit!(matches, "results_file", String);
-    let previous_results = Results::read(&results_file_name);
-    let mut tps_round_results = Results::new(results_file_name, previous_results, tps_round);
-    let tx_count_baseline = value_t_or_exit!(matches, "tx_count_baseline", u64);
-    let tx_count_increment = value_t_or_exit!(matches, "tx_count_increment", u64);
-    let round_minutes = value_t_or_exit!(matches, "round_minutes", u64).max(1);
-    let round_duration = Duration::from_secs(round_minutes * 60);
-    let initial_balance = value_t_or_exit!(matches, "initial_balance", u64);
-    let tmp_ledger_path = PathBuf::from(TMP_LEDGER_PATH);
-    let _ = fs::remove_dir_all(&tmp_ledger_path);
-    fs::create_dir_all(&tmp_ledger_path).expect("failed to create temp ledger path");
+    let mint_keypair = read_keypair_file(&config.mint_keypair_path).unwrap_or_else(|err| {
+        eprintln!(
+            "Error: Unable to read {}: {}",
+            config.mint_keypair_path, err
+        );
+        exit(1);
+    });
+    let state_file = StateFile::new(config.state_file.clone());
+    let saved_state = if config.round.is_some() || config.dry_run {
+        None
+    } else {
+        state_file.load().unwrap_or_else(|err| {
+            eprintln!("Error: Unable to load state file: {}", err);
+            exit(1);
+        })
+    };
+    let start_round = match &saved_state {
+        Some(state) => state.first_unrecorded_round(),
+        None => config.round.unwrap_or(1),
+    };
+    // Results::read creates a missing results file
+    let previous_results = if config.dry_run && !Path::new(&config.results_file).exists() {
+        vec![]
+    } else {
+        Results::read(&config.results_file)
+            .unwrap_or_else(|err| finish(&notifier, Stop::Failed(err)))
+    };
+    let tps_round_results =
+        Results::new(config.results_file.clone(), previous_results, start_round);
+    let schedule = config.schedule().unwrap_or_else(|err| {
+        eprintln!("Error: {}", err);
+        exit(1);
+    });
+    let gift_policy = config.gift_policy().unwrap_or_else(|err| {
+        eprintln!("Error: {}", err);
+        exit(1);
+    });
+    let gift_ledger = GiftLedger::load(
+        config.gift.ledger_file.clone(),
+        config.gift.keypair_dir.clone(),
+    )
+    .unwrap_or_else(|err| {
+        eprintln!("Error: Unable to load gift ledger: {}", err);
+        exit(1);
+    });
+    let bench_config = BenchConfig {
+        num_clients: config.bench.clients,
+        threads_per_client: config.bench.threads,
+        target_tps: config.bench.target_tps,
+        thread_batch_sleep: Duration::from_millis(config.bench.thread_batch_sleep_ms),
+        lamports_per_thread: sol_to_lamports(config.bench.fund_sol),
+    };
+    let tmp_ledger_path = PathBuf::from(&config.tmp_ledger_path);
+    fs::create_dir_all(&tmp_ledger_path).unwrap_or_else(|err| {
+        eprintln!("Error: Unable to create {:?}: {}", tmp_ledger_path, err);
+        exit(1);
+    });
+    let rpc_endpoints = config.rpc_endpoints().unwrap_or_else(|err| {
+        eprintln!("Error: {}", err);
+        exit(1);
+    });
+    let expected_genesis_hash = config.expected_genesis_hash().unwrap_or_else(|err| {
+        eprintln!("Error: {}", err);
+        exit(1);
+    });
+    let status_addr = config.status_addr().unwrap_or_else(|err| {
+        eprintln!("Error: {}", err);
+        exit(1);
+    });
+
+    let shutdown = Shutdown::install().unwrap_or_else(|err| {
+        eprintln!("Error: {}", err);
+        exit(1);
+    });
+    let control = match &config.control_socket {
+        Some(control_socket) => Control::listen(control_socket).unwrap_or_else(|err| {
+            eprintln!("Error: Unable to open the control socket: {}", err);
+            exit(1);
+        }),
+        None => Control::default(),
+    };
+    let status = match status_addr {
+        Some(status_addr) => {
+            let status = Status::serve(&status_addr).unwrap_or_else(|err| {
+                eprintln!("Error: Unable to start the status server: {}", err);
+                exit(1);
+            });
+            info!("Serving the ramp status on http://{}/status", status_addr);
+            status
+        }
+        None => Status::default(),
+    };
 
     notifier.notify("Hi!");
     datapoint_info!("ramp-tps", ("event", "boot", String),);
 
-    let entrypoint_str = matches.value_of("entrypoint").unwrap();
-    debug!("Connecting to {}", entrypoint_str);
-    let entrypoint_addr = solana_netutil::parse_host_port(&format!("{}:8899", entrypoint_str))
-        .expect("failed to parse entrypoint address");
-    utils::download_genesis(&entrypoint_addr, &tmp_ledger_path).expect("genesis download failed");
-    let genesis_block = GenesisBlock::load(&tmp_ledger_path).expect("failed to load genesis block");
+    debug!("Connecting to {}", config.entrypoint);
+    let rpc = RpcPool::new(
+        &rpc_endpoints,
+        Duration::from_secs(config.rpc.timeout_secs),
+        config.rpc.retry.clone(),
+    );
+    let genesis_block = utils::fetch_genesis(
+        &rpc,
+        &tmp_ledger_path,
+        expected_genesis_hash,
+        Duration::from_secs(config.genesis_timeout_secs),
+    )
+    .unwrap_or_else(|err| {
+        finish(
+            &notifier,
+            Stop::Failed(format!("Unable to fetch the genesis block: {}", err)),
+        )
+    });
 
     debug!("Fetching current slot...");
-    let rpc_client = RpcClient::new_socket_with_timeout(entrypoint_addr, Duration::from_secs(10));
-    let current_slot = rpc_client.get_slot().expect("failed to fetch current slot");
+    let current_slot = rpc
+        .call(CallCategory::Query, "get_slot", |rpc_client| {
+            rpc_client.get_slot()
+        })
+        .unwrap_or_else(|err| {
+            finish(
+                &notifier,
+                Stop::Failed(format!("Unable to fetch the current slot: {}", err)),
+            )
+        });
     debug!("Current slot: {}", current_slot);
     let first_normal_slot = genesis_block.epoch_schedule.first_normal_slot;
     debug!("First normal slot: {}", first_normal_slot);
-    let sleep_slots = first_normal_slot.saturating_sub(current_slot);
-    if sleep_slots > 0 {
+    let waiter = SlotWaiter::new(
+        &rpc,
+        &shutdown,
+        utils::slot_duration(&genesis_block),
+        config.slot_stall_timeout(),
+    );
+    if current_slot < first_normal_slot {
         notifier.notify(&format!(
             "Waiting for warm-up epochs to complete (epoch {})",
             genesis_block.epoch_schedule.first_normal_epoch
         ));
-        utils::sleep_n_slots(sleep_slots, &genesis_block);
+        if let Err(stop) = waiter.wait_for_slot(first_normal_slot) {
+            finish(&notifier, stop);
+        }
     }
 
     debug!("Fetching stake config...");
-    let stake_config_account = rpc_client
-        .get_account(&stake_config_id())
-        .expect("failed to fetch stake config");
-    let stake_config = StakeConfig::from(&stake_config_account).unwrap();
+    let stake_config_account = rpc
+        .call(CallCategory::Query, "get_account", |rpc_client| {
+            rpc_client.get_account(&stake_config_id())
+        })
+        .unwrap_or_else(|err| {
+            finish(
+                &notifier,
+                Stop::Failed(format!("Unable to fetch the stake config: {}", err)),
+            )
+        });
+    let stake_config = StakeConfig::from(&stake_config_account).unwrap_or_else(|| {
+        finish(
+            &notifier,
+            Stop::Failed("Unable to deserialize the stake config".to_string()),
+        )
+    });
 
-    // Check if destake-net-nodes.sh should be run
+    let epoch_info = rpc
+        .call(CallCategory::Query, "get_epoch_info", |rpc_client| {
+            rpc_client.get_epoch_info()
+        })
+        .unwrap_or_else(|err| {
+            finish(
+                &notifier,
+                Stop::Failed(format!("Unable to fetch the epoch info: {}", err)),
+            )
+        });
+    let destake_pending = epoch_info.epoch < config.destake_net_nodes_epoch && !config.dry_run;
+
+    // Check that the mint can pay for the stake gifts, bench funding and net node stake of the
+    // next rounds
+    if config.gift.budget_rounds > 0 {
+        let mint_balance = rpc
+            .call(CallCategory::Query, "get_balance", |rpc_client| {
+                rpc_client.get_balance(&mint_keypair.pubkey())
+            })
+            .unwrap_or_else(|err| {
+                finish(
+                    &notifier,
+                    Stop::Failed(format!("Unable to fetch the mint balance: {}", err)),
+                )
+            });
+        let num_voters = rpc
+            .call(CallCategory::Voters, "get_vote_accounts", |rpc_client| {
+                rpc_client.get_vote_accounts()
+            })
+            .unwrap_or_else(|err| {
+                finish(
+                    &notifier,
+                    Stop::Failed(format!("Unable to fetch the vote accounts: {}", err)),
+                )
+            })
+            .current
+            .len();
+        let last_round = start_round + config.gift.budget_rounds.saturating_sub(1);
+        let budget = Budget {
+            gift_lamports: budget::schedule_lamports(
+                &gift_policy,
+                start_round,
+                config.gift.budget_rounds,
+                num_voters,
+                genesis_block.fee_calculator.lamports_per_signature,
+            ),
+            bench_lamports: budget::bench_lamports(&bench_config)
+                .saturating_mul(u64::from(config.gift.budget_rounds)),
+            // The net nodes are among the current validators
+            destake_lamports: if destake_pending {
+                sol_to_lamports(config.destake.stake_sol).saturating_mul(num_voters as u64)
+            } else {
+                0
+            },
+        };
+        let message = format!(
+            "The mint {} has a balance of {} SOL, and rounds {} to {} need {} if all {} \
+             validators survive",
+            mint_keypair.pubkey(),
+            lamports_to_sol(mint_balance),
+            start_round,
+            last_round,
+            budget,
+            num_voters
+        );
+        if budget.total() <= mint_balance {
+            info!("{}", message);
+        } else if config.gift.budget_policy == BudgetPolicy::Bail && !config.dry_run {
+            finish(&notifier, Stop::Failed(message));
+        } else {
+            notifier.notify(&format!(
+                "Warning: {} (budget policy: {})",
+                message, config.gift.budget_policy
+            ));
+        }
+    }
+
+    // Check if the bootstrap stake of the net nodes should be replaced
     {
-        let epoch_info = rpc_client.get_epoch_info().unwrap();
-        let destake_net_nodes_epoch = value_t_or_exit!(matches, "destake_net_nodes_epoch", u64);
+        let destake_net_nodes_epoch = config.destake_net_nodes_epoch;
 
         if epoch_info.epoch >= destake_net_nodes_epoch {
             info!(
-                "Current epoch {} >= destake_net_nodes_epoch of {}, skipping destake-net-nodes.sh",
+                "Current epoch {} >= destake_net_nodes_epoch of {}, skipping destaking net nodes",
                 epoch_info.epoch, destake_net_nodes_epoch
             );
+        } else if config.dry_run {
+            info!(
+                "Dry run, not destaking net nodes at epoch {}",
+                destake_net_nodes_epoch
+            );
         } else {
-            let slots_per_epoch = genesis_block.epoch_schedule.slots_per_epoch;
-            let sleep_epochs = destake_net_nodes_epoch - epoch_info.epoch;
-            let sleep_slots = sleep_epochs * slots_per_epoch - epoch_info.slot_index;
             info!(
                 "Waiting for destake-net-nodes epoch {}",
                 destake_net_nodes_epoch
             );
-            utils::sleep_n_slots(sleep_slots, &genesis_block);
+            if let Err(stop) = waiter.wait_for_epoch(destake_net_nodes_epoch) {
+                finish(&notifier, stop);
+            }
+
+            info!("Fetching net node keypairs...");
+            let keypair_dir = &config.destake.keypair_dir;
+            let fetched = Command::new("bash")
+                .args(&[
+                    "fetch-net-node-keypairs.sh",
+                    config.net_dir.as_ref().unwrap(),
+                    keypair_dir,
+                ])
+                .status();
+            match fetched {
+                Ok(status) if status.success() => {}
+                Ok(status) => finish(
+                    &notifier,
+                    Stop::Failed(format!("fetch-net-node-keypairs.sh failed: {}", status)),
+                ),
+                Err(err) => finish(
+                    &notifier,
+                    Stop::Failed(format!("Unable to run fetch-net-node-keypairs.sh: {}", err)),
+                ),
+            }
 
             info!("Destaking net nodes...");
-            Command::new("bash")
-                .args(&["destake-net-nodes.sh", &net_dir])
-                .spawn()
-                .unwrap();
+            if let Err(err) = destake::destake_net_nodes(
+                &rpc,
+                &mint_keypair,
+                Path::new(keypair_dir),
+                sol_to_lamports(config.destake.stake_sol),
+                &notifier,
+            ) {
+                finish(&notifier, Stop::Failed(err));
+            }
             info!("Done destaking net nodes");
         }
     }
 
-    // Wait for the next epoch, or --stake-activation-epoch
-    {
-        let epoch_info = rpc_client.get_epoch_info().unwrap();
-        let activation_epoch =
-            if let Some(activation_epoch) = value_t!(matches, "stake_activation_epoch", u64).ok() {
-                activation_epoch
-            } else {
-                epoch_info.epoch - 1
-            };
-
-        let epoch_info = rpc_client.get_epoch_info().unwrap();
-        debug!("Current epoch info: {:?}", &epoch_info);
-        debug!("Activation epoch is: {:?}", activation_epoch);
-        stake::wait_for_activation(
-            activation_epoch,
-            epoch_info,
-            &rpc_client,
-            &stake_config,
-            &genesis_block,
-            &notifier,
-        );
-    }
-
-    loop {
-        notifier.notify(&format!("Round {}!", tps_round));
-        let tx_count = tx_count_for_round(tps_round, tx_count_baseline, tx_count_increment);
-        datapoint_info!(
-            "ramp-tps",
-            ("event", "round-start", String),
-            ("round", tps_round, i64),
-            ("tx_count", tx_count, i64)
-        );
-
-        let slot = rpc_client.get_slot().unwrap_or_else(|err| {
-            utils::bail(
-                &notifier,
-                &format!("Error: get_slot RPC call 1 failed: {}", err),
-            );
-        });
-        sleep(Duration::from_secs(5));
-        let latest_slot = rpc_client.get_slot().unwrap_or_else(|err| {
-            utils::bail(
-                &notifier,
-                &format!("Error: get_slot RPC call 2 failed: {}", err),
-            );
-        });
-        if slot == latest_slot {
-            utils::bail(&notifier, &format!("Slot is not advancing from {}", slot));
-        }
-
-        let remaining_voters = voters::fetch_remaining_voters(&rpc_client);
-        datapoint_info!(
-            "ramp-tps",
-            ("event", "start-transactions", String),
-            ("round", tps_round, i64),
-            ("validators", remaining_voters.len(), i64)
-        );
-
-        notifier.buffer(format!(
-            "There are {} validators present:",
-            remaining_voters.len()
-        ));
-        for (node_pubkey, _) in remaining_voters {
-            notifier.buffer(format!("* {}", pubkey_to_keybase(&node_pubkey)));
-        }
-        notifier.flush();
-
-        let client_tx_count = tx_count / NUM_BENCH_CLIENTS as u64;
+    let state = if let Some(state) = saved_state {
         notifier.notify(&format!(
-            "Starting transactions for {} minutes (batch size={})",
-            round_minutes, tx_count,
+            "Resuming round {} at the {} phase",
+            state.round, state.phase
         ));
-        info!(
-            "Running bench-tps={}='--tx_count={} --thread-batch-sleep-ms={}'",
-            NUM_BENCH_CLIENTS, client_tx_count, THREAD_BATCH_SLEEP_MS
-        );
-        for client_id in 0..NUM_BENCH_CLIENTS {
-            Command::new("bash")
-                .args(&[
-                    "wrapper-bench-tps.sh",
-                    &net_dir,
-                    &client_id.to_string(),
-                    &client_tx_count.to_string(),
-                    THREAD_BATCH_SLEEP_MS,
-                ])
-                .spawn()
-                .unwrap();
-        }
-        sleep(round_duration);
-        for client_id in 0..NUM_BENCH_CLIENTS {
-            Command::new("bash")
-                .args(&[
-                    "wrapper-bench-tps.sh",
-                    &net_dir,
-                    &client_id.to_string(),
-                    "0", // Setting txCount to 0 will kill bench-tps
-                    THREAD_BATCH_SLEEP_MS,
-                ])
-                .spawn()
-                .unwrap();
-        }
+        state
+    } else {
+        // Wait for the next epoch, or the stake activation epoch
+        let activation_epoch = if let Some(activation_epoch) = config.stake_activation_epoch {
+            activation_epoch
+        } else {
+            let epoch_info = rpc
+                .call(CallCategory::Query, "get_epoch_info", |rpc_client| {
+                    rpc_client.get_epoch_info()
+                })
+                .unwrap_or_else(|err| {
+                    finish(
+                        &notifier,
+                        Stop::Failed(format!("Unable to fetch the epoch info: {}", err)),
+                    )
+                });
+            // In the first epoch, wait for it to finish
+            epoch_info.epoch.saturating_sub(1)
+        };
+        RoundState::new(start_round, activation_epoch)
+    };
 
-        let remaining_voters: Vec<_> = voters::fetch_remaining_voters(&rpc_client)
-            .into_iter()
-            .map(|(node_pubkey, vote_account_pubkey)| {
-                (pubkey_to_keybase(&node_pubkey), vote_account_pubkey)
-            })
-            .collect();
+    let mut ramp = Ramp {
+        config: RampConfig {
+            bench: bench_config,
+            schedule,
+            gift_policy,
+            gift_dry_run: config.gift.dry_run || config.dry_run,
+            gift_workers: config.gift.workers,
+            gift_budget_policy: config.gift.budget_policy,
+            survivor_criteria: SurvivorCriteria {
+                max_vote_lag: config.survivors.max_vote_lag_slots,
+                max_root_lag: config.survivors.max_root_lag_slots,
+                min_activated_stake: sol_to_lamports(config.survivors.min_activated_stake),
+            },
+            survivor_samples: config.survivors.samples,
+            cooldown: config.cooldown(),
+            slot_advance_check: config.slot_advance_check(),
+            slot_stall_timeout: config.slot_stall_timeout(),
+            health_gate: config.health_gate(),
+            dry_run: config.dry_run,
+        },
+        rpc: Arc::new(rpc),
+        genesis_block,
+        stake_config,
+        mint_keypair: Arc::new(mint_keypair),
+        notifier,
+        results: tps_round_results,
+        pubkey_map,
+        state,
+        state_file,
+        gift_ledger,
+        bench: None,
+        shutdown,
+        control,
+        status,
+        dry_run_gifted: 0,
+        health_delay: None,
+    };
+    let stop = ramp.run();
+    finish(&ramp.notifier, stop);
+}
 
-        datapoint_info!(
-            "ramp-tps",
-            ("event", "stop-transactions", String),
-            ("round", tps_round, i64),
-            ("validators", remaining_voters.len(), i64)
-        );
+/// Print the projected stake warmup of the cluster, without gifts and with each of the gifts
+/// given on the command line
+fn plan_warmup(config: &Config, matches: &ArgMatches) -> Result<(), String> {
+    let rpc = RpcPool::new(
+        &config.rpc_endpoints()?,
+        Duration::from_secs(config.rpc.timeout_secs),
+        config.rpc.retry.clone(),
+    );
+    let max_epochs = config::arg_value(matches, "max_epochs")?.unwrap();
+    let num_validators = match config::arg_value(matches, "validators")? {
+        Some(num_validators) => num_validators,
+        None => rpc
+            .call(CallCategory::Voters, "get_vote_accounts", |rpc_client| {
+                rpc_client.get_vote_accounts()
+            })?
+            .current
+            .len(),
+    };
 
-        if remaining_voters.is_empty() {
-            utils::bail(&notifier, "Transactions stopped. No validators remain");
+    let mut scenarios = vec![("Without stake gifts".to_string(), 0)];
+    for gift_sol in matches.values_of("gift_sol").into_iter().flatten() {
+        let gift_sol: f64 = gift_sol
+            .parse()
+            .map_err(|err| format!("Invalid --gift-sol {}: {}", gift_sol, err))?;
+        if !gift_sol.is_finite() || gift_sol < 0.0 {
+            return Err(format!("Invalid --gift-sol {}", gift_sol));
         }
-        notifier.notify(&format!(
-            "Transactions stopped. There are {} validators remaining",
-            remaining_voters.len()
+        let lamports = sol_to_lamports(gift_sol).saturating_mul(num_validators as u64);
+        scenarios.push((
+            format!(
+                "Gifting {} SOL to each of {} validators ({} SOL in total)",
+                gift_sol,
+                num_validators,
+                lamports_to_sol(lamports)
+            ),
+            lamports,
         ));
+    }
+    if let Some(round) = config::arg_value::<u32>(matches, "gift_round")? {
+        let lamports =
+            budget::schedule_lamports(&config.gift_policy()?, round, 1, num_validators, 0);
+        scenarios.push((
+            format!(
+                "Gifting the round {} stake of the gift policy to {} validators ({} SOL in total)",
+                round,
+                num_validators,
+                lamports_to_sol(lamports)
+            ),
+            lamports,
+        ));
+    }
 
-        tps_round_results
-            .record(tps_round, &remaining_voters)
-            .unwrap_or_else(|err| {
-                warn!("Failed to record round results: {}", err);
-            });
-
-        datapoint_info!(
-            "ramp-tps",
-            ("event", "cooldown", String),
-            ("round", tps_round, i64)
-        );
-
-        // Idle for 5 minutes before awarding stake to let the cluster come back together before
-        // issuing RPC calls.
-        // This should not be necessary once https://github.com/solana-labs/solana/pull/6538 lands
-        notifier.notify("5 minute cool down");
-        sleep(Duration::from_secs(60 * 5));
-
-        datapoint_info!(
-            "ramp-tps",
-            ("event", "gifting", String),
-            ("round", tps_round, i64)
-        );
-
-        let next_gift = gift_for_round(tps_round + 1, initial_balance);
-        voters::award_stake(
-            &rpc_client,
-            &mint_keypair,
-            remaining_voters,
-            next_gift,
-            &mut notifier,
-        );
-
-        datapoint_info!(
-            "ramp-tps",
-            ("event", "new-stake-warmup", String),
-            ("round", tps_round, i64)
-        );
-
-        let epoch_info = rpc_client.get_epoch_info().unwrap();
-        debug!("Current epoch info: {:?}", &epoch_info);
-        let current_epoch = epoch_info.epoch;
-        stake::wait_for_activation(
-            current_epoch,
-            epoch_info,
-            &rpc_client,
-            &stake_config,
-            &genesis_block,
-            &notifier,
-        );
-        tps_round += 1;
+    let gifts: Vec<_> = scenarios.iter().map(|(_, lamports)| *lamports).collect();
+    let plans = stake::plan_warmup(&rpc, &gifts, max_epochs)?;
+    for ((description, _), plan) in scenarios.iter().zip(plans) {
+        println!("{}:\n{}\n", description, plan);
     }
+    Ok(())
+}
+
+/// Post why ramp-tps stopped, wait for every notification to be delivered and exit with a status
+/// that reflects it
+fn finish(notifier: &Notifications, stop: Stop) -> ! {
+    notifier.notify(&stop.to_string());
+    notifier.shutdown();
+    exit(stop.exit_code());
 }
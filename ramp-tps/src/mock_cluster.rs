@@ -0,0 +1,401 @@
+//! In-process stand-in for the JSON-RPC service of a cluster, for end-to-end tests
+//!
+//! Slots advance in real time at a compressed rate, every validator votes on the current slot
+//! until its scripted drop-out slot, and the genesis block is served as a tarball just like an
+//! RPC node does.
+
+use crate::{
+    health::MAX_SKIP_RATE_SLOTS,
+    http::{self, Request, Response},
+    rpc::{RetryPolicies, RpcPool},
+};
+use bzip2::{write::BzEncoder, Compression};
+use log::*;
+use serde::Serialize;
+use serde_json::{json, Value};
+use solana_client::{
+    rpc_client::RpcClient,
+    rpc_request::{
+        RpcContactInfo, RpcEpochInfo, RpcVersionInfo, RpcVoteAccountInfo, RpcVoteAccountStatus,
+    },
+};
+use solana_sdk::{
+    account::Account,
+    epoch_schedule::EpochSchedule,
+    fee_calculator::FeeCalculator,
+    genesis_block::GenesisBlock,
+    hash::Hash,
+    pubkey::Pubkey,
+    signature::Signature,
+    sysvar::stake_history::{self, StakeHistory, StakeHistoryEntry},
+    transaction::TransactionError,
+};
+use solana_stake_api::config as stake_config;
+use solana_vote_api::vote_state::{self, Lockout, VoteState};
+use std::{
+    fs, io,
+    net::{SocketAddr, TcpListener, TcpStream},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread::{sleep, JoinHandle},
+    time::{Duration, Instant},
+};
+
+pub const SLOTS_PER_EPOCH: u64 = 32;
+/// Short enough for a test to span several epochs in a few seconds
+pub const SLOT_DURATION: Duration = Duration::from_millis(10);
+/// Validators whose last vote is further behind are reported as delinquent
+const DELINQUENT_SLOT_DISTANCE: u64 = 64;
+/// Root slot of each validator, relative to its last vote
+const ROOT_SLOT_DISTANCE: u64 = 32;
+/// Transactions processed by the cluster per slot, besides the ones sent to it
+const BACKGROUND_TRANSACTIONS_PER_SLOT: u64 = 10;
+const GENESIS_ARCHIVE_PATH: &str = "/genesis.tar.bz2";
+
+static NEXT_CLUSTER_ID: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone, Debug)]
+pub struct MockValidator {
+    pub node_pubkey: Pubkey,
+    pub vote_pubkey: Pubkey,
+    pub activated_stake: u64,
+    /// The validator stops voting at this slot
+    pub drop_out_slot: Option<u64>,
+}
+
+impl MockValidator {
+    /// Derives the pubkeys of the validator from `seed`
+    pub fn new(seed: u8, activated_stake: u64) -> Self {
+        MockValidator {
+            node_pubkey: Pubkey::new(&[seed; 32]),
+            vote_pubkey: Pubkey::new(&[seed.wrapping_add(128); 32]),
+            activated_stake,
+            drop_out_slot: None,
+        }
+    }
+
+    fn last_vote(&self, slot: u64) -> u64 {
+        self.drop_out_slot
+            .map_or(slot, |drop_out_slot| drop_out_slot.min(slot))
+    }
+
+    fn vote_account(&self, slot: u64) -> Account {
+        let mut account = vote_state::create_account(
+            &self.vote_pubkey,
+            &self.node_pubkey,
+            0,
+            self.activated_stake,
+        );
+        let mut vote_state = VoteState::from(&account).unwrap();
+        let last_vote = self.last_vote(slot);
+        vote_state.root_slot = last_vote.checked_sub(ROOT_SLOT_DISTANCE);
+        // The tower holds a vote on each of the latest slots
+        vote_state.votes = (last_vote.saturating_sub(MAX_SKIP_RATE_SLOTS - 1)..=last_vote)
+            .map(|vote_slot| Lockout {
+                slot: vote_slot,
+                confirmation_count: (last_vote - vote_slot + 1) as u32,
+            })
+            .collect();
+        vote_state.to(&mut account).unwrap();
+        account
+    }
+}
+
+struct ClusterState {
+    validators: Vec<MockValidator>,
+    transactions_sent: u64,
+}
+
+struct Shared {
+    started: Instant,
+    /// The slot stops advancing at this slot, see `MockCluster::stall`
+    stalled_slot: AtomicU64,
+    rpc_addr: SocketAddr,
+    genesis_archive: Vec<u8>,
+    genesis_hash: Hash,
+    state: Mutex<ClusterState>,
+}
+
+pub struct MockCluster {
+    pub genesis_block: GenesisBlock,
+    shared: Arc<Shared>,
+    exit: Arc<AtomicBool>,
+    server: Option<JoinHandle<()>>,
+}
+
+fn to_json<T: Serialize>(value: T) -> Result<Value, String> {
+    serde_json::to_value(value).map_err(|err| err.to_string())
+}
+
+fn pubkey_param(params: &Value) -> Result<Pubkey, String> {
+    params[0]
+        .as_str()
+        .and_then(|pubkey| Pubkey::from_str(pubkey).ok())
+        .ok_or_else(|| format!("invalid pubkey param: {}", params))
+}
+
+/// Returns the first signature of a serialized transaction, whose signatures are prefixed with
+/// their count
+fn transaction_signature(params: &Value) -> Result<Signature, String> {
+    let bytes: Vec<u8> =
+        serde_json::from_value(params[0].clone()).map_err(|err| err.to_string())?;
+    if bytes.len() < 65 || bytes[0] == 0 {
+        return Err("transaction is not signed".to_string());
+    }
+    Ok(Signature::new(&bytes[1..65]))
+}
+
+fn genesis_archive(genesis_block: &GenesisBlock) -> io::Result<Vec<u8>> {
+    let genesis_dir = std::env::temp_dir().join(format!(
+        "ramp-tps-mock-genesis-{}-{}",
+        std::process::id(),
+        NEXT_CLUSTER_ID.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::create_dir_all(&genesis_dir)?;
+    genesis_block.write(&genesis_dir)?;
+    let mut archive = tar::Builder::new(BzEncoder::new(vec![], Compression::Best));
+    archive.append_dir_all(".", &genesis_dir)?;
+    let archive = archive.into_inner()?.finish()?;
+    fs::remove_dir_all(&genesis_dir)?;
+    Ok(archive)
+}
+
+impl Shared {
+    fn slot(&self) -> u64 {
+        let slot = (self.started.elapsed().as_millis() / SLOT_DURATION.as_millis()) as u64;
+        slot.min(self.stalled_slot.load(Ordering::Relaxed))
+    }
+
+    fn account(&self, state: &ClusterState, pubkey: &Pubkey, slot: u64) -> Option<Account> {
+        if *pubkey == stake_config::id() {
+            return Some(stake_config::create_account(
+                1,
+                &stake_config::Config::default(),
+            ));
+        }
+        if *pubkey == stake_history::id() {
+            // All stake is fully active from the first epoch on
+            let effective = state
+                .validators
+                .iter()
+                .map(|validator| validator.activated_stake)
+                .sum();
+            let mut stake_history = StakeHistory::default();
+            for epoch in 0..slot / SLOTS_PER_EPOCH {
+                stake_history.add(
+                    epoch,
+                    StakeHistoryEntry {
+                        effective,
+                        ..StakeHistoryEntry::default()
+                    },
+                );
+            }
+            return Some(stake_history::create_account(1, &stake_history));
+        }
+        state
+            .validators
+            .iter()
+            .find(|validator| validator.vote_pubkey == *pubkey)
+            .map(|validator| validator.vote_account(slot))
+    }
+
+    fn handle_rpc(&self, method: &str, params: &Value) -> Result<Value, String> {
+        let slot = self.slot();
+        let mut state = self.state.lock().unwrap();
+        match method {
+            "getSlot" => Ok(json!(slot)),
+            "getGenesisBlockhash" => Ok(json!(self.genesis_hash.to_string())),
+            "getEpochInfo" => to_json(RpcEpochInfo {
+                epoch: slot / SLOTS_PER_EPOCH,
+                slot_index: slot % SLOTS_PER_EPOCH,
+                slots_in_epoch: SLOTS_PER_EPOCH,
+                absolute_slot: slot,
+            }),
+            "getVoteAccounts" => {
+                let (current, delinquent): (Vec<_>, Vec<_>) = state
+                    .validators
+                    .iter()
+                    .map(|validator| RpcVoteAccountInfo {
+                        vote_pubkey: validator.vote_pubkey.to_string(),
+                        node_pubkey: validator.node_pubkey.to_string(),
+                        activated_stake: validator.activated_stake,
+                        commission: 0,
+                        epoch_vote_account: true,
+                        last_vote: validator.last_vote(slot),
+                    })
+                    .partition(|info| slot - info.last_vote <= DELINQUENT_SLOT_DISTANCE);
+                to_json(RpcVoteAccountStatus {
+                    current,
+                    delinquent,
+                })
+            }
+            "getAccountInfo" => {
+                let pubkey = pubkey_param(params)?;
+                let account = self
+                    .account(&state, &pubkey, slot)
+                    .ok_or_else(|| format!("account {} not found", pubkey))?;
+                to_json(account)
+            }
+            // Stake accounts are never created, so every gift is sent
+            "getBalance" => Ok(json!(0)),
+            "getRecentBlockhash" => Ok(json!([
+                Hash::default().to_string(),
+                FeeCalculator::default()
+            ])),
+            "sendTransaction" => {
+                let signature = transaction_signature(params)?;
+                state.transactions_sent += 1;
+                Ok(json!(signature.to_string()))
+            }
+            "getSignatureStatus" => to_json(Some(Ok::<(), TransactionError>(()))),
+            "getTransactionCount" => Ok(json!(
+                slot * BACKGROUND_TRANSACTIONS_PER_SLOT + state.transactions_sent
+            )),
+            "getClusterNodes" => to_json(
+                state
+                    .validators
+                    .iter()
+                    .map(|validator| RpcContactInfo {
+                        id: validator.node_pubkey.to_string(),
+                        gossip: Some(self.rpc_addr),
+                        tpu: None,
+                        rpc: None,
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            "getVersion" => to_json(RpcVersionInfo {
+                solana_core: "0.20.0".to_string(),
+            }),
+            _ => Err(format!("unsupported method {}", method)),
+        }
+    }
+
+    fn handle_request(&self, request: &Request) -> Response {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", GENESIS_ARCHIVE_PATH) => {
+                Response::ok("application/octet-stream", self.genesis_archive.clone())
+            }
+            ("POST", _) => {
+                let request: Value = serde_json::from_slice(&request.body).unwrap_or_default();
+                let method = request["method"].as_str().unwrap_or_default();
+                let response = match self.handle_rpc(method, &request["params"]) {
+                    Ok(result) => json!({"jsonrpc": "2.0", "id": request["id"], "result": result}),
+                    Err(err) => {
+                        debug!("Mock RPC {} failed: {}", method, err);
+                        json!({
+                            "jsonrpc": "2.0",
+                            "id": request["id"],
+                            "error": {"code": -32602, "message": err},
+                        })
+                    }
+                };
+                Response::ok("application/json", response.to_string().into_bytes())
+            }
+            _ => Response::not_found(),
+        }
+    }
+}
+
+impl MockCluster {
+    pub fn start(validators: Vec<MockValidator>) -> Self {
+        let mut genesis_block = GenesisBlock::default();
+        genesis_block.ticks_per_slot = 1;
+        genesis_block.poh_config.target_tick_duration = SLOT_DURATION;
+        genesis_block.epoch_schedule =
+            EpochSchedule::custom(SLOTS_PER_EPOCH, SLOTS_PER_EPOCH, false);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let shared = Arc::new(Shared {
+            started: Instant::now(),
+            stalled_slot: AtomicU64::new(std::u64::MAX),
+            rpc_addr: listener.local_addr().unwrap(),
+            genesis_archive: genesis_archive(&genesis_block).unwrap(),
+            genesis_hash: genesis_block.hash(),
+            state: Mutex::new(ClusterState {
+                validators,
+                transactions_sent: 0,
+            }),
+        });
+        let exit = Arc::new(AtomicBool::new(false));
+
+        let server = {
+            let shared = shared.clone();
+            http::serve(
+                "mock-cluster",
+                listener,
+                Some(exit.clone()),
+                move |request| shared.handle_request(request),
+            )
+            .unwrap()
+        };
+
+        MockCluster {
+            genesis_block,
+            shared,
+            exit,
+            server: Some(server),
+        }
+    }
+
+    pub fn rpc_addr(&self) -> SocketAddr {
+        self.shared.rpc_addr
+    }
+
+    pub fn rpc_client(&self) -> RpcClient {
+        RpcClient::new_socket_with_timeout(self.rpc_addr(), Duration::from_secs(10))
+    }
+
+    pub fn rpc_pool(&self) -> RpcPool {
+        RpcPool::new(
+            &[self.rpc_addr()],
+            Duration::from_secs(10),
+            RetryPolicies::default(),
+        )
+    }
+
+    pub fn slot(&self) -> u64 {
+        self.shared.slot()
+    }
+
+    /// Wait until the cluster reaches `slot`
+    pub fn wait_for_slot(&self, slot: u64) {
+        while self.slot() < slot {
+            sleep(SLOT_DURATION);
+        }
+    }
+
+    /// The slot stops advancing at the current slot
+    pub fn stall(&self) {
+        let slot = self.slot();
+        self.shared.stalled_slot.store(slot, Ordering::Relaxed);
+    }
+
+    /// The validator voting with `vote_pubkey` stops voting at the current slot
+    pub fn drop_out(&self, vote_pubkey: &Pubkey) {
+        let slot = self.slot();
+        let mut state = self.shared.state.lock().unwrap();
+        for validator in state.validators.iter_mut() {
+            if validator.vote_pubkey == *vote_pubkey {
+                validator.drop_out_slot = Some(slot);
+            }
+        }
+    }
+
+    pub fn transactions_sent(&self) -> u64 {
+        self.shared.state.lock().unwrap().transactions_sent
+    }
+}
+
+impl Drop for MockCluster {
+    fn drop(&mut self) {
+        self.exit.store(true, Ordering::Relaxed);
+        // Wake up the server, which is blocked accepting the next connection
+        let _ = TcpStream::connect(self.shared.rpc_addr);
+        if let Some(server) = self.server.take() {
+            let _ = server.join();
+        }
+    }
+}
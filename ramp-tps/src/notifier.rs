@@ -1,52 +1,541 @@
+use crate::utils;
 use log::*;
-use reqwest::Client;
+use reqwest::{Client, RequestBuilder, StatusCode, Url};
+use serde_derive::Deserialize;
 use serde_json::json;
-use std::env;
+use std::{
+    env, fmt,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, sleep, JoinHandle},
+    time::{Duration, Instant},
+};
+
+const MAX_SEND_ATTEMPTS: usize = 5;
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub enum SendError {
+    /// The backend asked us to wait before sending again
+    RateLimited(Duration),
+    Failed(String),
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SendError::RateLimited(retry_after) => write!(f, "rate limited for {:?}", retry_after),
+            SendError::Failed(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// A destination for ramp-tps notifications
+pub trait Notifier: Send {
+    fn name(&self) -> &str;
+    fn send(&self, msg: &str) -> Result<(), SendError>;
+
+    /// Longer messages are split into several sends
+    fn max_message_len(&self) -> usize {
+        usize::max_value()
+    }
+
+    /// The shortest interval between two sends which stays within the backend's rate limit
+    fn min_send_interval(&self) -> Duration {
+        Duration::from_secs(0)
+    }
+}
+
+fn send_request(request: RequestBuilder) -> Result<(), SendError> {
+    let response = request
+        .send()
+        .map_err(|err| SendError::Failed(format!("{:?}", err)))?;
+    if response.status() == StatusCode::TOO_MANY_REQUESTS {
+        let retry_after = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|retry_after| retry_after.to_str().ok())
+            .and_then(|retry_after| retry_after.parse().ok())
+            .unwrap_or(1);
+        return Err(SendError::RateLimited(Duration::from_secs(retry_after)));
+    }
+    response
+        .error_for_status()
+        .map(|_| ())
+        .map_err(|err| SendError::Failed(format!("{:?}", err)))
+}
+
+fn post_json(client: &Client, url: &str, data: &serde_json::Value) -> Result<(), SendError> {
+    send_request(client.post(url).json(data))
+}
+
+pub struct DiscordNotifier {
+    client: Client,
+    webhook: String,
+}
+
+impl Notifier for DiscordNotifier {
+    fn name(&self) -> &str {
+        "Discord"
+    }
+
+    fn send(&self, msg: &str) -> Result<(), SendError> {
+        post_json(&self.client, &self.webhook, &json!({ "content": msg }))
+    }
+
+    fn max_message_len(&self) -> usize {
+        2000
+    }
+
+    // Discord allows 30 webhook messages per minute in a channel
+    fn min_send_interval(&self) -> Duration {
+        Duration::from_secs(2)
+    }
+}
+
+pub struct SlackNotifier {
+    client: Client,
+    webhook: String,
+}
+
+impl Notifier for SlackNotifier {
+    fn name(&self) -> &str {
+        "Slack"
+    }
+
+    fn send(&self, msg: &str) -> Result<(), SendError> {
+        post_json(&self.client, &self.webhook, &json!({ "text": msg }))
+    }
+
+    fn max_message_len(&self) -> usize {
+        4000
+    }
+
+    fn min_send_interval(&self) -> Duration {
+        Duration::from_secs(1)
+    }
+}
+
+pub struct TelegramNotifier {
+    client: Client,
+    bot_token: String,
+    chat_id: String,
+}
+
+impl Notifier for TelegramNotifier {
+    fn name(&self) -> &str {
+        "Telegram"
+    }
+
+    fn send(&self, msg: &str) -> Result<(), SendError> {
+        let url = format!("https://api.telegram.org/bot{}/sendMessage", self.bot_token);
+        post_json(
+            &self.client,
+            &url,
+            &json!({ "chat_id": self.chat_id, "text": msg }),
+        )
+    }
+
+    fn max_message_len(&self) -> usize {
+        4096
+    }
+
+    // Telegram allows 20 messages per minute in a group
+    fn min_send_interval(&self) -> Duration {
+        Duration::from_secs(3)
+    }
+}
+
+pub struct MatrixNotifier {
+    client: Client,
+    homeserver: String,
+    room_id: String,
+    access_token: String,
+    txn_count: AtomicUsize,
+}
+
+impl Notifier for MatrixNotifier {
+    fn name(&self) -> &str {
+        "Matrix"
+    }
+
+    fn send(&self, msg: &str) -> Result<(), SendError> {
+        // Matrix de-duplicates messages by transaction id, so each message needs a unique one
+        let txn_id = format!(
+            "ramp-tps-{}-{}",
+            utils::unix_timestamp(),
+            self.txn_count.fetch_add(1, Ordering::Relaxed)
+        );
+        let mut url =
+            Url::parse(&self.homeserver).map_err(|err| SendError::Failed(format!("{:?}", err)))?;
+        url.path_segments_mut()
+            .map_err(|_| SendError::Failed(format!("invalid homeserver url: {}", self.homeserver)))?
+            .pop_if_empty()
+            .extend(&[
+                "_matrix",
+                "client",
+                "r0",
+                "rooms",
+                self.room_id.as_str(),
+                "send",
+                "m.room.message",
+                txn_id.as_str(),
+            ]);
+        send_request(
+            self.client
+                .put(url)
+                .bearer_auth(&self.access_token)
+                .json(&json!({ "msgtype": "m.text", "body": msg })),
+        )
+    }
+
+    fn max_message_len(&self) -> usize {
+        32_000
+    }
+}
+
+/// Posts `{"source": "ramp-tps", "message": ...}` to an arbitrary URL
+pub struct WebhookNotifier {
+    client: Client,
+    url: String,
+}
+
+impl Notifier for WebhookNotifier {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    fn send(&self, msg: &str) -> Result<(), SendError> {
+        post_json(
+            &self.client,
+            &self.url,
+            &json!({ "source": "ramp-tps", "message": msg }),
+        )
+    }
+}
+
+/// Appends every notification to a file, for the audit trail
+pub struct FileNotifier {
+    path: PathBuf,
+}
+
+impl Notifier for FileNotifier {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    fn send(&self, msg: &str) -> Result<(), SendError> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|err| SendError::Failed(format!("Unable to open {:?}: {}", self.path, err)))?;
+        writeln!(file, "[{}] {}", utils::unix_timestamp(), msg)
+            .map_err(|err| SendError::Failed(format!("Unable to write {:?}: {}", self.path, err)))
+    }
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub url: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TelegramConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub bot_token: String,
+    pub chat_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MatrixConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub homeserver: String,
+    pub room_id: String,
+    pub access_token: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub path: PathBuf,
+}
+
+/// Notification backends, each of which may be enabled or disabled independently
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotifierConfig {
+    pub discord: Option<WebhookConfig>,
+    pub slack: Option<WebhookConfig>,
+    pub telegram: Option<TelegramConfig>,
+    pub matrix: Option<MatrixConfig>,
+    pub webhook: Option<WebhookConfig>,
+    pub file: Option<FileConfig>,
+}
+
+impl NotifierConfig {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let file = File::open(path).map_err(|err| format!("Unable to open {}: {}", path, err))?;
+        serde_yaml::from_reader(file).map_err(|err| format!("Unable to parse {}: {}", path, err))
+    }
+
+    /// Discord and Slack webhooks may be configured with the `DISCORD_WEBHOOK` and
+    /// `SLACK_WEBHOOK` env vars
+    pub fn from_env() -> Self {
+        let webhook_from_env = |var: &str| {
+            env::var(var)
+                .ok()
+                .map(|url| WebhookConfig { enabled: true, url })
+        };
+        NotifierConfig {
+            discord: webhook_from_env("DISCORD_WEBHOOK"),
+            slack: webhook_from_env("SLACK_WEBHOOK"),
+            ..NotifierConfig::default()
+        }
+    }
+}
+
+/// Split `msg` into chunks of at most `max_len` characters, preferably at line breaks
+fn split_message(msg: &str, max_len: usize) -> Vec<String> {
+    let max_len = max_len.max(1);
+    let mut chunks = vec![];
+    let mut chunk = String::new();
+    let mut chunk_len = 0;
+    for line in msg.split('\n') {
+        let line_len = line.chars().count();
+        if chunk_len > 0 && chunk_len + 1 + line_len > max_len {
+            chunks.push(chunk.split_off(0));
+            chunk_len = 0;
+        }
+        if chunk_len > 0 {
+            chunk.push('\n');
+            chunk_len += 1;
+        }
+        for c in line.chars() {
+            if chunk_len == max_len {
+                chunks.push(chunk.split_off(0));
+                chunk_len = 0;
+            }
+            chunk.push(c);
+            chunk_len += 1;
+        }
+    }
+    if chunk_len > 0 || chunks.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+/// Delivery counters for a single notification backend
+#[derive(Default)]
+pub struct DeliveryStats {
+    pub delivered: AtomicUsize,
+    pub retried: AtomicUsize,
+    pub failed: AtomicUsize,
+}
+
+struct Worker {
+    name: String,
+    sender: Sender<Arc<String>>,
+    handle: JoinHandle<()>,
+    stats: Arc<DeliveryStats>,
+}
+
+/// Deliver queued messages in order, retrying failed sends with exponential backoff
+fn run_worker(
+    notifier: Box<dyn Notifier>,
+    receiver: Receiver<Arc<String>>,
+    stats: Arc<DeliveryStats>,
+) {
+    let min_send_interval = notifier.min_send_interval();
+    let mut last_send: Option<Instant> = None;
+    for msg in receiver.iter() {
+        for chunk in split_message(&msg, notifier.max_message_len()) {
+            let mut retry_delay = INITIAL_RETRY_DELAY;
+            for attempt in 1..=MAX_SEND_ATTEMPTS {
+                if let Some(last_send) = last_send {
+                    let elapsed = last_send.elapsed();
+                    if elapsed < min_send_interval {
+                        sleep(min_send_interval - elapsed);
+                    }
+                }
+                last_send = Some(Instant::now());
+
+                match notifier.send(&chunk) {
+                    Ok(()) => {
+                        stats.delivered.fetch_add(1, Ordering::Relaxed);
+                        break;
+                    }
+                    Err(err) if attempt < MAX_SEND_ATTEMPTS => {
+                        warn!(
+                            "Failed to send {} message (attempt {}/{}): {}",
+                            notifier.name(),
+                            attempt,
+                            MAX_SEND_ATTEMPTS,
+                            err
+                        );
+                        stats.retried.fetch_add(1, Ordering::Relaxed);
+                        match err {
+                            SendError::RateLimited(retry_after) => {
+                                sleep(retry_after.max(retry_delay))
+                            }
+                            SendError::Failed(_) => sleep(retry_delay),
+                        }
+                        retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+                    }
+                    Err(err) => {
+                        warn!(
+                            "Giving up on {} message after {} attempts: {}",
+                            notifier.name(),
+                            MAX_SEND_ATTEMPTS,
+                            err
+                        );
+                        stats.failed.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+    }
+}
 
 /// For each notification
 ///   1) Log an info level message
-///   2) Notify Slack channel if Slack is configured
-///   3) Notify Discord channel if Discord is configured
-pub struct Notifier {
+///   2) Queue the message for every enabled notification backend
+///
+/// Each backend delivers its queue on its own thread, so a slow or failing backend does not
+/// hold up the others.
+pub struct Notifications {
     buffer: Vec<String>,
-    client: Client,
-    discord_webhook: Option<String>,
-    slack_webhook: Option<String>,
+    workers: Mutex<Vec<Worker>>,
 }
 
-impl Notifier {
-    pub fn new() -> Self {
-        let discord_webhook = env::var("DISCORD_WEBHOOK")
-            .map_err(|_| {
-                warn!("Discord notifications disabled");
-            })
-            .ok();
-        let slack_webhook = env::var("SLACK_WEBHOOK")
-            .map_err(|_| {
-                warn!("Slack notifications disabled");
+impl Notifications {
+    pub fn new(config: NotifierConfig) -> Self {
+        let client = Client::new();
+        let mut notifiers: Vec<Box<dyn Notifier>> = vec![];
+        match config.discord {
+            Some(ref discord) if discord.enabled => notifiers.push(Box::new(DiscordNotifier {
+                client: client.clone(),
+                webhook: discord.url.clone(),
+            })),
+            _ => warn!("Discord notifications disabled"),
+        }
+        match config.slack {
+            Some(ref slack) if slack.enabled => notifiers.push(Box::new(SlackNotifier {
+                client: client.clone(),
+                webhook: slack.url.clone(),
+            })),
+            _ => warn!("Slack notifications disabled"),
+        }
+        match config.telegram {
+            Some(ref telegram) if telegram.enabled => notifiers.push(Box::new(TelegramNotifier {
+                client: client.clone(),
+                bot_token: telegram.bot_token.clone(),
+                chat_id: telegram.chat_id.clone(),
+            })),
+            _ => info!("Telegram notifications disabled"),
+        }
+        match config.matrix {
+            Some(ref matrix) if matrix.enabled => notifiers.push(Box::new(MatrixNotifier {
+                client: client.clone(),
+                homeserver: matrix.homeserver.clone(),
+                room_id: matrix.room_id.clone(),
+                access_token: matrix.access_token.clone(),
+                txn_count: AtomicUsize::new(0),
+            })),
+            _ => info!("Matrix notifications disabled"),
+        }
+        match config.webhook {
+            Some(ref webhook) if webhook.enabled => notifiers.push(Box::new(WebhookNotifier {
+                client,
+                url: webhook.url.clone(),
+            })),
+            _ => info!("Webhook notifications disabled"),
+        }
+        match config.file {
+            Some(ref file) if file.enabled => notifiers.push(Box::new(FileNotifier {
+                path: file.path.clone(),
+            })),
+            _ => info!("File notifications disabled"),
+        }
+
+        let workers = notifiers
+            .into_iter()
+            .map(|notifier| {
+                let name = notifier.name().to_string();
+                let (sender, receiver) = channel();
+                let stats = Arc::new(DeliveryStats::default());
+                let worker_stats = stats.clone();
+                let handle = thread::Builder::new()
+                    .name(format!("notifier-{}", name))
+                    .spawn(move || run_worker(notifier, receiver, worker_stats))
+                    .unwrap();
+                Worker {
+                    name,
+                    sender,
+                    handle,
+                    stats,
+                }
             })
-            .ok();
-        Notifier {
+            .collect();
+
+        Notifications {
             buffer: Vec::new(),
-            client: Client::new(),
-            discord_webhook,
-            slack_webhook,
+            workers: Mutex::new(workers),
         }
     }
 
     fn send(&self, msg: &str) {
-        if let Some(webhook) = &self.discord_webhook {
-            let data = json!({ "content": msg });
-            if let Err(err) = self.client.post(webhook).json(&data).send() {
-                warn!("Failed to send Discord message: {:?}", err);
+        let msg = Arc::new(msg.to_string());
+        for worker in self.workers.lock().unwrap().iter() {
+            if worker.sender.send(msg.clone()).is_err() {
+                warn!("{} notifier is not running", worker.name);
             }
         }
+    }
 
-        if let Some(webhook) = &self.slack_webhook {
-            let data = json!({ "text": msg });
-            if let Err(err) = self.client.post(webhook).json(&data).send() {
-                warn!("Failed to send Slack message: {:?}", err);
+    /// Wait until every queued message has been delivered or given up on. Notifications sent
+    /// after shutdown are only logged.
+    pub fn shutdown(&self) {
+        let workers: Vec<_> = self.workers.lock().unwrap().drain(..).collect();
+        for worker in workers {
+            let Worker {
+                name,
+                sender,
+                handle,
+                stats,
+            } = worker;
+            drop(sender);
+            if handle.join().is_err() {
+                warn!("{} notifier panicked", name);
             }
+            info!(
+                "{} notifications: {} delivered, {} retried, {} failed",
+                name,
+                stats.delivered.load(Ordering::Relaxed),
+                stats.retried.load(Ordering::Relaxed),
+                stats.failed.load(Ordering::Relaxed),
+            );
         }
     }
 
@@ -64,3 +553,46 @@ impl Notifier {
         self.send(msg);
     }
 }
+
+impl Drop for Notifications {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_message() {
+        assert_eq!(split_message("", 10), vec![""]);
+        assert_eq!(split_message("short", 10), vec!["short"]);
+        assert_eq!(
+            split_message("line 1\nline 2\nline 3", 13),
+            vec!["line 1\nline 2", "line 3"]
+        );
+        assert_eq!(
+            split_message("abcdefghij\nk", 4),
+            vec!["abcd", "efgh", "ij\nk"]
+        );
+        assert_eq!(split_message("ééé", 2), vec!["éé", "é"]);
+    }
+
+    #[test]
+    fn test_notifier_config_rejects_unknown_keys() {
+        let config: NotifierConfig =
+            serde_yaml::from_str("discord:\n  url: https://discord.example/webhook\n").unwrap();
+        assert!(config.discord.unwrap().enabled);
+
+        // A misspelled backend would otherwise leave notifications silently disabled
+        assert!(serde_yaml::from_str::<NotifierConfig>(
+            "dicsord:\n  url: https://discord.example/webhook\n"
+        )
+        .is_err());
+        assert!(serde_yaml::from_str::<NotifierConfig>(
+            "telegram:\n  bot_token: token\n  chat_id: chat\n  enable: false\n"
+        )
+        .is_err());
+    }
+}
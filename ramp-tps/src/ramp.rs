@@ -0,0 +1,1442 @@
+//! Round state machine which drives the TPS ramp
+//!
+//! The current `RoundState` is saved after every phase transition so that a restarted ramp-tps
+//! picks up exactly where the previous one left off.
+
+use crate::{
+    bench::{Bench, BenchConfig},
+    budget::{self, BudgetPolicy},
+    control::Control,
+    gift::{GiftCandidate, GiftPolicy},
+    gifting::{self, GiftJob},
+    health::{HealthGate, HealthSample, MAX_SKIP_RATE_SLOTS},
+    ledger::{GiftLedger, GiftLedgerEntry, GiftStatus},
+    notifier::Notifications,
+    results::{DropOut, GiftRecord, Results, RoundRecord, ValidatorRecord},
+    rpc::{CallCategory, RpcPool},
+    schedule::Schedule,
+    shutdown::{Shutdown, Stop},
+    stake,
+    state::{FailedGift, GiftRecipient, PendingGift, Phase, RoundState, StateFile},
+    status::{BenchStatus, GiftSummary, RampStatus, Status},
+    throughput::{self, ProgressSample},
+    utils,
+    voters::{self, SurvivorCriteria, VoterSample},
+    wait::SlotWaiter,
+};
+use log::*;
+use solana_metrics::datapoint_info;
+use solana_sdk::{
+    genesis_block::GenesisBlock,
+    native_token::lamports_to_sol,
+    pubkey::Pubkey,
+    signature::{Keypair, KeypairUtil},
+};
+use solana_stake_api::config::Config as StakeConfig;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const BENCH_PROGRESS_INTERVAL: Duration = Duration::from_secs(60);
+/// How often operator requests are checked while waiting
+const CONTROL_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// While a round is delayed by an unhealthy cluster, the reasons are posted at most this often
+const HEALTH_NOTIFY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+pub struct RampConfig {
+    pub bench: BenchConfig,
+    pub schedule: Schedule,
+    pub gift_policy: GiftPolicy,
+    /// Print the planned stake gifts without delegating any stake
+    pub gift_dry_run: bool,
+    /// Maximum number of stake gifts in flight at once
+    pub gift_workers: usize,
+    /// What to do when the mint balance does not cover the gifts of a round
+    pub gift_budget_policy: BudgetPolicy,
+    pub survivor_criteria: SurvivorCriteria,
+    /// Number of survivor samples taken while transactions are running
+    pub survivor_samples: u32,
+    /// Idle time between stopping transactions and awarding stake
+    pub cooldown: Duration,
+    /// How long to wait for the slot to advance before starting a round
+    pub slot_advance_check: Duration,
+    /// How long the slot may stop advancing while waiting for an epoch
+    pub slot_stall_timeout: Duration,
+    /// Checked before each round starts, which is delayed until the gate passes
+    pub health_gate: Option<HealthGate>,
+    /// Only read from the cluster: no bench clients, no stake gifts and no saved progress
+    pub dry_run: bool,
+}
+
+pub struct Ramp {
+    pub config: RampConfig,
+    pub rpc: Arc<RpcPool>,
+    pub genesis_block: GenesisBlock,
+    pub stake_config: StakeConfig,
+    pub mint_keypair: Arc<Keypair>,
+    pub notifier: Notifications,
+    pub results: Results,
+    pub pubkey_map: HashMap<String, String>,
+    pub state: RoundState,
+    pub state_file: StateFile,
+    pub gift_ledger: GiftLedger,
+    pub bench: Option<Bench>,
+    pub shutdown: Shutdown,
+    pub control: Control,
+    pub status: Status,
+    /// Stake that would have been gifted so far including fees, in dry run mode
+    pub dry_run_gifted: u64,
+    /// When the current round was first delayed by the health gate, and when that was last
+    /// posted
+    pub health_delay: Option<(Instant, Instant)>,
+}
+
+impl Ramp {
+    /// Run rounds until every validator drops out, an error occurs or a shutdown is requested.
+    /// Bench clients are stopped before returning
+    pub fn run(&mut self) -> Stop {
+        let mut result = self.save_state();
+        while result.is_ok() {
+            result = self.run_phase();
+        }
+        let stop = result.unwrap_err();
+        if let Some(bench) = self.bench.take() {
+            info!("Stopping bench clients...");
+            bench.stop(&self.rpc);
+        }
+        if stop == Stop::Interrupted {
+            info!(
+                "Stopped at the {} phase of round {}",
+                self.state.phase, self.state.round
+            );
+        }
+        stop
+    }
+
+    fn run_phase(&mut self) -> Result<(), Stop> {
+        self.shutdown.check()?;
+        self.control.update(&self.state);
+        self.wait_while_paused()?;
+        debug!("Round {}: {}", self.state.round, self.state.phase);
+        match self.state.phase {
+            Phase::NewStakeWarmup => self.new_stake_warmup(),
+            Phase::RoundStart => self.round_start(),
+            Phase::StartTransactions => self.start_transactions(),
+            Phase::StopTransactions => self.stop_transactions(),
+            Phase::Cooldown => self.cooldown(),
+            Phase::Gifting => self.gifting(),
+        }
+    }
+
+    fn wait_while_paused(&mut self) -> Result<(), Stop> {
+        if !self.control.is_paused() {
+            return Ok(());
+        }
+        self.notifier.notify(&format!(
+            "Paused before the {} phase of round {}",
+            self.state.phase, self.state.round
+        ));
+        while self.control.is_paused() {
+            if self.control.abort_requested() {
+                return Err(Stop::Complete(
+                    "Stopped while paused at the request of an operator".to_string(),
+                ));
+            }
+            self.shutdown.sleep(CONTROL_POLL_INTERVAL)?;
+        }
+        self.notifier.notify("Resumed");
+        Ok(())
+    }
+
+    fn transition(&mut self, phase: Phase) -> Result<(), Stop> {
+        self.state.transition(phase);
+        self.save_state()
+    }
+
+    fn save_state(&self) -> Result<(), Stop> {
+        self.status.publish(self.ramp_status());
+        if self.config.dry_run {
+            return Ok(());
+        }
+        self.state_file
+            .save(&self.state)
+            .map_err(|err| Stop::Failed(format!("Failed to save round state: {}", err)))
+    }
+
+    fn ramp_status(&self) -> RampStatus {
+        let state = &self.state;
+        let phase_ends_at = match state.phase {
+            Phase::StartTransactions => state.transactions_started_at.map(|started_at| {
+                started_at
+                    + self.config.schedule.round_duration(state.round).as_secs()
+                    + state.round_extension_secs
+            }),
+            Phase::Cooldown => Some(state.phase_started_at + self.config.cooldown.as_secs()),
+            _ => None,
+        };
+        let survivors = if state.survivor_samples > 0 {
+            state
+                .healthy_samples
+                .keys()
+                .filter(|vote_pubkey| state.healthy_in_every_sample(vote_pubkey))
+                .cloned()
+                .collect()
+        } else {
+            vec![]
+        };
+        RampStatus {
+            round: state.round,
+            phase: Some(state.phase),
+            phase_started_at: state.phase_started_at,
+            phase_ends_at,
+            tx_count: state.tx_count,
+            survivors,
+            drop_outs: state.drop_outs.len(),
+            achieved_tps: throughput::measure(&state.progress_samples, self.target_slot_duration())
+                .map(|throughput| throughput.achieved_tps),
+            bench: self.bench.as_ref().map(|bench| {
+                let counters = bench.counters();
+                BenchStatus {
+                    submitted: counters.submitted(),
+                    confirmed: counters.confirmed(),
+                    failed: counters.failed(),
+                }
+            }),
+            next_gift: state.pending_gift.as_ref().map(|pending_gift| GiftSummary {
+                recipients: pending_gift.recipients.len(),
+                lamports: pending_gift
+                    .recipients
+                    .iter()
+                    .map(|recipient| recipient.lamports)
+                    .sum(),
+            }),
+        }
+    }
+
+    fn pubkey_to_keybase(&self, pubkey: &Pubkey) -> String {
+        let pubkey = pubkey.to_string();
+        match self.pubkey_map.get(&pubkey) {
+            Some(keybase) => format!("{} ({})", keybase, pubkey),
+            None => pubkey,
+        }
+    }
+
+    fn validator_record(&self, identity: String, vote_pubkey: Option<String>) -> ValidatorRecord {
+        ValidatorRecord {
+            keybase: self.pubkey_map.get(&identity).cloned(),
+            identity,
+            vote_pubkey,
+        }
+    }
+
+    /// Record the current slot and cluster transaction count. Returns `None` if either is
+    /// unavailable
+    fn sample_progress(&mut self) -> Result<Option<ProgressSample>, Stop> {
+        let sample = self
+            .rpc
+            .call(CallCategory::Query, "get_slot", |rpc_client| {
+                rpc_client.get_slot()
+            })
+            .and_then(|slot| {
+                self.rpc
+                    .call(CallCategory::Query, "get_transaction_count", |rpc_client| {
+                        rpc_client.get_transaction_count()
+                    })
+                    .map(|transaction_count| ProgressSample {
+                        timestamp_ms: utils::unix_timestamp_ms(),
+                        slot,
+                        transaction_count,
+                    })
+            });
+        match sample {
+            Ok(sample) => {
+                self.state.progress_samples.push(sample.clone());
+                self.save_state()?;
+                Ok(Some(sample))
+            }
+            Err(err) => {
+                warn!("Unable to sample the cluster progress: {}", err);
+                Ok(None)
+            }
+        }
+    }
+
+    fn target_slot_duration(&self) -> Duration {
+        utils::slot_duration(&self.genesis_block)
+    }
+
+    fn current_epoch(&self) -> Result<u64, Stop> {
+        self.rpc
+            .call(CallCategory::Query, "get_epoch_info", |rpc_client| {
+                rpc_client.get_epoch_info()
+            })
+            .map(|epoch_info| epoch_info.epoch)
+            .map_err(Stop::Failed)
+    }
+
+    fn new_stake_warmup(&mut self) -> Result<(), Stop> {
+        datapoint_info!(
+            "ramp-tps",
+            ("event", "new-stake-warmup", String),
+            ("round", self.state.round, i64)
+        );
+
+        let epoch_info = self
+            .rpc
+            .call(CallCategory::Query, "get_epoch_info", |rpc_client| {
+                rpc_client.get_epoch_info()
+            })?;
+        debug!("Current epoch info: {:?}", &epoch_info);
+        let activation_epoch = self.state.activation_epoch.unwrap_or(epoch_info.epoch);
+        debug!("Activation epoch is: {:?}", activation_epoch);
+        stake::wait_for_activation(
+            activation_epoch,
+            epoch_info,
+            &self.rpc,
+            &self.stake_config,
+            &SlotWaiter::new(
+                &self.rpc,
+                &self.shutdown,
+                self.target_slot_duration(),
+                self.config.slot_stall_timeout,
+            ),
+            &self.notifier,
+            &self.shutdown,
+        )?;
+        self.transition(Phase::RoundStart)
+    }
+
+    /// Returns whether the cluster passes the health gate. Otherwise the round is delayed by the
+    /// retry interval of the gate, and the phase is run again
+    fn check_cluster_health(&mut self) -> Result<bool, Stop> {
+        let gate = match &self.config.health_gate {
+            Some(gate) => gate.clone(),
+            None => return Ok(true),
+        };
+        let tps_round = self.state.round;
+        let reasons = gate.check(&self.rpc, &self.shutdown)?;
+        if reasons.is_empty() {
+            if let Some((delayed_at, _)) = self.health_delay.take() {
+                self.notifier.notify(&format!(
+                    "The cluster is healthy again after {} minutes",
+                    delayed_at.elapsed().as_secs() / 60
+                ));
+            }
+            return Ok(true);
+        }
+
+        let now = Instant::now();
+        let message = format!(
+            "Delaying round {} by {}s, the cluster is unhealthy: {}",
+            tps_round,
+            gate.retry_interval.as_secs(),
+            reasons.join(", ")
+        );
+        match self.health_delay {
+            Some((delayed_at, notified_at))
+                if now.duration_since(notified_at) < HEALTH_NOTIFY_INTERVAL =>
+            {
+                warn!("{}", message);
+                self.health_delay = Some((delayed_at, notified_at));
+            }
+            Some((delayed_at, _)) => {
+                self.notifier.notify(&message);
+                self.health_delay = Some((delayed_at, now));
+            }
+            None => {
+                self.notifier.notify(&message);
+                self.health_delay = Some((now, now));
+            }
+        }
+
+        while now.elapsed() < gate.retry_interval {
+            if self.control.abort_requested() {
+                return Err(Stop::Complete(format!(
+                    "Stopped before round {} at the request of an operator",
+                    tps_round
+                )));
+            }
+            self.shutdown.sleep(
+                gate.retry_interval
+                    .checked_sub(now.elapsed())
+                    .unwrap_or_default()
+                    .min(CONTROL_POLL_INTERVAL),
+            )?;
+        }
+        Ok(false)
+    }
+
+    fn round_start(&mut self) -> Result<(), Stop> {
+        if !self.check_cluster_health()? {
+            return Ok(());
+        }
+        let tps_round = self.state.round;
+        self.notifier.notify(&format!("Round {}!", tps_round));
+        if self.config.schedule.is_first_round_past_table(tps_round) {
+            warn!(
+                "Round {} is past the end of the schedule table, repeating its last round from now on",
+                tps_round
+            );
+        }
+        let tx_count = self.config.schedule.tx_count(tps_round);
+        datapoint_info!(
+            "ramp-tps",
+            ("event", "round-start", String),
+            ("round", tps_round, i64),
+            ("tx_count", tx_count, i64)
+        );
+
+        let slot = self
+            .rpc
+            .call(CallCategory::Query, "get_slot", |rpc_client| {
+                rpc_client.get_slot()
+            })?;
+        self.shutdown.sleep(self.config.slot_advance_check)?;
+        let latest_slot = self
+            .rpc
+            .call(CallCategory::Query, "get_slot", |rpc_client| {
+                rpc_client.get_slot()
+            })?;
+        if slot == latest_slot {
+            return Err(Stop::Failed(format!("Slot is not advancing from {}", slot)));
+        }
+
+        self.state.tx_count = tx_count;
+        self.state.transactions_started_at = None;
+        self.state.round_extension_secs = 0;
+        self.state.progress_samples.clear();
+        self.state.clear_survivor_samples();
+        self.transition(Phase::StartTransactions)
+    }
+
+    fn start_transactions(&mut self) -> Result<(), Stop> {
+        let tps_round = self.state.round;
+        let started_at = match self.state.transactions_started_at {
+            Some(started_at) => {
+                // Bench clients do not survive a restart, so resume the load for the rest of the
+                // round
+                info!("Resuming transactions of round {}", tps_round);
+                self.start_bench()?;
+                started_at
+            }
+            None => {
+                let remaining_voters = self.sample_survivors().map_err(|err| {
+                    format!("Unable to determine the validators present: {}", err)
+                })?;
+                self.state.round_start_credits.clear();
+                if self.config.gift_policy.needs_round_credits() {
+                    for (_, vote_account_pubkey) in &remaining_voters {
+                        if let Some(credits) =
+                            voters::fetch_vote_credits(&self.rpc, vote_account_pubkey)
+                        {
+                            self.state
+                                .round_start_credits
+                                .insert(vote_account_pubkey.to_string(), credits);
+                        }
+                    }
+                }
+                let remaining_voters: Vec<_> = remaining_voters
+                    .into_iter()
+                    .map(|(node_pubkey, _)| self.pubkey_to_keybase(&node_pubkey))
+                    .collect();
+                datapoint_info!(
+                    "ramp-tps",
+                    ("event", "start-transactions", String),
+                    ("round", tps_round, i64),
+                    ("validators", remaining_voters.len(), i64)
+                );
+
+                self.notifier.buffer(format!(
+                    "There are {} validators present:",
+                    remaining_voters.len()
+                ));
+                for name in remaining_voters {
+                    self.notifier.buffer(format!("* {}", name));
+                }
+                self.notifier.flush();
+
+                let tx_count = self.state.tx_count;
+                self.notifier.notify(&format!(
+                    "Starting transactions for {} minutes (batch size={})",
+                    self.config.schedule.round_duration(tps_round).as_secs() / 60,
+                    tx_count,
+                ));
+                self.start_bench()?;
+
+                let started_at = utils::unix_timestamp();
+                self.state.transactions_started_at = Some(started_at);
+                self.sample_progress()?;
+                self.save_state()?;
+                started_at
+            }
+        };
+
+        let elapsed = Duration::from_secs(utils::unix_timestamp().saturating_sub(started_at));
+        let transactions_started = Instant::now()
+            .checked_sub(elapsed)
+            .unwrap_or_else(Instant::now);
+        let round_duration = self.config.schedule.round_duration(tps_round);
+        let mut round_end = transactions_started
+            + round_duration
+            + Duration::from_secs(self.state.round_extension_secs);
+        // Survivors are sampled at evenly spaced intervals, in addition to the samples taken when
+        // transactions start and stop
+        let sample_interval = round_duration / (self.config.survivor_samples + 1);
+        let mut next_sample = transactions_started + sample_interval * self.state.survivor_samples;
+        let mut next_progress = Instant::now() + BENCH_PROGRESS_INTERVAL;
+        loop {
+            let extension = self.control.take_extension();
+            if extension > Duration::from_secs(0) {
+                round_end += extension;
+                self.state.round_extension_secs += extension.as_secs();
+                self.save_state()?;
+                self.notifier.notify(&format!(
+                    "Round {} was extended by {} minutes",
+                    tps_round,
+                    extension.as_secs() / 60
+                ));
+            }
+            let now = Instant::now();
+            if now >= round_end {
+                break;
+            }
+            if now >= next_sample {
+                next_sample += sample_interval;
+                match self.sample_survivors() {
+                    Ok(survivors) => {
+                        datapoint_info!(
+                            "ramp-tps",
+                            ("event", "survivor-sample", String),
+                            ("round", tps_round, i64),
+                            ("validators", survivors.len(), i64)
+                        );
+                        info!("{} validators are healthy", survivors.len());
+                        self.save_state()?;
+                    }
+                    Err(err) => warn!("Failed to sample survivors: {}", err),
+                }
+                continue;
+            }
+            if now < next_progress {
+                // Keep the bench counters of the status endpoint current
+                self.status.publish(self.ramp_status());
+                // Wake up regularly to act on operator requests
+                self.shutdown.sleep(
+                    (round_end - now)
+                        .min(next_sample - now)
+                        .min(next_progress - now)
+                        .min(CONTROL_POLL_INTERVAL),
+                )?;
+                continue;
+            }
+            next_progress = now + BENCH_PROGRESS_INTERVAL;
+            if let Some(bench) = &self.bench {
+                let counters = bench.counters();
+                info!(
+                    "Bench progress: {} transactions submitted, ~{} confirmed, {} failed",
+                    counters.submitted(),
+                    counters.confirmed(),
+                    counters.failed()
+                );
+            }
+            let previous_sample = self.state.progress_samples.last().cloned();
+            if let Some(sample) = self.sample_progress()? {
+                let interval_tps = previous_sample
+                    .and_then(|previous_sample| {
+                        throughput::measure(
+                            &[previous_sample, sample.clone()],
+                            self.target_slot_duration(),
+                        )
+                    })
+                    .map_or(0.0, |throughput| throughput.achieved_tps);
+                datapoint_info!(
+                    "ramp-tps",
+                    ("event", "progress-sample", String),
+                    ("round", tps_round, i64),
+                    ("slot", sample.slot, i64),
+                    ("transaction_count", sample.transaction_count, i64),
+                    ("tps", interval_tps, f64)
+                );
+            }
+        }
+        self.transition(Phase::StopTransactions)
+    }
+
+    fn stop_transactions(&mut self) -> Result<(), Stop> {
+        let tps_round = self.state.round;
+        // Measured before the bench clients are stopped, while the latest slots are under load
+        let skip_rate_percent = self.sample_skip_rate();
+        if let Some(bench) = self.bench.take() {
+            let counters = bench.stop(&self.rpc);
+            datapoint_info!(
+                "ramp-tps",
+                ("event", "bench-stopped", String),
+                ("round", tps_round, i64),
+                ("submitted", counters.submitted(), i64),
+                ("confirmed", counters.confirmed(), i64),
+                ("failed", counters.failed(), i64)
+            );
+            info!(
+                "Bench stopped: {} transactions submitted, ~{} confirmed, {} failed",
+                counters.submitted(),
+                counters.confirmed(),
+                counters.failed()
+            );
+        }
+        let ended_at = utils::unix_timestamp();
+        self.sample_progress()?;
+
+        let survivors = self
+            .sample_survivors()
+            .map_err(|err| format!("Unable to determine the surviving validators: {}", err))?;
+        let candidates: Vec<_> = survivors
+            .into_iter()
+            .filter(|(_, vote_pubkey)| self.state.healthy_in_every_sample(&vote_pubkey.to_string()))
+            .map(|(node_pubkey, vote_pubkey)| GiftCandidate {
+                name: self.pubkey_to_keybase(&node_pubkey),
+                identity: node_pubkey,
+                round_credits: self.round_credits(&vote_pubkey),
+                vote_pubkey,
+            })
+            .collect();
+
+        datapoint_info!(
+            "ramp-tps",
+            ("event", "stop-transactions", String),
+            ("round", tps_round, i64),
+            ("validators", candidates.len(), i64)
+        );
+
+        if candidates.is_empty() {
+            return Err(Stop::Complete(
+                "Transactions stopped. No validators remain".to_string(),
+            ));
+        }
+        self.notifier.notify(&format!(
+            "Transactions stopped. There are {} validators remaining",
+            candidates.len()
+        ));
+
+        let samples = &self.state.progress_samples;
+        let throughput = throughput::measure(samples, self.target_slot_duration());
+        match &throughput {
+            Some(throughput) => {
+                datapoint_info!(
+                    "ramp-tps",
+                    ("event", "round-throughput", String),
+                    ("round", tps_round, i64),
+                    ("tx_count", self.state.tx_count, i64),
+                    ("achieved_tps", throughput.achieved_tps, f64),
+                    ("peak_tps", throughput.peak_tps, f64),
+                    ("slot_rate", throughput.slot_rate, f64),
+                    ("slot_rate_shortfall", throughput.slot_rate_shortfall, f64)
+                );
+                self.notifier.notify(&format!(
+                    "Round {} achieved {:.0} TPS (peak {:.0} TPS) at {:.2} slots per second, \
+                     {:.1}% below the target slot rate",
+                    tps_round,
+                    throughput.achieved_tps,
+                    throughput.peak_tps,
+                    throughput.slot_rate,
+                    throughput.slot_rate_shortfall * 100.0
+                ));
+            }
+            None => warn!("Not enough progress samples to measure round {}", tps_round),
+        }
+        if let Some(skip_rate_percent) = skip_rate_percent {
+            datapoint_info!(
+                "ramp-tps",
+                ("event", "round-skip-rate", String),
+                ("round", tps_round, i64),
+                ("skip_rate_percent", skip_rate_percent, f64)
+            );
+            info!(
+                "{:.1}% of the last {} slots of round {} were skipped",
+                skip_rate_percent, MAX_SKIP_RATE_SLOTS, tps_round
+            );
+        }
+        let record = RoundRecord {
+            round: tps_round,
+            started_at: self.state.transactions_started_at,
+            ended_at: Some(ended_at),
+            start_slot: samples.first().map(|sample| sample.slot),
+            end_slot: samples.last().map(|sample| sample.slot),
+            tx_count: Some(self.state.tx_count),
+            measured_tps: throughput
+                .as_ref()
+                .map(|throughput| throughput.achieved_tps),
+            peak_tps: throughput.as_ref().map(|throughput| throughput.peak_tps),
+            slot_rate: throughput.as_ref().map(|throughput| throughput.slot_rate),
+            slot_rate_shortfall: throughput
+                .as_ref()
+                .map(|throughput| throughput.slot_rate_shortfall),
+            skip_rate_percent,
+            survivors: candidates
+                .iter()
+                .map(|candidate| {
+                    self.validator_record(
+                        candidate.identity.to_string(),
+                        Some(candidate.vote_pubkey.to_string()),
+                    )
+                })
+                .collect(),
+            dropped: self.state.drop_outs.values().cloned().collect(),
+            gifts: vec![],
+        };
+        if self.config.dry_run {
+            info!("Dry run, not recording the results of round {}", tps_round);
+        } else {
+            self.results.record(record).unwrap_or_else(|err| {
+                warn!("Failed to record round results: {}", err);
+            });
+        }
+
+        let gifts = self.config.gift_policy.plan(tps_round, &candidates);
+        self.state.pending_gift = Some(PendingGift {
+            recipients: candidates
+                .into_iter()
+                .zip(gifts)
+                .map(|(candidate, lamports)| GiftRecipient {
+                    name: candidate.name,
+                    identity: candidate.identity.to_string(),
+                    vote_pubkey: candidate.vote_pubkey.to_string(),
+                    lamports,
+                })
+                .collect(),
+        });
+        self.transition(Phase::Cooldown)
+    }
+
+    /// Share of the latest slots which no vote account voted on, in percent. `None` if the vote
+    /// towers are unavailable
+    fn sample_skip_rate(&self) -> Option<f64> {
+        match voters::fetch_voters(&self.rpc) {
+            Ok(voters) => HealthSample::from_voters(&voters).skip_rate_percent(MAX_SKIP_RATE_SLOTS),
+            Err(err) => {
+                warn!("Unable to measure the skip rate: {}", err);
+                None
+            }
+        }
+    }
+
+    /// Returns the validators which are healthy right now and records them as a survivor sample,
+    /// which is saved with the next state change
+    fn sample_survivors(&mut self) -> Result<Vec<(Pubkey, Pubkey)>, String> {
+        let sample = voters::fetch_voters(&self.rpc)?;
+        let survivors = self.config.survivor_criteria.survivors(&sample);
+        let healthy: HashSet<String> = survivors
+            .iter()
+            .map(|(_, vote_pubkey)| vote_pubkey.to_string())
+            .collect();
+
+        let new_drop_outs: Vec<String> = self
+            .state
+            .healthy_samples
+            .keys()
+            .filter(|vote_pubkey| {
+                !healthy.contains(*vote_pubkey) && !self.state.drop_outs.contains_key(*vote_pubkey)
+            })
+            .cloned()
+            .collect();
+        if !new_drop_outs.is_empty() {
+            let drop_outs = self.diagnose_drop_outs(&sample, &new_drop_outs);
+            for (vote_pubkey, drop_out) in new_drop_outs.into_iter().zip(drop_outs) {
+                info!(
+                    "{} dropped out: {}",
+                    drop_out
+                        .validator
+                        .keybase
+                        .as_ref()
+                        .unwrap_or(&drop_out.validator.identity),
+                    drop_out.reason
+                );
+                self.state.drop_outs.insert(vote_pubkey, drop_out);
+            }
+        }
+
+        self.state.record_survivor_sample(healthy);
+        Ok(survivors)
+    }
+
+    fn diagnose_drop_outs(&self, sample: &VoterSample, vote_pubkeys: &[String]) -> Vec<DropOut> {
+        let cluster_nodes = self
+            .rpc
+            .call(CallCategory::Query, "get_cluster_nodes", |rpc_client| {
+                rpc_client.get_cluster_nodes()
+            })
+            .unwrap_or_else(|err| {
+                warn!("{}", err);
+                vec![]
+            });
+        let secs_into_round = self.state.transactions_started_at.map_or(0, |started_at| {
+            utils::unix_timestamp().saturating_sub(started_at)
+        });
+
+        vote_pubkeys
+            .iter()
+            .map(|vote_pubkey| {
+                let status = Pubkey::from_str(vote_pubkey)
+                    .ok()
+                    .and_then(|vote_pubkey| sample.status(&vote_pubkey));
+                let node = status.and_then(|status| {
+                    let node_pubkey = status.node_pubkey.to_string();
+                    cluster_nodes.iter().find(|node| node.id == node_pubkey)
+                });
+                DropOut {
+                    validator: self.validator_record(
+                        status.map_or_else(
+                            || "unknown".to_string(),
+                            |status| status.node_pubkey.to_string(),
+                        ),
+                        Some(vote_pubkey.clone()),
+                    ),
+                    reason: match status {
+                        Some(status) => self
+                            .config
+                            .survivor_criteria
+                            .unhealthy_reason(status, sample.slot)
+                            .unwrap_or_default(),
+                        None => "vote account not found".to_string(),
+                    },
+                    secs_into_round,
+                    slot: sample.slot,
+                    last_vote: status.map(|status| status.last_vote),
+                    root_slot: status.and_then(|status| status.root_slot),
+                    activated_stake: status.map(|status| status.activated_stake),
+                    in_gossip: node.is_some(),
+                    version: node
+                        .and_then(|node| node.rpc)
+                        .and_then(voters::fetch_node_version),
+                }
+            })
+            .collect()
+    }
+
+    /// Vote credits that `vote_pubkey` earned since the bench clients were started
+    fn round_credits(&self, vote_pubkey: &Pubkey) -> u64 {
+        if !self.config.gift_policy.needs_round_credits() {
+            return 0;
+        }
+        let start_credits = self.state.round_start_credits.get(&vote_pubkey.to_string());
+        match (
+            start_credits,
+            voters::fetch_vote_credits(&self.rpc, vote_pubkey),
+        ) {
+            (Some(start_credits), Some(credits)) => credits.saturating_sub(*start_credits),
+            _ => {
+                warn!("Unable to determine the round credits of {}", vote_pubkey);
+                0
+            }
+        }
+    }
+
+    fn cooldown(&mut self) -> Result<(), Stop> {
+        datapoint_info!(
+            "ramp-tps",
+            ("event", "cooldown", String),
+            ("round", self.state.round, i64)
+        );
+
+        // Idle before awarding stake to let the cluster come back together before issuing RPC
+        // calls.
+        // This should not be necessary once https://github.com/solana-labs/solana/pull/6538 lands
+        let cooldown_secs = self.config.cooldown.as_secs();
+        if cooldown_secs % 60 == 0 {
+            self.notifier
+                .notify(&format!("{} minute cool down", cooldown_secs / 60));
+        } else {
+            self.notifier
+                .notify(&format!("{} second cool down", cooldown_secs));
+        }
+        loop {
+            let remaining = self
+                .config
+                .cooldown
+                .checked_sub(self.state.phase_elapsed())
+                .unwrap_or_default();
+            if remaining == Duration::from_secs(0) {
+                break;
+            }
+            if self.control.take_skip_cooldown() {
+                self.notifier.notify("Skipping the rest of the cool down");
+                break;
+            }
+            self.shutdown.sleep(remaining.min(CONTROL_POLL_INTERVAL))?;
+        }
+        self.transition(Phase::Gifting)
+    }
+
+    fn gifting(&mut self) -> Result<(), Stop> {
+        datapoint_info!(
+            "ramp-tps",
+            ("event", "gifting", String),
+            ("round", self.state.round, i64)
+        );
+
+        if !self.config.dry_run && !self.config.gift_dry_run {
+            self.retry_failed_gifts()?;
+        }
+
+        let tps_round = self.state.round;
+        if let Some(mut pending_gift) = self.state.pending_gift.take() {
+            if !self.config.gift_dry_run {
+                self.check_gift_budget(&mut pending_gift)?;
+            }
+            self.announce_gifts(&pending_gift);
+            if self.config.dry_run {
+                self.check_dry_run_gifts(&pending_gift);
+            } else if self.config.gift_dry_run {
+                self.notifier
+                    .notify("Gift dry run, no stake was delegated this round");
+            } else {
+                self.deliver_gifts(tps_round, &pending_gift.recipients);
+                // Gifts which were not sent yet are still owed by the saved state
+                self.shutdown.check()?;
+                self.record_gifts(tps_round, &pending_gift.recipients);
+                let failed_gifts = self.failed_gifts(tps_round, pending_gift.recipients);
+                self.state.failed_gifts.extend(failed_gifts);
+            }
+        } else {
+            warn!("No stake gift pending for round {}", tps_round);
+        }
+
+        let activation_epoch = self.current_epoch()?;
+        self.state.next_round(activation_epoch);
+        self.save_state()?;
+        if self.control.abort_requested() {
+            return Err(Stop::Complete(format!(
+                "Stopped after round {} at the request of an operator",
+                tps_round
+            )));
+        }
+        Ok(())
+    }
+
+    /// Retry the gifts of earlier rounds which failed. Those which fail again are kept for the
+    /// next gifting phase
+    fn retry_failed_gifts(&mut self) -> Result<(), Stop> {
+        let mut recipients_by_round: BTreeMap<u32, Vec<GiftRecipient>> = BTreeMap::new();
+        for failed_gift in &self.state.failed_gifts {
+            recipients_by_round
+                .entry(failed_gift.round)
+                .or_default()
+                .push(failed_gift.recipient.clone());
+        }
+        let mut failed_gifts = vec![];
+        for (round, recipients) in recipients_by_round {
+            self.notifier.notify(&format!(
+                "Retrying {} failed stake gifts of round {}",
+                recipients.len(),
+                round
+            ));
+            self.deliver_gifts(round, &recipients);
+            // The saved state still lists every failed gift, and the delivered ones are skipped
+            self.shutdown.check()?;
+            self.record_gifts(round, &recipients);
+            failed_gifts.extend(self.failed_gifts(round, recipients));
+        }
+        self.state.failed_gifts = failed_gifts;
+        Ok(())
+    }
+
+    /// Returns the gifts of `round` to `recipients` which are not confirmed in the ledger
+    fn failed_gifts(&self, round: u32, recipients: Vec<GiftRecipient>) -> Vec<FailedGift> {
+        recipients
+            .into_iter()
+            .filter(|recipient| !self.gift_ledger.is_confirmed(round, &recipient.vote_pubkey))
+            .map(|recipient| FailedGift { round, recipient })
+            .collect()
+    }
+
+    /// Compares the gifts that are still to be sent with the mint balance, and applies the budget
+    /// policy if the mint falls short
+    fn check_gift_budget(&mut self, pending_gift: &mut PendingGift) -> Result<(), Stop> {
+        let tps_round = self.state.round;
+        let (balance, lamports_per_signature) = match self
+            .rpc
+            .call(CallCategory::Query, "get_balance", |rpc_client| {
+                rpc_client.get_balance(&self.mint_keypair.pubkey())
+            })
+            .and_then(|balance| {
+                let (_blockhash, fee_calculator) =
+                    self.rpc
+                        .call(CallCategory::Query, "get_recent_blockhash", |rpc_client| {
+                            rpc_client.get_recent_blockhash()
+                        })?;
+                Ok((balance, fee_calculator.lamports_per_signature))
+            }) {
+            Ok(budget) => budget,
+            Err(err) => {
+                warn!("Unable to check the mint balance before gifting: {}", err);
+                return Ok(());
+            }
+        };
+        let gift_ledger = &self.gift_ledger;
+        let mut unsent: Vec<_> = pending_gift
+            .recipients
+            .iter_mut()
+            .filter(|recipient| !gift_ledger.is_confirmed(tps_round, &recipient.vote_pubkey))
+            .collect();
+        let gifts: Vec<_> = unsent.iter().map(|recipient| recipient.lamports).collect();
+        let required_lamports = budget::required_lamports(&gifts, lamports_per_signature);
+        if required_lamports <= balance {
+            return Ok(());
+        }
+
+        let message = format!(
+            "The mint balance of {} SOL does not cover the {} SOL needed for the stake gifts of \
+             round {}",
+            lamports_to_sol(balance),
+            lamports_to_sol(required_lamports),
+            tps_round
+        );
+        match self.config.gift_budget_policy {
+            BudgetPolicy::Warn => {
+                self.notifier.notify(&format!("Warning: {}", message));
+                Ok(())
+            }
+            // The saved state still owes the gifts, so they are sent once the mint is topped up
+            // and ramp-tps is restarted
+            BudgetPolicy::Bail => Err(Stop::Failed(message)),
+            BudgetPolicy::Cap => {
+                let mut capped = gifts;
+                budget::cap_gifts(&mut capped, balance, lamports_per_signature);
+                for (recipient, lamports) in unsent.iter_mut().zip(capped) {
+                    recipient.lamports = lamports;
+                }
+                // A stake account can not be created without lamports
+                pending_gift.recipients.retain(|recipient| {
+                    recipient.lamports > 0
+                        || gift_ledger.is_confirmed(tps_round, &recipient.vote_pubkey)
+                });
+                self.notifier
+                    .notify(&format!("{}, the gifts were scaled down to fit", message));
+                // Resume with the capped gifts if ramp-tps is restarted while gifting, they are
+                // cleared by the transition to the next round
+                self.state.pending_gift = Some(pending_gift.clone());
+                self.save_state()
+            }
+        }
+    }
+
+    /// Warn once the stake that would have been gifted so far exceeds the mint balance
+    fn check_dry_run_gifts(&mut self, pending_gift: &PendingGift) {
+        let gifts: Vec<_> = pending_gift
+            .recipients
+            .iter()
+            .map(|recipient| recipient.lamports)
+            .collect();
+        let lamports = budget::required_lamports(
+            &gifts,
+            self.genesis_block.fee_calculator.lamports_per_signature,
+        );
+        self.dry_run_gifted += lamports;
+        self.notifier.notify(&format!(
+            "Dry run, {} SOL would have been spent on gifts this round and {} SOL so far",
+            lamports_to_sol(lamports),
+            lamports_to_sol(self.dry_run_gifted)
+        ));
+        match self
+            .rpc
+            .call(CallCategory::Query, "get_balance", |rpc_client| {
+                rpc_client.get_balance(&self.mint_keypair.pubkey())
+            }) {
+            Ok(balance) if balance < self.dry_run_gifted => self.notifier.notify(&format!(
+                "Dry run, the mint balance of {} SOL does not cover the gifts so far",
+                lamports_to_sol(balance)
+            )),
+            Ok(_) => {}
+            Err(err) => warn!("Unable to fetch the mint balance: {}", err),
+        }
+    }
+
+    fn record_gifts(&mut self, tps_round: u32, recipients: &[GiftRecipient]) {
+        let gifts = recipients
+            .iter()
+            .map(|recipient| GiftRecord {
+                validator: self.validator_record(
+                    recipient.identity.clone(),
+                    Some(recipient.vote_pubkey.clone()),
+                ),
+                lamports: recipient.lamports,
+                status: self
+                    .gift_ledger
+                    .entry(tps_round, &recipient.vote_pubkey)
+                    .map_or(GiftStatus::Failed, |entry| entry.status),
+            })
+            .collect();
+        self.results
+            .record_gifts(tps_round, gifts)
+            .unwrap_or_else(|err| {
+                warn!("Failed to record round gifts: {}", err);
+            });
+    }
+
+    /// Print the planned distribution before anything is signed
+    fn announce_gifts(&mut self, pending_gift: &PendingGift) {
+        let total_lamports: u64 = pending_gift
+            .recipients
+            .iter()
+            .map(|recipient| recipient.lamports)
+            .sum();
+        self.notifier.buffer(format!(
+            "Planned stake gifts for round {}, {} SOL in total:",
+            self.state.round,
+            lamports_to_sol(total_lamports)
+        ));
+        for recipient in &pending_gift.recipients {
+            let status = if self
+                .gift_ledger
+                .is_confirmed(self.state.round, &recipient.vote_pubkey)
+            {
+                " (delivered)"
+            } else {
+                ""
+            };
+            self.notifier.buffer(format!(
+                "* {} SOL to {}{}",
+                lamports_to_sol(recipient.lamports),
+                recipient.name,
+                status
+            ));
+        }
+        self.notifier.flush();
+    }
+
+    fn deliver_gifts(&mut self, tps_round: u32, recipients: &[GiftRecipient]) {
+        let mut jobs = vec![];
+        let mut failed = 0;
+        for recipient in recipients {
+            if self
+                .gift_ledger
+                .is_confirmed(tps_round, &recipient.vote_pubkey)
+            {
+                info!(
+                    "Already delegated {} SOL to {}",
+                    lamports_to_sol(recipient.lamports),
+                    recipient.name
+                );
+                continue;
+            }
+            match self.prepare_gift(tps_round, recipient) {
+                Ok(job) => jobs.push(job),
+                Err(err) => {
+                    failed += 1;
+                    self.notifier.buffer(format!(
+                        "Failed to delegate {} SOL to {}: {}",
+                        lamports_to_sol(recipient.lamports),
+                        recipient.name,
+                        err
+                    ));
+                }
+            }
+        }
+
+        let mut delivered = 0;
+        let mut delivered_lamports = 0;
+        let gift_ledger = &mut self.gift_ledger;
+        let notifier = &mut self.notifier;
+        gifting::deliver(
+            &self.rpc,
+            &self.mint_keypair,
+            jobs,
+            self.config.gift_workers,
+            &self.shutdown,
+            |outcome| {
+                let recipient = &outcome.recipient;
+                let sol_gift = lamports_to_sol(recipient.lamports);
+                let mut entry = gift_ledger
+                    .entry(tps_round, &recipient.vote_pubkey)
+                    .cloned()
+                    .expect("gift was recorded in the ledger before it was sent");
+                entry.attempts += outcome.attempts;
+                let message = match outcome.result {
+                    Ok(signature) => {
+                        delivered += 1;
+                        delivered_lamports += recipient.lamports;
+                        entry.signature = signature.or(entry.signature);
+                        entry.status = GiftStatus::Confirmed;
+                        entry.error = None;
+                        format!("Delegated {} SOL to {}", sol_gift, recipient.name)
+                    }
+                    Err(err) => {
+                        failed += 1;
+                        entry.status = GiftStatus::Failed;
+                        let message = format!(
+                            "Failed to delegate {} SOL to {}: {}",
+                            sol_gift, recipient.name, err
+                        );
+                        entry.error = Some(err);
+                        message
+                    }
+                };
+                if let Err(err) = gift_ledger.record(entry) {
+                    warn!("Failed to record gift to {}: {}", recipient.name, err);
+                }
+                notifier.buffer(message);
+            },
+        );
+
+        datapoint_info!(
+            "ramp-tps",
+            ("event", "gifting-summary", String),
+            ("round", tps_round, i64),
+            ("delivered", delivered, i64),
+            ("failed", failed, i64),
+            ("lamports", delivered_lamports, i64)
+        );
+        self.notifier.buffer(format!(
+            "Round {} gifts: {} delivered ({} SOL), {} failed{}",
+            tps_round,
+            delivered,
+            lamports_to_sol(delivered_lamports),
+            failed,
+            if failed > 0 {
+                ", the failed gifts are retried at the next gifting phase"
+            } else {
+                ""
+            }
+        ));
+        self.notifier.flush();
+    }
+
+    /// Save the stake account keypair of a gift and mark the gift as pending in the ledger,
+    /// before anything is signed
+    fn prepare_gift(
+        &mut self,
+        tps_round: u32,
+        recipient: &GiftRecipient,
+    ) -> Result<GiftJob, String> {
+        let vote_account_pubkey = Pubkey::from_str(&recipient.vote_pubkey)
+            .map_err(|err| format!("invalid vote account pubkey: {:?}", err))?;
+        let stake_account_keypair = self
+            .gift_ledger
+            .stake_keypair(tps_round, &recipient.vote_pubkey)?;
+        let previous_entry = self.gift_ledger.entry(tps_round, &recipient.vote_pubkey);
+        let entry = GiftLedgerEntry {
+            round: tps_round,
+            identity: recipient.identity.clone(),
+            vote_pubkey: recipient.vote_pubkey.clone(),
+            stake_pubkey: stake_account_keypair.pubkey().to_string(),
+            lamports: recipient.lamports,
+            signature: previous_entry.and_then(|entry| entry.signature.clone()),
+            status: GiftStatus::Pending,
+            error: None,
+            attempts: previous_entry.map_or(0, |entry| entry.attempts),
+            updated_at: 0,
+        };
+        self.gift_ledger.record(entry)?;
+        Ok(GiftJob {
+            recipient: recipient.clone(),
+            vote_account_pubkey,
+            stake_account_keypair,
+        })
+    }
+
+    fn start_bench(&mut self) -> Result<(), Stop> {
+        if self.config.dry_run {
+            let bench = &self.config.bench;
+            info!(
+                "Dry run, not starting {} bench clients with {} threads each, funded with {} SOL \
+                 per thread, for a tx-count of {}",
+                bench.num_clients,
+                bench.threads_per_client,
+                lamports_to_sol(bench.lamports_per_thread),
+                self.state.tx_count
+            );
+            return Ok(());
+        }
+        match Bench::start(
+            &self.rpc,
+            &self.mint_keypair,
+            &self.config.bench,
+            self.state.tx_count,
+        ) {
+            Ok(bench) => {
+                self.bench = Some(bench);
+                Ok(())
+            }
+            Err(err) => Err(Stop::Failed(format!(
+                "Failed to start bench clients: {}",
+                err
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        mock_cluster::{MockCluster, MockValidator},
+        notifier::NotifierConfig,
+    };
+    use solana_stake_api::config::id as stake_config_id;
+    use std::{fs, path::Path};
+
+    /// A ramp against `cluster` which keeps its files in `dir`
+    fn mock_ramp(cluster: &MockCluster, dir: &Path) -> Ramp {
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let stake_config = StakeConfig::from(
+            &cluster
+                .rpc_client()
+                .get_account(&stake_config_id())
+                .unwrap(),
+        )
+        .unwrap();
+        Ramp {
+            config: RampConfig {
+                bench: BenchConfig {
+                    num_clients: 0,
+                    threads_per_client: 0,
+                    target_tps: None,
+                    thread_batch_sleep: Duration::from_millis(0),
+                    lamports_per_thread: 0,
+                },
+                schedule: Schedule::linear(1000, 1000, 1),
+                gift_policy: GiftPolicy::Fixed { sol: 1.0 },
+                gift_dry_run: false,
+                gift_workers: 2,
+                gift_budget_policy: BudgetPolicy::Warn,
+                survivor_criteria: SurvivorCriteria {
+                    max_vote_lag: 20,
+                    max_root_lag: 60,
+                    min_activated_stake: 1,
+                },
+                survivor_samples: 1,
+                cooldown: Duration::from_millis(0),
+                slot_advance_check: Duration::from_millis(50),
+                slot_stall_timeout: Duration::from_secs(10),
+                health_gate: Some(HealthGate {
+                    window: Duration::from_millis(100),
+                    require_root_advance: true,
+                    min_voting_stake_percent: 67.0,
+                    skip_rate_slots: 10,
+                    max_skip_rate_percent: 20.0,
+                    min_gossip_nodes: 2,
+                    max_rpc_latency: Some(Duration::from_secs(1)),
+                    retry_interval: Duration::from_millis(100),
+                }),
+                dry_run: false,
+            },
+            rpc: Arc::new(cluster.rpc_pool()),
+            genesis_block: cluster.genesis_block.clone(),
+            stake_config,
+            mint_keypair: Arc::new(Keypair::new()),
+            notifier: Notifications::new(NotifierConfig::default()),
+            results: Results::new(path("results.yml"), vec![], 1),
+            pubkey_map: HashMap::new(),
+            state: RoundState::new(1, 0),
+            state_file: StateFile::new(path("state.yml")),
+            gift_ledger: GiftLedger::load(path("gift-ledger.yml"), path("gift-keypairs")).unwrap(),
+            bench: None,
+            shutdown: Shutdown::default(),
+            control: Control::default(),
+            status: Status::default(),
+            dry_run_gifted: 0,
+            health_delay: None,
+        }
+    }
+
+    #[test]
+    fn test_abort_while_paused() {
+        let cluster = MockCluster::start(vec![]);
+        let dir = std::env::temp_dir().join("ramp-tps-test-abort-while-paused");
+        let mut ramp = mock_ramp(&cluster, &dir);
+
+        assert!(ramp.control.handle("pause").is_ok());
+        assert!(ramp.control.handle("abort").is_ok());
+        assert_eq!(
+            ramp.run(),
+            Stop::Complete("Stopped while paused at the request of an operator".to_string())
+        );
+        // The paused phase was never run
+        assert_eq!(ramp.state.round, 1);
+        assert_eq!(ramp.state.phase, Phase::NewStakeWarmup);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_round_against_mock_cluster() {
+        let survivor = MockValidator::new(1, 100);
+        let drop_out = MockValidator::new(2, 100);
+        let cluster = MockCluster::start(vec![survivor.clone(), drop_out.clone()]);
+        let dir = std::env::temp_dir().join("ramp-tps-test-round-against-mock-cluster");
+        let mut ramp = mock_ramp(&cluster, &dir);
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        // The stake history has an entry for the previous epoch from epoch 2 on
+        cluster.wait_for_slot(70);
+        ramp.new_stake_warmup().unwrap();
+        assert_eq!(ramp.state.phase, Phase::RoundStart);
+        ramp.round_start().unwrap();
+        assert_eq!(ramp.state.phase, Phase::StartTransactions);
+
+        // Compress the round: take the samples that start it, then resume it as though its
+        // whole duration had passed once the second validator dropped out
+        assert_eq!(ramp.sample_survivors().unwrap().len(), 2);
+        ramp.sample_progress().unwrap();
+        cluster.drop_out(&drop_out.vote_pubkey);
+        cluster.wait_for_slot(cluster.slot() + 30);
+        ramp.state.transactions_started_at = Some(utils::unix_timestamp() - 60);
+        ramp.start_transactions().unwrap();
+        assert_eq!(ramp.state.phase, Phase::StopTransactions);
+        ramp.stop_transactions().unwrap();
+        assert_eq!(ramp.state.phase, Phase::Cooldown);
+        ramp.cooldown().unwrap();
+        assert_eq!(ramp.state.phase, Phase::Gifting);
+        // A gift which failed in an earlier round is retried along with the gifts of this one
+        let drop_out_vote_pubkey = drop_out.vote_pubkey.to_string();
+        ramp.state.failed_gifts.push(FailedGift {
+            round: 0,
+            recipient: GiftRecipient {
+                name: "drop-out".to_string(),
+                identity: drop_out.node_pubkey.to_string(),
+                vote_pubkey: drop_out_vote_pubkey.clone(),
+                lamports: 1,
+            },
+        });
+        ramp.gifting().unwrap();
+        assert_eq!(ramp.state.round, 2);
+        assert!(ramp.state.failed_gifts.is_empty());
+        assert!(ramp.gift_ledger.is_confirmed(0, &drop_out_vote_pubkey));
+
+        // The next round stops at once, with its progress saved
+        ramp.shutdown.request();
+        assert_eq!(ramp.run(), Stop::Interrupted);
+        let saved_state = ramp.state_file.load().unwrap().unwrap();
+        assert_eq!(saved_state.round, 2);
+        assert_eq!(saved_state.phase, Phase::NewStakeWarmup);
+
+        let survivor_vote_pubkey = survivor.vote_pubkey.to_string();
+        assert!(ramp.gift_ledger.is_confirmed(1, &survivor_vote_pubkey));
+        assert_eq!(cluster.transactions_sent(), 2);
+
+        let rounds = Results::read(&path("results.yml")).unwrap();
+        assert_eq!(rounds.len(), 1);
+        let record = &rounds[0];
+        assert_eq!(record.tx_count, Some(1000));
+        assert!(record.measured_tps.is_some());
+        // Every validator of the mock cluster votes on every slot
+        assert_eq!(record.skip_rate_percent, Some(0.0));
+        assert_eq!(
+            record.survivors[0].vote_pubkey,
+            Some(survivor_vote_pubkey.clone())
+        );
+        assert_eq!(record.dropped.len(), 1);
+        assert_eq!(
+            record.dropped[0].validator.vote_pubkey,
+            Some(drop_out.vote_pubkey.to_string())
+        );
+        assert!(record.dropped[0].reason.starts_with("last vote is"));
+        assert!(record.dropped[0].in_gossip);
+        assert_eq!(record.gifts.len(), 1);
+        assert_eq!(record.gifts[0].status, GiftStatus::Confirmed);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
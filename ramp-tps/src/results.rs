@@ -1,101 +1,327 @@
+use crate::{ledger::GiftStatus, utils};
 use log::*;
-use serde::{Serialize, Serializer};
-use solana_sdk::pubkey::Pubkey;
+use serde_derive::{Deserialize, Serialize};
 use std::{
-    collections::{BTreeMap, HashMap},
-    error::Error,
+    collections::HashMap,
     fs::File,
-    io::ErrorKind,
-    process::exit,
+    io::{ErrorKind, Read},
+    path::Path,
     str::FromStr,
 };
 
-const ROUND_KEY_PREFIX: &str = "round-";
+/// Version of the results file schema, bumped whenever a change is not backwards compatible
+pub const RESULTS_VERSION: u32 = 1;
 
-#[derive(Eq, PartialEq, Ord, PartialOrd)]
-struct Round(u32);
+/// Key prefix of the rounds in results files written before the schema was versioned
+const LEGACY_ROUND_KEY_PREFIX: &str = "round-";
 
-impl Serialize for Round {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        serializer.serialize_str(&format!("{}{}", ROUND_KEY_PREFIX, self.0))
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ValidatorRecord {
+    /// Validator identity pubkey
+    pub identity: String,
+    /// Not known for rounds migrated from unversioned results files
+    pub vote_pubkey: Option<String>,
+    pub keybase: Option<String>,
+}
+
+/// Diagnostics of a validator which fell out of a round
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct DropOut {
+    pub validator: ValidatorRecord,
+    /// Why the validator was no longer healthy
+    pub reason: String,
+    /// Seconds since transactions started when the validator was first seen unhealthy
+    pub secs_into_round: u64,
+    /// Current slot when the validator was first seen unhealthy
+    pub slot: u64,
+    pub last_vote: Option<u64>,
+    pub root_slot: Option<u64>,
+    pub activated_stake: Option<u64>,
+    pub in_gossip: bool,
+    /// Software version reported by the validator's RPC port, if it has one
+    pub version: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GiftRecord {
+    pub validator: ValidatorRecord,
+    pub lamports: u64,
+    pub status: GiftStatus,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RoundRecord {
+    pub round: u32,
+    /// Unix timestamps of when transactions were started and stopped
+    pub started_at: Option<u64>,
+    pub ended_at: Option<u64>,
+    pub start_slot: Option<u64>,
+    pub end_slot: Option<u64>,
+    pub tx_count: Option<u64>,
+    /// Transactions processed by the cluster per second, on average over the round
+    pub measured_tps: Option<f64>,
+    /// Highest transactions per second between two consecutive progress samples
+    #[serde(default)]
+    pub peak_tps: Option<f64>,
+    /// Slots per second
+    #[serde(default)]
+    pub slot_rate: Option<f64>,
+    /// Share of the slots expected from the PoH target slot duration that were not reached
+    #[serde(default)]
+    pub slot_rate_shortfall: Option<f64>,
+    /// Share of the latest slots before transactions stopped which no vote account voted on, in
+    /// percent, measured from the vote towers
+    #[serde(default)]
+    pub skip_rate_percent: Option<f64>,
+    pub survivors: Vec<ValidatorRecord>,
+    #[serde(default)]
+    pub dropped: Vec<DropOut>,
+    #[serde(default)]
+    pub gifts: Vec<GiftRecord>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct ResultsFile {
+    version: u32,
+    rounds: Vec<RoundRecord>,
+}
+
+/// Parses a "keybase (identity)" display name, as written by unversioned results files
+fn parse_legacy_name(name: &str) -> ValidatorRecord {
+    let name = name.trim();
+    if name.ends_with(')') {
+        if let Some(open) = name.rfind(" (") {
+            return ValidatorRecord {
+                identity: name[open + 2..name.len() - 1].to_string(),
+                vote_pubkey: None,
+                keybase: Some(name[..open].to_string()),
+            };
+        }
+    }
+    ValidatorRecord {
+        identity: name.to_string(),
+        vote_pubkey: None,
+        keybase: None,
+    }
+}
+
+/// Migrates the rounds of results files written before the schema was versioned, which only
+/// list the survivors of each round as "keybase (identity)" strings
+fn migrate_legacy_rounds(legacy: HashMap<String, Vec<String>>) -> Vec<RoundRecord> {
+    let mut rounds: Vec<_> = legacy
+        .into_iter()
+        .filter_map(|(key, survivors)| {
+            if !key.starts_with(LEGACY_ROUND_KEY_PREFIX) {
+                return None;
+            }
+            let round = u32::from_str(&key[LEGACY_ROUND_KEY_PREFIX.len()..]).ok()?;
+            Some(RoundRecord {
+                round,
+                survivors: survivors
+                    .iter()
+                    .map(|name| parse_legacy_name(name))
+                    .collect(),
+                ..RoundRecord::default()
+            })
+        })
+        .collect();
+    rounds.sort_by_key(|record| record.round);
+    rounds
+}
+
+/// Parses a results file of any schema version, migrating it to the current one
+fn parse_results(contents: &str) -> Result<Vec<RoundRecord>, String> {
+    if contents.trim().is_empty() {
+        return Ok(vec![]);
+    }
+    let value: serde_yaml::Value = serde_yaml::from_str(contents).map_err(|err| err.to_string())?;
+    let version = value
+        .as_mapping()
+        .and_then(|mapping| mapping.get(&serde_yaml::Value::from("version")))
+        .and_then(|version| version.as_u64());
+    match version {
+        Some(version) if version == u64::from(RESULTS_VERSION) => {
+            let results_file: ResultsFile =
+                serde_yaml::from_value(value).map_err(|err| err.to_string())?;
+            Ok(results_file.rounds)
+        }
+        Some(version) => Err(format!("unsupported results version {}", version)),
+        None => {
+            info!("Migrating unversioned results");
+            serde_yaml::from_value(value)
+                .map(migrate_legacy_rounds)
+                .map_err(|err| err.to_string())
+        }
     }
 }
 
 pub struct Results {
     file_path: String,
-    results: BTreeMap<Round, Vec<String>>,
+    rounds: Vec<RoundRecord>,
 }
 
 impl Results {
     /// Keep any result entries which occurred before the starting round.
-    pub fn new(
-        file_path: String,
-        mut previous_results: HashMap<String, Vec<String>>,
-        start_round: u32,
-    ) -> Self {
-        let mut results: BTreeMap<Round, Vec<String>> = BTreeMap::new();
-        previous_results.drain().for_each(|(key, value)| {
-            if key.starts_with(ROUND_KEY_PREFIX) {
-                let round_str = &key[ROUND_KEY_PREFIX.len()..];
-                dbg!(round_str);
-                if let Ok(round) = u32::from_str(round_str) {
-                    if round < start_round {
-                        results.insert(Round(round), value);
-                    }
-                }
-            }
-        });
-
-        Results { file_path, results }
+    pub fn new(file_path: String, previous_rounds: Vec<RoundRecord>, start_round: u32) -> Self {
+        let rounds = previous_rounds
+            .into_iter()
+            .filter(|record| record.round < start_round)
+            .collect();
+        Results { file_path, rounds }
     }
 
-    // Reads the previous results file and if it exists, parses the contents
-    pub fn read(file_path: &str) -> HashMap<String, Vec<String>> {
+    /// Reads the previous results file and if it exists, parses the contents. A file which can
+    /// not be parsed is an error rather than an empty history, so that it is never overwritten
+    pub fn read(file_path: &str) -> Result<Vec<RoundRecord>, String> {
         match File::open(file_path) {
-            Ok(file) => serde_yaml::from_reader(&file)
-                .map_err(|err| {
-                    warn!("Failed to recover previous results: {}", err);
-                })
-                .unwrap_or_default(),
+            Ok(mut file) => {
+                let mut contents = String::new();
+                file.read_to_string(&mut contents)
+                    .map_err(|err| err.to_string())
+                    .and_then(|_| parse_results(&contents))
+                    .map_err(|err| format!("Unable to read --results-file {}: {}", file_path, err))
+            }
             Err(err) => match err.kind() {
                 ErrorKind::NotFound => {
                     // Check that we can write to this file
-                    File::create(file_path).unwrap_or_else(|err| {
-                        eprintln!(
-                            "Error: Unable to create --results-file {}: {}",
-                            file_path, err
-                        );
-                        exit(1);
-                    });
-                    HashMap::new()
-                }
-                err => {
-                    eprintln!(
-                        "Error: Unable to open --results-file {}: {:?}",
-                        file_path, err
-                    );
-                    exit(1);
+                    File::create(file_path).map_err(|err| {
+                        format!("Unable to create --results-file {}: {}", file_path, err)
+                    })?;
+                    Ok(vec![])
                 }
+                err => Err(format!(
+                    "Unable to open --results-file {}: {:?}",
+                    file_path, err
+                )),
             },
         }
     }
 
-    /// Record the remaining validators after each TPS round
-    pub fn record(
-        &mut self,
-        round: u32,
-        validators: &[(String, Pubkey)],
-    ) -> Result<(), Box<dyn Error>> {
-        self.results.insert(
-            Round(round),
-            validators.iter().map(|v| v.0.clone()).collect(),
+    /// Record the outcome of a TPS round, replacing any earlier record of the same round
+    pub fn record(&mut self, record: RoundRecord) -> Result<(), String> {
+        self.rounds
+            .retain(|existing| existing.round != record.round);
+        self.rounds.push(record);
+        self.rounds.sort_by_key(|record| record.round);
+        self.save()
+    }
+
+    /// Record the stake gifted to the survivors of `round`, replacing any earlier record of a
+    /// gift to the same vote account
+    pub fn record_gifts(&mut self, round: u32, gifts: Vec<GiftRecord>) -> Result<(), String> {
+        let record = self
+            .rounds
+            .iter_mut()
+            .find(|record| record.round == round)
+            .ok_or_else(|| format!("round {} has no results", round))?;
+        for gift in gifts {
+            match record
+                .gifts
+                .iter_mut()
+                .find(|existing| existing.validator.vote_pubkey == gift.validator.vote_pubkey)
+            {
+                Some(existing) => *existing = gift,
+                None => record.gifts.push(gift),
+            }
+        }
+        self.save()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        utils::save_yaml(
+            Path::new(&self.file_path),
+            &ResultsFile {
+                version: RESULTS_VERSION,
+                rounds: self.rounds.clone(),
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_migrate_unversioned_results() {
+        let rounds = parse_results(
+            "
+round-2:
+  - alice (AliceIdentity)
+round-1:
+  - alice (AliceIdentity)
+  - CarolIdentity
+",
+        )
+        .unwrap();
+        assert_eq!(rounds.len(), 2);
+        assert_eq!(rounds[0].round, 1);
+        assert_eq!(
+            rounds[0].survivors,
+            vec![
+                ValidatorRecord {
+                    identity: "AliceIdentity".to_string(),
+                    vote_pubkey: None,
+                    keybase: Some("alice".to_string()),
+                },
+                ValidatorRecord {
+                    identity: "CarolIdentity".to_string(),
+                    vote_pubkey: None,
+                    keybase: None,
+                },
+            ]
         );
+        assert_eq!(rounds[1].round, 2);
+        assert_eq!(rounds[1].survivors, rounds[0].survivors[..1].to_vec());
+    }
 
-        let file = File::create(&self.file_path)?;
-        serde_yaml::to_writer(&file, &self.results)?;
-        Ok(())
+    #[test]
+    fn test_results_roundtrip() {
+        let path = std::env::temp_dir().join("ramp-tps-test-results-roundtrip.yml");
+        let file_path = path.to_str().unwrap().to_string();
+        let mut results = Results::new(file_path.clone(), vec![], 1);
+        let record = RoundRecord {
+            round: 1,
+            started_at: Some(100),
+            ended_at: Some(200),
+            tx_count: Some(5000),
+            measured_tps: Some(1234.5),
+            survivors: vec![ValidatorRecord {
+                identity: "identity".to_string(),
+                vote_pubkey: Some("vote".to_string()),
+                keybase: None,
+            }],
+            ..RoundRecord::default()
+        };
+        results.record(record.clone()).unwrap();
+        let gifts = vec![GiftRecord {
+            validator: record.survivors[0].clone(),
+            lamports: 42,
+            status: GiftStatus::Confirmed,
+        }];
+        results.record_gifts(1, gifts.clone()).unwrap();
+        assert!(results.record_gifts(2, vec![]).is_err());
+
+        let rounds = Results::read(&file_path).unwrap();
+        assert_eq!(rounds, vec![RoundRecord { gifts, ..record }]);
+        assert!(Results::new(file_path, rounds, 1).rounds.is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_unsupported_results_version() {
+        assert!(parse_results("version: 999\nrounds: []\n").is_err());
+        assert_eq!(parse_results(""), Ok(vec![]));
+
+        // The file is left alone for a newer ramp-tps to read
+        let path = std::env::temp_dir().join("ramp-tps-test-unsupported-results-version.yml");
+        let file_path = path.to_str().unwrap();
+        std::fs::write(&path, "version: 999\nrounds: []\n").unwrap();
+        assert!(Results::read(file_path).is_err());
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "version: 999\nrounds: []\n"
+        );
+        std::fs::remove_file(&path).unwrap();
     }
 }
@@ -0,0 +1,272 @@
+//! RPC calls with retries and failover between several RPC endpoints
+//!
+//! Calls go to the current endpoint. When a call fails, every other endpoint is health checked
+//! in order and the first one which answers becomes the current endpoint, before the call is
+//! retried according to the retry policy of its category.
+
+use log::*;
+use serde_derive::{Deserialize, Serialize};
+use solana_client::rpc_client::RpcClient;
+use std::{
+    fmt::Display,
+    net::SocketAddr,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread::sleep,
+    time::Duration,
+};
+
+/// Kinds of RPC calls, which are retried differently
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CallCategory {
+    /// Reads of the slot, epoch, balances and accounts
+    Query,
+    /// Sampling every vote account for the survivors of a round
+    Voters,
+    /// Submitting and confirming a transaction
+    Transaction,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first
+    pub attempts: u32,
+    /// Delay after the first failure, doubled after every further failure
+    pub backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl RetryPolicy {
+    /// Delay before retrying after `attempt` failed
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let backoff_ms = self
+            .backoff_ms
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16));
+        Duration::from_millis(backoff_ms.min(self.max_backoff_ms))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.attempts == 0 {
+            return Err("attempts must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicies {
+    pub query: RetryPolicy,
+    pub voters: RetryPolicy,
+    pub transaction: RetryPolicy,
+}
+
+impl Default for RetryPolicies {
+    fn default() -> Self {
+        RetryPolicies {
+            query: RetryPolicy {
+                attempts: 5,
+                backoff_ms: 500,
+                max_backoff_ms: 8000,
+            },
+            voters: RetryPolicy {
+                attempts: 5,
+                backoff_ms: 5000,
+                max_backoff_ms: 5000,
+            },
+            transaction: RetryPolicy {
+                attempts: 3,
+                backoff_ms: 2000,
+                max_backoff_ms: 2000,
+            },
+        }
+    }
+}
+
+impl RetryPolicies {
+    pub fn get(&self, category: CallCategory) -> &RetryPolicy {
+        match category {
+            CallCategory::Query => &self.query,
+            CallCategory::Voters => &self.voters,
+            CallCategory::Transaction => &self.transaction,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        self.query
+            .validate()
+            .map_err(|err| format!("invalid query retry policy: {}", err))?;
+        self.voters
+            .validate()
+            .map_err(|err| format!("invalid voters retry policy: {}", err))?;
+        self.transaction
+            .validate()
+            .map_err(|err| format!("invalid transaction retry policy: {}", err))
+    }
+}
+
+struct Endpoint {
+    addr: SocketAddr,
+    client: RpcClient,
+}
+
+pub struct RpcPool {
+    endpoints: Vec<Endpoint>,
+    current: AtomicUsize,
+    policies: RetryPolicies,
+}
+
+impl RpcPool {
+    /// Calls go to the first of `addrs` until it fails
+    pub fn new(addrs: &[SocketAddr], timeout: Duration, policies: RetryPolicies) -> Self {
+        assert!(!addrs.is_empty(), "no RPC endpoints");
+        RpcPool {
+            endpoints: addrs
+                .iter()
+                .map(|addr| Endpoint {
+                    addr: *addr,
+                    client: RpcClient::new_socket_with_timeout(*addr, timeout),
+                })
+                .collect(),
+            current: AtomicUsize::new(0),
+            policies,
+        }
+    }
+
+    pub fn current_addr(&self) -> SocketAddr {
+        self.endpoints[self.current.load(Ordering::Relaxed)].addr
+    }
+
+    /// Run `f` against the current endpoint, failing over and retrying according to the policy
+    /// of `category`. `name` describes the call in logs and errors
+    pub fn call<T, E, F>(&self, category: CallCategory, name: &str, mut f: F) -> Result<T, String>
+    where
+        E: Display,
+        F: FnMut(&RpcClient) -> Result<T, E>,
+    {
+        let policy = self.policies.get(category);
+        let mut attempt = 1;
+        loop {
+            let index = self.current.load(Ordering::Relaxed);
+            let endpoint = &self.endpoints[index];
+            let err = match f(&endpoint.client) {
+                Ok(value) => return Ok(value),
+                Err(err) => err,
+            };
+            if attempt >= policy.attempts {
+                return Err(format!(
+                    "{} RPC call failed after {} attempts: {}",
+                    name, attempt, err
+                ));
+            }
+            let backoff = policy.backoff(attempt);
+            warn!(
+                "{} RPC call to {} failed (attempt {}), retrying in {:?}: {}",
+                name, endpoint.addr, attempt, backoff, err
+            );
+            self.fail_over(index);
+            sleep(backoff);
+            attempt += 1;
+        }
+    }
+
+    /// Switch from the endpoint at `failed` to the next endpoint which passes a health check.
+    /// Stays on the failed endpoint if no other one is healthy
+    fn fail_over(&self, failed: usize) {
+        if self.current.load(Ordering::Relaxed) != failed {
+            // Another thread already failed over
+            return;
+        }
+        let num_endpoints = self.endpoints.len();
+        for offset in 1..num_endpoints {
+            let index = (failed + offset) % num_endpoints;
+            let endpoint = &self.endpoints[index];
+            match endpoint.client.get_slot() {
+                Ok(_) => {
+                    if self
+                        .current
+                        .compare_exchange(failed, index, Ordering::Relaxed, Ordering::Relaxed)
+                        .is_ok()
+                    {
+                        warn!(
+                            "Failing over from RPC endpoint {} to {}",
+                            self.endpoints[failed].addr, endpoint.addr
+                        );
+                    }
+                    return;
+                }
+                Err(err) => debug!("RPC endpoint {} is unhealthy: {}", endpoint.addr, err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock_cluster::MockCluster;
+    use std::net::TcpListener;
+
+    fn fast_policies() -> RetryPolicies {
+        let policy = RetryPolicy {
+            attempts: 3,
+            backoff_ms: 1,
+            max_backoff_ms: 1,
+        };
+        RetryPolicies {
+            query: policy.clone(),
+            voters: policy.clone(),
+            transaction: policy,
+        }
+    }
+
+    /// An address which refuses connections
+    fn closed_addr() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            attempts: 10,
+            backoff_ms: 500,
+            max_backoff_ms: 3000,
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(500));
+        assert_eq!(policy.backoff(2), Duration::from_millis(1000));
+        assert_eq!(policy.backoff(3), Duration::from_millis(2000));
+        assert_eq!(policy.backoff(4), Duration::from_millis(3000));
+        assert_eq!(policy.backoff(100), Duration::from_millis(3000));
+        assert!(RetryPolicies::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_fail_over() {
+        let cluster = MockCluster::start(vec![]);
+        let rpc = RpcPool::new(
+            &[closed_addr(), cluster.rpc_addr()],
+            Duration::from_secs(10),
+            fast_policies(),
+        );
+        assert!(rpc
+            .call(CallCategory::Query, "get_slot", |rpc_client| rpc_client
+                .get_slot())
+            .is_ok());
+        assert_eq!(rpc.current_addr(), cluster.rpc_addr());
+
+        let mut attempts = 0;
+        let err = rpc
+            .call(CallCategory::Transaction, "send", |_rpc_client| {
+                attempts += 1;
+                Err::<(), _>("rejected")
+            })
+            .unwrap_err();
+        assert_eq!(attempts, 3);
+        assert_eq!(err, "send RPC call failed after 3 attempts: rejected");
+        // The only healthy endpoint is kept
+        assert_eq!(rpc.current_addr(), cluster.rpc_addr());
+    }
+}
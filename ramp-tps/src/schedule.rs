@@ -0,0 +1,256 @@
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs::File, time::Duration};
+
+/// Longest round duration, which keeps the end of a round within the range of `Instant`
+const MAX_ROUND_MINUTES: u64 = 7 * 24 * 60;
+
+/// An explicit round of a `TxCountSchedule::Table`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledRound {
+    pub tx_count: u64,
+    pub round_minutes: Option<u64>,
+}
+
+/// How the transaction count changes from one round to the next
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum TxCountSchedule {
+    /// `baseline + (round - 1) * increment`
+    Linear { baseline: u64, increment: u64 },
+    /// `baseline * factor ^ (round - 1)`
+    Exponential { baseline: u64, factor: f64 },
+    /// Increase by `increment` once every `rounds_per_step` rounds
+    Stepped {
+        baseline: u64,
+        increment: u64,
+        rounds_per_step: u32,
+    },
+    /// One entry per round. Rounds past the end of the table repeat the last entry
+    Table { rounds: Vec<ScheduledRound> },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Schedule {
+    pub tx_count: TxCountSchedule,
+    /// Duration of rounds which are not overridden
+    pub round_minutes: u64,
+    /// Round durations by round number
+    #[serde(default)]
+    pub round_minutes_overrides: BTreeMap<u32, u64>,
+}
+
+impl Schedule {
+    pub fn linear(baseline: u64, increment: u64, round_minutes: u64) -> Self {
+        Schedule {
+            tx_count: TxCountSchedule::Linear {
+                baseline,
+                increment,
+            },
+            round_minutes,
+            round_minutes_overrides: BTreeMap::new(),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let file = File::open(path).map_err(|err| format!("Unable to open {}: {}", path, err))?;
+        let schedule: Schedule = serde_yaml::from_reader(file)
+            .map_err(|err| format!("Unable to parse {}: {}", path, err))?;
+        schedule.validate()?;
+        Ok(schedule)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match &self.tx_count {
+            TxCountSchedule::Exponential { factor, .. } if !factor.is_finite() => {
+                return Err(format!("exponential factor {} is not a number", factor));
+            }
+            TxCountSchedule::Exponential { factor, .. } if *factor < 1.0 => {
+                return Err(format!("exponential factor {} is less than 1", factor));
+            }
+            TxCountSchedule::Stepped {
+                rounds_per_step: 0, ..
+            } => {
+                return Err("rounds_per_step must be at least 1".to_string());
+            }
+            TxCountSchedule::Table { rounds } if rounds.is_empty() => {
+                return Err("table has no rounds".to_string());
+            }
+            _ => {}
+        }
+        let table_minutes: Vec<u64> = match &self.tx_count {
+            TxCountSchedule::Table { rounds } => rounds
+                .iter()
+                .filter_map(|round| round.round_minutes)
+                .collect(),
+            _ => vec![],
+        };
+        let mut round_minutes = table_minutes
+            .iter()
+            .chain(self.round_minutes_overrides.values())
+            .chain(Some(&self.round_minutes));
+        if round_minutes.clone().any(|minutes| *minutes == 0) {
+            return Err("round duration must be at least 1 minute".to_string());
+        }
+        if round_minutes.any(|minutes| *minutes > MAX_ROUND_MINUTES) {
+            return Err(format!(
+                "round duration must be at most {} minutes",
+                MAX_ROUND_MINUTES
+            ));
+        }
+        Ok(())
+    }
+
+    fn table_entry(&self, round: u32) -> Option<&ScheduledRound> {
+        match &self.tx_count {
+            TxCountSchedule::Table { rounds } => {
+                let index = (round.max(1) - 1) as usize;
+                rounds.get(index).or_else(|| rounds.last())
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether `round` is the first one past the end of a table, which repeats its last round
+    pub fn is_first_round_past_table(&self, round: u32) -> bool {
+        match &self.tx_count {
+            TxCountSchedule::Table { rounds } => round as usize == rounds.len() + 1,
+            _ => false,
+        }
+    }
+
+    pub fn tx_count(&self, round: u32) -> u64 {
+        let round_index = round.max(1) - 1;
+        match &self.tx_count {
+            TxCountSchedule::Linear {
+                baseline,
+                increment,
+            } => baseline.saturating_add(u64::from(round_index).saturating_mul(*increment)),
+            TxCountSchedule::Exponential { baseline, factor } => {
+                (*baseline as f64 * factor.powi(round_index as i32)) as u64
+            }
+            TxCountSchedule::Stepped {
+                baseline,
+                increment,
+                rounds_per_step,
+            } => baseline.saturating_add(
+                u64::from(round_index / rounds_per_step).saturating_mul(*increment),
+            ),
+            TxCountSchedule::Table { .. } => {
+                self.table_entry(round).map_or(0, |entry| entry.tx_count)
+            }
+        }
+    }
+
+    pub fn round_duration(&self, round: u32) -> Duration {
+        let round_minutes = self
+            .table_entry(round)
+            .and_then(|entry| entry.round_minutes)
+            .or_else(|| self.round_minutes_overrides.get(&round).cloned())
+            .unwrap_or(self.round_minutes);
+        Duration::from_secs(round_minutes.saturating_mul(60))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_linear_schedule() {
+        let schedule = Schedule::linear(5000, 2000, 60);
+        assert_eq!(schedule.tx_count(1), 5000);
+        assert_eq!(schedule.tx_count(2), 7000);
+        assert_eq!(schedule.tx_count(5), 13000);
+        assert_eq!(schedule.round_duration(5), Duration::from_secs(3600));
+
+        // The transaction count stops growing instead of overflowing
+        let schedule = Schedule::linear(std::u64::MAX - 1, 2, 60);
+        assert_eq!(schedule.tx_count(2), std::u64::MAX);
+        assert_eq!(schedule.tx_count(std::u32::MAX), std::u64::MAX);
+    }
+
+    #[test]
+    fn test_exponential_schedule() {
+        let mut schedule = Schedule::linear(0, 0, 60);
+        schedule.tx_count = TxCountSchedule::Exponential {
+            baseline: 1000,
+            factor: 1.5,
+        };
+        assert_eq!(schedule.tx_count(1), 1000);
+        assert_eq!(schedule.tx_count(2), 1500);
+        assert_eq!(schedule.tx_count(3), 2250);
+    }
+
+    #[test]
+    fn test_stepped_schedule() {
+        let mut schedule = Schedule::linear(0, 0, 60);
+        schedule.tx_count = TxCountSchedule::Stepped {
+            baseline: 1000,
+            increment: 500,
+            rounds_per_step: 2,
+        };
+        assert_eq!(schedule.tx_count(1), 1000);
+        assert_eq!(schedule.tx_count(2), 1000);
+        assert_eq!(schedule.tx_count(3), 1500);
+        assert_eq!(schedule.tx_count(6), 2000);
+
+        schedule.tx_count = TxCountSchedule::Stepped {
+            baseline: 1000,
+            increment: std::u64::MAX,
+            rounds_per_step: 1,
+        };
+        assert_eq!(schedule.tx_count(3), std::u64::MAX);
+    }
+
+    #[test]
+    fn test_table_schedule() {
+        let schedule: Schedule = serde_yaml::from_str(
+            "
+round_minutes: 20
+round_minutes_overrides:
+  2: 30
+tx_count:
+  type: table
+  rounds:
+    - tx_count: 1000
+      round_minutes: 10
+    - tx_count: 5000
+    - tx_count: 9000
+",
+        )
+        .unwrap();
+        assert_eq!(schedule.validate(), Ok(()));
+        assert_eq!(schedule.tx_count(1), 1000);
+        assert_eq!(schedule.tx_count(3), 9000);
+        assert_eq!(schedule.tx_count(4), 9000);
+        assert_eq!(schedule.round_duration(1), Duration::from_secs(600));
+        assert_eq!(schedule.round_duration(2), Duration::from_secs(1800));
+        assert_eq!(schedule.round_duration(3), Duration::from_secs(1200));
+        assert!(!schedule.is_first_round_past_table(3));
+        assert!(schedule.is_first_round_past_table(4));
+        assert!(!schedule.is_first_round_past_table(5));
+        assert!(!Schedule::linear(1000, 1000, 60).is_first_round_past_table(4));
+    }
+
+    #[test]
+    fn test_validate_schedule() {
+        let mut schedule = Schedule::linear(1000, 1000, 0);
+        assert!(schedule.validate().is_err());
+        schedule.round_minutes = 1;
+        assert_eq!(schedule.validate(), Ok(()));
+        schedule
+            .round_minutes_overrides
+            .insert(3, MAX_ROUND_MINUTES + 1);
+        assert!(schedule.validate().is_err());
+        schedule.round_minutes_overrides.clear();
+        schedule.tx_count = TxCountSchedule::Table { rounds: vec![] };
+        assert!(schedule.validate().is_err());
+        for factor in &[0.5, std::f64::NAN, std::f64::INFINITY] {
+            schedule.tx_count = TxCountSchedule::Exponential {
+                baseline: 1000,
+                factor: *factor,
+            };
+            assert!(schedule.validate().is_err(), "{}", factor);
+        }
+    }
+}
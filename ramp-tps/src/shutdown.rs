@@ -0,0 +1,143 @@
+//! Stops ramp-tps cleanly on SIGINT or SIGTERM and maps the reason it stopped to an exit status
+
+use log::*;
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+pub const EXIT_FAILED: i32 = 1;
+/// 128 + SIGINT, as a shell reports a process killed by Ctrl-C
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+/// Longest delay between a shutdown signal and a sleeping thread noticing it
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Why ramp-tps stopped
+#[derive(Clone, Debug, PartialEq)]
+pub enum Stop {
+    /// The TPS ramp is over, because no validators remain or an operator ended it
+    Complete(String),
+    /// A shutdown signal was received
+    Interrupted,
+    /// An error which ramp-tps can not recover from
+    Failed(String),
+}
+
+impl Stop {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Stop::Complete(_) => 0,
+            Stop::Interrupted => EXIT_INTERRUPTED,
+            Stop::Failed(_) => EXIT_FAILED,
+        }
+    }
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Complete(msg) => write!(f, "{}", msg),
+            Stop::Interrupted => write!(f, "Interrupted, restart ramp-tps to resume"),
+            Stop::Failed(msg) => write!(f, "Error: {}", msg),
+        }
+    }
+}
+
+impl From<String> for Stop {
+    fn from(msg: String) -> Self {
+        Stop::Failed(msg)
+    }
+}
+
+/// Shared flag which is set once a shutdown has been requested
+#[derive(Clone, Debug, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    /// Request a shutdown on SIGINT or SIGTERM. A second signal exits immediately
+    pub fn install() -> Result<Self, String> {
+        let shutdown = Shutdown::default();
+        let requested = shutdown.requested.clone();
+        ctrlc::set_handler(move || {
+            if requested.swap(true, Ordering::Relaxed) {
+                eprintln!("Received a second shutdown signal, exiting immediately");
+                std::process::exit(EXIT_INTERRUPTED);
+            }
+            warn!("Shutdown signal received, stopping after the current step");
+        })
+        .map_err(|err| format!("Unable to install the signal handler: {}", err))?;
+        Ok(shutdown)
+    }
+
+    #[cfg(test)]
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::Relaxed)
+    }
+
+    /// Returns `Stop::Interrupted` once a shutdown has been requested
+    pub fn check(&self) -> Result<(), Stop> {
+        if self.is_requested() {
+            Err(Stop::Interrupted)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Sleep for `duration`, waking up early if a shutdown is requested
+    pub fn sleep(&self, duration: Duration) -> Result<(), Stop> {
+        let deadline = Instant::now() + duration;
+        loop {
+            self.check()?;
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(());
+            }
+            sleep((deadline - now).min(POLL_INTERVAL));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_sleep_is_interrupted() {
+        let shutdown = Shutdown::default();
+        assert_eq!(shutdown.sleep(Duration::from_millis(10)), Ok(()));
+
+        let requester = shutdown.clone();
+        let started = Instant::now();
+        let handle = thread::spawn(move || {
+            sleep(Duration::from_millis(50));
+            requester.request();
+        });
+        assert_eq!(
+            shutdown.sleep(Duration::from_secs(60)),
+            Err(Stop::Interrupted)
+        );
+        assert!(started.elapsed() < Duration::from_secs(10));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        assert_eq!(Stop::Complete("done".to_string()).exit_code(), 0);
+        assert_eq!(Stop::from("oops".to_string()).exit_code(), EXIT_FAILED);
+        assert_eq!(Stop::Interrupted.exit_code(), EXIT_INTERRUPTED);
+        assert_eq!(Stop::Failed("oops".to_string()).to_string(), "Error: oops");
+    }
+}
@@ -1,52 +1,182 @@
-use crate::{notifier, utils};
+use crate::{
+    notifier,
+    rpc::{CallCategory, RpcPool},
+    shutdown::{Shutdown, Stop},
+    wait::SlotWaiter,
+};
 use log::*;
-use solana_client::{rpc_client::RpcClient, rpc_request::RpcEpochInfo};
+use solana_client::rpc_request::RpcEpochInfo;
 use solana_sdk::{
-    genesis_block::GenesisBlock,
+    native_token::lamports_to_sol,
     sysvar::stake_history::{self, StakeHistory, StakeHistoryEntry},
 };
-use solana_stake_api::config::Config as StakeConfig;
-use std::{thread::sleep, time::Duration};
+use solana_stake_api::config::{self as stake_config, Config as StakeConfig};
+use std::{fmt, time::Duration};
 
-fn calculate_stake_warmup(mut stake_entry: StakeHistoryEntry, stake_config: &StakeConfig) -> u64 {
-    let mut epochs = 0;
-    loop {
-        let percent_warming_up =
-            stake_entry.activating as f64 / stake_entry.effective.max(1) as f64;
-        let percent_cooling_down =
-            stake_entry.deactivating as f64 / stake_entry.effective.max(1) as f64;
-        debug!(
-            "epoch +{}: stake warming up {:.1}%, cooling down {:.1}% ",
-            epochs,
-            percent_warming_up * 100.,
-            percent_cooling_down * 100.
-        );
+/// Rounds give up waiting for stake which is projected to take longer than this to warm up
+const MAX_WARMUP_EPOCHS: u64 = 50;
+
+/// Stake is considered warmed up once less than 5% of it is still activating or deactivating
+fn is_warmed_up(stake_entry: &StakeHistoryEntry) -> bool {
+    let percent_warming_up = stake_entry.activating as f64 / stake_entry.effective.max(1) as f64;
+    let percent_cooling_down =
+        stake_entry.deactivating as f64 / stake_entry.effective.max(1) as f64;
+    percent_warming_up < 0.05 && percent_cooling_down < 0.05
+}
+
+/// Returns the stake one epoch after `stake_entry`
+fn next_epoch_stake(
+    stake_entry: &StakeHistoryEntry,
+    stake_config: &StakeConfig,
+) -> StakeHistoryEntry {
+    let mut stake_entry = stake_entry.clone();
+    let max_warmup_stake = (stake_entry.effective as f64 * stake_config.warmup_rate) as u64;
+    let warmup_stake = stake_entry.activating.min(max_warmup_stake);
+    stake_entry.effective += warmup_stake;
+    stake_entry.activating -= warmup_stake;
+
+    let max_cooldown_stake = (stake_entry.effective as f64 * stake_config.cooldown_rate) as u64;
+    let cooldown_stake = stake_entry.deactivating.min(max_cooldown_stake);
+    stake_entry.effective -= cooldown_stake;
+    stake_entry.deactivating -= cooldown_stake;
+    stake_entry
+}
+
+/// Projected stake of the cluster, epoch by epoch
+pub struct WarmupPlan {
+    /// Epoch of the first entry
+    pub epoch: u64,
+    /// Stake at the end of each epoch from `epoch` on, up to the first epoch in which it is
+    /// warmed up
+    pub entries: Vec<StakeHistoryEntry>,
+}
 
-        if (percent_warming_up < 0.05) && (percent_cooling_down < 0.05) {
-            break;
+impl WarmupPlan {
+    /// Project the stake of `stake_entry` in `epoch`, with `gift_lamports` of hypothetical stake
+    /// gifts added to the activating stake, for at most `max_epochs` more epochs
+    pub fn new(
+        epoch: u64,
+        mut stake_entry: StakeHistoryEntry,
+        gift_lamports: u64,
+        stake_config: &StakeConfig,
+        max_epochs: u64,
+    ) -> Self {
+        stake_entry.activating = stake_entry.activating.saturating_add(gift_lamports);
+        let mut entries = vec![stake_entry];
+        while (entries.len() as u64) <= max_epochs && !is_warmed_up(entries.last().unwrap()) {
+            let next_stake_entry = next_epoch_stake(entries.last().unwrap(), stake_config);
+            entries.push(next_stake_entry);
         }
-        let max_warmup_stake = (stake_entry.effective as f64 * stake_config.warmup_rate) as u64;
-        let warmup_stake = stake_entry.activating.min(max_warmup_stake);
-        stake_entry.effective += warmup_stake;
-        stake_entry.activating -= warmup_stake;
-
-        let max_cooldown_stake = (stake_entry.effective as f64 * stake_config.cooldown_rate) as u64;
-        let cooldown_stake = stake_entry.deactivating.min(max_cooldown_stake);
-        stake_entry.effective -= cooldown_stake;
-        stake_entry.deactivating -= cooldown_stake;
-        debug!(
-            "epoch +{}: stake warming up {}, cooling down {}",
-            epochs, warmup_stake, cooldown_stake
-        );
+        WarmupPlan { epoch, entries }
+    }
+
+    /// The first epoch in which the stake is warmed up, if it is within the projection
+    pub fn warm_epoch(&self) -> Option<u64> {
+        self.entries
+            .iter()
+            .position(is_warmed_up)
+            .map(|offset| self.epoch + offset as u64)
+    }
+}
+
+impl fmt::Display for WarmupPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>7} {:>16} {:>16} {:>16}",
+            "epoch", "effective SOL", "activating SOL", "deactivating SOL"
+        )?;
+        for (offset, stake_entry) in self.entries.iter().enumerate() {
+            writeln!(
+                f,
+                "{:>7} {:>16.3} {:>16.3} {:>16.3}",
+                self.epoch + offset as u64,
+                lamports_to_sol(stake_entry.effective),
+                lamports_to_sol(stake_entry.activating),
+                lamports_to_sol(stake_entry.deactivating)
+            )?;
+        }
+        match self.warm_epoch() {
+            // Rounds wait for the stake history entry of the previous epoch to be warm
+            Some(warm_epoch) => write!(
+                f,
+                "95% of the stake is warm in epoch {}, the next round can start in epoch {}",
+                warm_epoch,
+                warm_epoch + 1
+            ),
+            None => write!(
+                f,
+                "The stake is still warming up after epoch {}",
+                self.epoch + self.entries.len() as u64 - 1
+            ),
+        }
+    }
+}
 
-        epochs += 1;
+/// Returns how many epochs after `epoch` the stake of `stake_entry` is warmed up, failing if it
+/// is not projected to warm up within `MAX_WARMUP_EPOCHS`
+fn remaining_warmup_epochs(
+    epoch: u64,
+    stake_entry: StakeHistoryEntry,
+    stake_config: &StakeConfig,
+) -> Result<u64, Stop> {
+    let plan = WarmupPlan::new(epoch, stake_entry, 0, stake_config, MAX_WARMUP_EPOCHS);
+    debug!("Projected stake warmup:\n{}", plan);
+    match plan.warm_epoch() {
+        Some(warm_epoch) => {
+            info!("95% stake warmup will take {} epochs", warm_epoch - epoch);
+            Ok(warm_epoch - epoch)
+        }
+        None => Err(Stop::Failed(format!(
+            "The stake is not projected to warm up within {} epochs of epoch {}:\n{}",
+            MAX_WARMUP_EPOCHS, epoch, plan
+        ))),
     }
-    info!("95% stake warmup will take {} epochs", epochs);
-    epochs
 }
 
-fn stake_history_entry(epoch: u64, rpc_client: &RpcClient) -> Option<StakeHistoryEntry> {
-    let stake_history_account = rpc_client.get_account(&stake_history::id()).ok()?;
+/// Project the warmup of the live stake of the cluster, with each of `gifts` (in lamports)
+/// added to the activating stake in turn
+pub fn plan_warmup(
+    rpc: &RpcPool,
+    gifts: &[u64],
+    max_epochs: u64,
+) -> Result<Vec<WarmupPlan>, String> {
+    let stake_config_account = rpc.call(CallCategory::Query, "get_account", |rpc_client| {
+        rpc_client.get_account(&stake_config::id())
+    })?;
+    let stake_config = StakeConfig::from(&stake_config_account)
+        .ok_or_else(|| "Unable to deserialize the stake config".to_string())?;
+    let epoch_info = rpc.call(CallCategory::Query, "get_epoch_info", |rpc_client| {
+        rpc_client.get_epoch_info()
+    })?;
+    // The entry of the current epoch is only recorded once it is finished
+    let epoch = epoch_info
+        .epoch
+        .checked_sub(1)
+        .ok_or_else(|| "There is no stake history in the first epoch".to_string())?;
+    let stake_entry = stake_history_entry(epoch, rpc)
+        .ok_or_else(|| format!("No stake history entry for epoch {}", epoch))?;
+
+    Ok(gifts
+        .iter()
+        .map(|gift_lamports| {
+            WarmupPlan::new(
+                epoch,
+                stake_entry.clone(),
+                *gift_lamports,
+                &stake_config,
+                max_epochs,
+            )
+        })
+        .collect())
+}
+
+fn stake_history_entry(epoch: u64, rpc: &RpcPool) -> Option<StakeHistoryEntry> {
+    let stake_history_account = rpc
+        .call(CallCategory::Query, "get_account", |rpc_client| {
+            rpc_client.get_account(&stake_history::id())
+        })
+        .ok()?;
     let stake_history = StakeHistory::from_account(&stake_history_account)?;
     stake_history.get(&epoch).cloned()
 }
@@ -54,37 +184,29 @@ fn stake_history_entry(epoch: u64, rpc_client: &RpcClient) -> Option<StakeHistor
 pub fn wait_for_activation(
     activation_epoch: u64,
     mut epoch_info: RpcEpochInfo,
-    rpc_client: &RpcClient,
+    rpc: &RpcPool,
     stake_config: &StakeConfig,
-    genesis_block: &GenesisBlock,
-    notifier: &notifier::Notifier,
-) {
-    // Sleep until activation_epoch has finished
+    waiter: &SlotWaiter,
+    notifier: &notifier::Notifications,
+    shutdown: &Shutdown,
+) -> Result<(), Stop> {
+    // Wait until activation_epoch has finished
     let mut current_epoch = epoch_info.epoch;
-    let sleep_epochs = (activation_epoch + 1).saturating_sub(current_epoch);
-    let slots_per_epoch = genesis_block.epoch_schedule.slots_per_epoch;
-    if sleep_epochs > 0 {
-        let sleep_slots = sleep_epochs * slots_per_epoch - epoch_info.slot_index;
+    if current_epoch <= activation_epoch {
         notifier.notify(&format!(
             "Waiting until epoch {} is finished...",
             activation_epoch
         ));
-        utils::sleep_n_slots(sleep_slots, genesis_block);
+        waiter.wait_for_epoch(activation_epoch + 1)?;
     }
 
     loop {
-        epoch_info = rpc_client.get_epoch_info().unwrap_or_else(|err| {
-            utils::bail(
-                notifier,
-                &format!("Error: get_epoch_info RPC call failed: {}", err),
-            );
-        });
-        let slot = rpc_client.get_slot().unwrap_or_else(|err| {
-            utils::bail(
-                notifier,
-                &format!("Error: get_slot RPC call 3 failed: {}", err),
-            );
-        });
+        epoch_info = rpc.call(CallCategory::Query, "get_epoch_info", |rpc_client| {
+            rpc_client.get_epoch_info()
+        })?;
+        let slot = rpc.call(CallCategory::Query, "get_slot", |rpc_client| {
+            rpc_client.get_slot()
+        })?;
         info!("Current slot is {}", slot);
 
         current_epoch = epoch_info.epoch - 1;
@@ -93,36 +215,103 @@ pub fn wait_for_activation(
             current_epoch
         );
 
-        if let Some(stake_entry) = stake_history_entry(current_epoch, &rpc_client) {
+        if let Some(stake_entry) = stake_history_entry(current_epoch, rpc) {
             debug!("Stake history entry: {:?}", &stake_entry);
-            let warm_up_epochs = calculate_stake_warmup(stake_entry, stake_config);
+            let warm_up_epochs = remaining_warmup_epochs(current_epoch, stake_entry, stake_config)?;
             if warm_up_epochs > 0 {
                 notifier.notify(&format!(
                     "Waiting until epoch {} for stake to warmup (current epoch is {})...",
                     current_epoch + warm_up_epochs,
                     current_epoch
                 ));
-                let sleep_slots = epoch_info.slots_in_epoch - epoch_info.slot_index;
-                utils::sleep_n_slots(sleep_slots, genesis_block);
+                waiter.wait_for_epoch(epoch_info.epoch + 1)?;
             } else {
-                break;
+                return Ok(());
             }
         } else {
             warn!(
                 "Failed to fetch stake history entry for epoch: {}",
                 current_epoch
             );
-            sleep(Duration::from_secs(5));
+            shutdown.sleep(Duration::from_secs(5))?;
         }
 
-        let latest_slot = rpc_client.get_slot().unwrap_or_else(|err| {
-            utils::bail(
-                notifier,
-                &format!("Error: get_slot RPC call 3 failed: {}", err),
-            );
-        });
+        let latest_slot = rpc.call(CallCategory::Query, "get_slot", |rpc_client| {
+            rpc_client.get_slot()
+        })?;
         if slot == latest_slot {
-            utils::bail(notifier, &format!("Slot did not advance from {}", slot));
+            return Err(Stop::Failed(format!("Slot did not advance from {}", slot)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock_cluster::{MockCluster, MockValidator, SLOTS_PER_EPOCH};
+    use solana_sdk::native_token::sol_to_lamports;
+
+    #[test]
+    fn test_warmup_plan() {
+        let stake_config = StakeConfig {
+            warmup_rate: 0.25,
+            cooldown_rate: 0.25,
+        };
+        let stake_entry = StakeHistoryEntry {
+            effective: 100,
+            activating: 100,
+            deactivating: 0,
+        };
+        assert_eq!(
+            remaining_warmup_epochs(10, stake_entry.clone(), &stake_config),
+            Ok(3)
+        );
+
+        let plan = WarmupPlan::new(10, stake_entry.clone(), 0, &stake_config, 50);
+        assert_eq!(
+            plan.entries
+                .iter()
+                .map(|stake_entry| stake_entry.effective)
+                .collect::<Vec<_>>(),
+            vec![100, 125, 156, 195]
+        );
+        assert_eq!(plan.warm_epoch(), Some(13));
+
+        // A large gift stalls the schedule
+        let plan = WarmupPlan::new(10, stake_entry.clone(), 1000, &stake_config, 5);
+        assert_eq!(plan.entries.len(), 6);
+        assert_eq!(plan.warm_epoch(), None);
+
+        // Without any effective stake, nothing warms up
+        let stake_entry = StakeHistoryEntry {
+            activating: 1,
+            ..StakeHistoryEntry::default()
+        };
+        let plan = WarmupPlan::new(10, stake_entry.clone(), 0, &stake_config, 5);
+        assert_eq!(plan.warm_epoch(), None);
+        match remaining_warmup_epochs(10, stake_entry, &stake_config) {
+            Err(Stop::Failed(err)) => assert!(
+                err.starts_with(
+                    "The stake is not projected to warm up within 50 epochs of epoch 10"
+                ),
+                "{}",
+                err
+            ),
+            result => panic!("unexpected result: {:?}", result),
         }
     }
+
+    #[test]
+    fn test_plan_warmup_from_mock_cluster() {
+        let cluster = MockCluster::start(vec![MockValidator::new(1, sol_to_lamports(100.0))]);
+        cluster.wait_for_slot(SLOTS_PER_EPOCH + 1);
+        let rpc = cluster.rpc_pool();
+
+        let plans = plan_warmup(&rpc, &[0, sol_to_lamports(100.0)], 50).unwrap();
+        assert_eq!(plans.len(), 2);
+        assert_eq!(plans[0].entries.len(), 1);
+        assert_eq!(plans[0].warm_epoch(), Some(plans[0].epoch));
+        assert_eq!(plans[1].entries[0].activating, sol_to_lamports(100.0));
+        assert_eq!(plans[1].warm_epoch(), Some(plans[1].epoch + 3));
+    }
 }
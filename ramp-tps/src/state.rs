@@ -0,0 +1,262 @@
+use crate::{results::DropOut, throughput::ProgressSample, utils};
+use serde_derive::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, fs::File, io::ErrorKind, path::PathBuf, time::Duration};
+
+/// The phases of a TPS round, in the order that they are run
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Phase {
+    /// Wait for the stake awarded in the previous round to warm up
+    NewStakeWarmup,
+    /// Announce the round and check that the cluster is making progress
+    RoundStart,
+    /// Run the bench clients for the duration of the round
+    StartTransactions,
+    /// Stop the bench clients and record the surviving validators
+    StopTransactions,
+    /// Let the cluster come back together before issuing RPC calls
+    Cooldown,
+    /// Award stake to the surviving validators
+    Gifting,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Phase::NewStakeWarmup => "new-stake-warmup",
+            Phase::RoundStart => "round-start",
+            Phase::StartTransactions => "start-transactions",
+            Phase::StopTransactions => "stop-transactions",
+            Phase::Cooldown => "cooldown",
+            Phase::Gifting => "gifting",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A validator which survived a round and is owed a stake gift
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GiftRecipient {
+    pub name: String,
+    /// Validator identity pubkey
+    pub identity: String,
+    pub vote_pubkey: String,
+    pub lamports: u64,
+}
+
+/// Stake gift which is awarded at the end of a round
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PendingGift {
+    pub recipients: Vec<GiftRecipient>,
+}
+
+/// A stake gift which was not delivered in the gifting phase of its round
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FailedGift {
+    pub round: u32,
+    pub recipient: GiftRecipient,
+}
+
+/// Progress of the TPS ramp, persisted after every phase transition
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RoundState {
+    pub round: u32,
+    pub phase: Phase,
+    /// Unix timestamp of when the current phase began
+    pub phase_started_at: u64,
+    /// Transaction count of the current round, set at round start
+    pub tx_count: u64,
+    /// Unix timestamp of when the bench clients were started for the current round
+    pub transactions_started_at: Option<u64>,
+    /// Time added to the current round by an operator
+    #[serde(default)]
+    pub round_extension_secs: u64,
+    /// Cluster progress, sampled from when the bench clients were started until they were stopped
+    #[serde(default)]
+    pub progress_samples: Vec<ProgressSample>,
+    /// The stake activated in this epoch must warm up before the round begins
+    pub activation_epoch: Option<u64>,
+    /// Vote credits of each vote account when the bench clients were started, by vote pubkey
+    #[serde(default)]
+    pub round_start_credits: BTreeMap<String, u64>,
+    /// Number of survivor samples taken during the current round
+    #[serde(default)]
+    pub survivor_samples: u32,
+    /// Number of survivor samples in which each vote account was healthy, by vote pubkey
+    #[serde(default)]
+    pub healthy_samples: BTreeMap<String, u32>,
+    /// Validators which were healthy in an earlier sample of the current round but no longer
+    /// are, by vote pubkey
+    #[serde(default)]
+    pub drop_outs: BTreeMap<String, DropOut>,
+    pub pending_gift: Option<PendingGift>,
+    /// Gifts which failed in earlier rounds, retried at the next gifting phase
+    #[serde(default)]
+    pub failed_gifts: Vec<FailedGift>,
+}
+
+impl RoundState {
+    /// Begin a new ramp at `round`, once the stake activated in `activation_epoch` has warmed up
+    pub fn new(round: u32, activation_epoch: u64) -> Self {
+        RoundState {
+            round,
+            phase: Phase::NewStakeWarmup,
+            phase_started_at: utils::unix_timestamp(),
+            tx_count: 0,
+            transactions_started_at: None,
+            round_extension_secs: 0,
+            progress_samples: vec![],
+            activation_epoch: Some(activation_epoch),
+            round_start_credits: BTreeMap::new(),
+            survivor_samples: 0,
+            healthy_samples: BTreeMap::new(),
+            drop_outs: BTreeMap::new(),
+            pending_gift: None,
+            failed_gifts: vec![],
+        }
+    }
+
+    pub fn transition(&mut self, phase: Phase) {
+        self.phase = phase;
+        self.phase_started_at = utils::unix_timestamp();
+    }
+
+    /// Move on to the next round, after the stake gifted in `activation_epoch` warms up
+    pub fn next_round(&mut self, activation_epoch: u64) {
+        self.round += 1;
+        self.tx_count = 0;
+        self.transactions_started_at = None;
+        self.round_extension_secs = 0;
+        self.progress_samples.clear();
+        self.activation_epoch = Some(activation_epoch);
+        self.round_start_credits.clear();
+        self.clear_survivor_samples();
+        self.pending_gift = None;
+        self.transition(Phase::NewStakeWarmup);
+    }
+
+    pub fn clear_survivor_samples(&mut self) {
+        self.survivor_samples = 0;
+        self.healthy_samples.clear();
+        self.drop_outs.clear();
+    }
+
+    pub fn record_survivor_sample<I: IntoIterator<Item = String>>(&mut self, healthy: I) {
+        self.survivor_samples += 1;
+        for vote_pubkey in healthy {
+            *self.healthy_samples.entry(vote_pubkey).or_insert(0) += 1;
+        }
+    }
+
+    /// A validator survives the round only if it was healthy in every sample
+    pub fn healthy_in_every_sample(&self, vote_pubkey: &str) -> bool {
+        self.healthy_samples.get(vote_pubkey) == Some(&self.survivor_samples)
+    }
+
+    pub fn phase_elapsed(&self) -> Duration {
+        Duration::from_secs(utils::unix_timestamp().saturating_sub(self.phase_started_at))
+    }
+
+    /// The results of the current round are recorded when its transactions are stopped
+    pub fn first_unrecorded_round(&self) -> u32 {
+        if self.phase > Phase::StopTransactions {
+            self.round + 1
+        } else {
+            self.round
+        }
+    }
+}
+
+pub struct StateFile {
+    path: PathBuf,
+}
+
+impl StateFile {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        StateFile { path: path.into() }
+    }
+
+    /// Returns `None` if no state has been saved yet
+    pub fn load(&self) -> Result<Option<RoundState>, String> {
+        match File::open(&self.path) {
+            Ok(file) => serde_yaml::from_reader(file)
+                .map(Some)
+                .map_err(|err| format!("Unable to parse {:?}: {}", self.path, err)),
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(format!("Unable to open {:?}: {}", self.path, err)),
+        }
+    }
+
+    pub fn save(&self, state: &RoundState) -> Result<(), String> {
+        utils::save_yaml(&self.path, state)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_state_file_roundtrip() {
+        let path = std::env::temp_dir().join("ramp-tps-test-state-file-roundtrip.yml");
+        let _ = fs::remove_file(&path);
+        let state_file = StateFile::new(&path);
+        assert_eq!(state_file.load(), Ok(None));
+
+        let mut state = RoundState::new(3, 10);
+        state.transition(Phase::Gifting);
+        state.round_start_credits.insert("vote".to_string(), 100);
+        state.pending_gift = Some(PendingGift {
+            recipients: vec![GiftRecipient {
+                name: "alice".to_string(),
+                identity: "identity".to_string(),
+                vote_pubkey: "vote".to_string(),
+                lamports: 4,
+            }],
+        });
+        state_file.save(&state).unwrap();
+        assert_eq!(state_file.load(), Ok(Some(state)));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_survivor_samples() {
+        let mut state = RoundState::new(1, 0);
+        state.record_survivor_sample(vec!["alice".to_string(), "bob".to_string()]);
+        state.record_survivor_sample(vec!["alice".to_string(), "carol".to_string()]);
+        assert!(state.healthy_in_every_sample("alice"));
+        assert!(!state.healthy_in_every_sample("bob"));
+        assert!(!state.healthy_in_every_sample("carol"));
+
+        state.next_round(1);
+        assert_eq!(state.survivor_samples, 0);
+        assert!(state.healthy_samples.is_empty());
+    }
+
+    #[test]
+    fn test_first_unrecorded_round() {
+        let mut state = RoundState::new(2, 0);
+        assert_eq!(state.first_unrecorded_round(), 2);
+        state.transition(Phase::StopTransactions);
+        assert_eq!(state.first_unrecorded_round(), 2);
+        state.transition(Phase::Cooldown);
+        assert_eq!(state.first_unrecorded_round(), 3);
+        let failed_gift = FailedGift {
+            round: 2,
+            recipient: GiftRecipient {
+                name: "alice".to_string(),
+                identity: "identity".to_string(),
+                vote_pubkey: "vote".to_string(),
+                lamports: 4,
+            },
+        };
+        state.failed_gifts.push(failed_gift.clone());
+        state.next_round(5);
+        assert_eq!(state.phase, Phase::NewStakeWarmup);
+        assert_eq!(state.activation_epoch, Some(5));
+        assert_eq!(state.first_unrecorded_round(), 3);
+        // Failed gifts are carried over until they are delivered
+        assert_eq!(state.failed_gifts, vec![failed_gift]);
+    }
+}
@@ -0,0 +1,286 @@
+//! HTTP endpoint which reports the progress of the ramp, as JSON on `/status` and in the
+//! Prometheus text format on `/metrics`
+
+use crate::{
+    http::{self, Request, Response},
+    state::Phase,
+    utils,
+};
+use serde_derive::Serialize;
+use std::{
+    fmt::Write,
+    net::{SocketAddr, TcpListener},
+    sync::{Arc, Mutex},
+};
+
+const PHASES: [Phase; 6] = [
+    Phase::NewStakeWarmup,
+    Phase::RoundStart,
+    Phase::StartTransactions,
+    Phase::StopTransactions,
+    Phase::Cooldown,
+    Phase::Gifting,
+];
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct BenchStatus {
+    pub submitted: u64,
+    pub confirmed: u64,
+    pub failed: u64,
+}
+
+/// The stake gift which is awarded at the end of the current round
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct GiftSummary {
+    pub recipients: usize,
+    pub lamports: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct RampStatus {
+    pub round: u32,
+    pub phase: Option<Phase>,
+    /// Unix timestamp of when the current phase began
+    pub phase_started_at: u64,
+    /// Unix timestamp of when the current phase is due to end, if it runs for a set time
+    pub phase_ends_at: Option<u64>,
+    pub tx_count: u64,
+    /// Vote pubkeys of the validators which were healthy in every sample of the current round
+    pub survivors: Vec<String>,
+    pub drop_outs: usize,
+    /// Average TPS of the current round so far
+    pub achieved_tps: Option<f64>,
+    /// Only set while the bench clients are running
+    pub bench: Option<BenchStatus>,
+    pub next_gift: Option<GiftSummary>,
+}
+
+impl RampStatus {
+    fn time_remaining_secs(&self, now: u64) -> Option<u64> {
+        self.phase_ends_at
+            .map(|phase_ends_at| phase_ends_at.saturating_sub(now))
+    }
+}
+
+#[derive(Serialize)]
+struct StatusReply<'a> {
+    #[serde(flatten)]
+    status: &'a RampStatus,
+    time_remaining_secs: Option<u64>,
+}
+
+fn metric(metrics: &mut String, name: &str, help: &str, value: f64) {
+    let _ = writeln!(metrics, "# HELP ramp_tps_{} {}", name, help);
+    let _ = writeln!(metrics, "# TYPE ramp_tps_{} gauge", name);
+    let _ = writeln!(metrics, "ramp_tps_{} {}", name, value);
+}
+
+/// Renders `status` in the Prometheus text exposition format
+fn render_metrics(status: &RampStatus, now: u64) -> String {
+    let mut metrics = String::new();
+    metric(
+        &mut metrics,
+        "round",
+        "Current round",
+        f64::from(status.round),
+    );
+    let _ = writeln!(metrics, "# HELP ramp_tps_phase Current phase of the round");
+    let _ = writeln!(metrics, "# TYPE ramp_tps_phase gauge");
+    for phase in PHASES.iter() {
+        let value = if status.phase == Some(*phase) { 1 } else { 0 };
+        let _ = writeln!(metrics, "ramp_tps_phase{{phase=\"{}\"}} {}", phase, value);
+    }
+    if let Some(time_remaining_secs) = status.time_remaining_secs(now) {
+        metric(
+            &mut metrics,
+            "phase_remaining_seconds",
+            "Time until the current phase is due to end",
+            time_remaining_secs as f64,
+        );
+    }
+    metric(
+        &mut metrics,
+        "tx_count",
+        "Transactions per batch in the current round",
+        status.tx_count as f64,
+    );
+    metric(
+        &mut metrics,
+        "survivors",
+        "Validators healthy in every sample of the current round",
+        status.survivors.len() as f64,
+    );
+    metric(
+        &mut metrics,
+        "drop_outs",
+        "Validators which dropped out of the current round",
+        status.drop_outs as f64,
+    );
+    if let Some(achieved_tps) = status.achieved_tps {
+        metric(
+            &mut metrics,
+            "achieved_tps",
+            "Average TPS of the current round so far",
+            achieved_tps,
+        );
+    }
+    if let Some(bench) = &status.bench {
+        metric(
+            &mut metrics,
+            "bench_submitted",
+            "Transactions submitted by the bench clients this round",
+            bench.submitted as f64,
+        );
+        metric(
+            &mut metrics,
+            "bench_confirmed",
+            "Estimated transactions of the bench clients confirmed this round",
+            bench.confirmed as f64,
+        );
+        metric(
+            &mut metrics,
+            "bench_failed",
+            "Transactions of the bench clients which failed this round",
+            bench.failed as f64,
+        );
+    }
+    if let Some(next_gift) = &status.next_gift {
+        metric(
+            &mut metrics,
+            "next_gift_recipients",
+            "Validators owed a stake gift at the end of the current round",
+            next_gift.recipients as f64,
+        );
+        metric(
+            &mut metrics,
+            "next_gift_lamports",
+            "Total stake owed at the end of the current round",
+            next_gift.lamports as f64,
+        );
+    }
+    metrics
+}
+
+/// Latest status of the ramp, shared with the HTTP server
+#[derive(Clone, Debug, Default)]
+pub struct Status {
+    current: Arc<Mutex<RampStatus>>,
+}
+
+impl Status {
+    /// Serve `/status` and `/metrics` on `addr` from a background thread, which runs until the
+    /// process exits
+    pub fn serve(addr: &SocketAddr) -> Result<Self, String> {
+        let listener =
+            TcpListener::bind(addr).map_err(|err| format!("Unable to bind {}: {}", addr, err))?;
+        let status = Status::default();
+        let server = status.clone();
+        http::serve("status-server", listener, None, move |request| {
+            server.handle_request(request)
+        })
+        .map_err(|err| format!("Unable to start the status server: {}", err))?;
+        Ok(status)
+    }
+
+    pub fn publish(&self, status: RampStatus) {
+        *self.current.lock().unwrap() = status;
+    }
+
+    fn handle_request(&self, request: &Request) -> Response {
+        let status = self.current.lock().unwrap().clone();
+        let now = utils::unix_timestamp();
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/status") => {
+                let reply = StatusReply {
+                    status: &status,
+                    time_remaining_secs: status.time_remaining_secs(now),
+                };
+                Response::ok(
+                    "application/json",
+                    serde_json::to_vec(&reply).unwrap_or_default(),
+                )
+            }
+            ("GET", "/metrics") => Response::ok(
+                "text/plain; version=0.0.4",
+                render_metrics(&status, now).into_bytes(),
+            ),
+            _ => Response::not_found(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::Value;
+    use std::io::Read;
+
+    fn status() -> RampStatus {
+        RampStatus {
+            round: 4,
+            phase: Some(Phase::Cooldown),
+            phase_started_at: 1000,
+            phase_ends_at: Some(1300),
+            tx_count: 5000,
+            survivors: vec!["vote1".to_string(), "vote2".to_string()],
+            drop_outs: 1,
+            achieved_tps: Some(1250.5),
+            bench: None,
+            next_gift: Some(GiftSummary {
+                recipients: 2,
+                lamports: 16,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_render_metrics() {
+        let metrics = render_metrics(&status(), 1100);
+        assert!(metrics.contains("# TYPE ramp_tps_round gauge\nramp_tps_round 4\n"));
+        assert!(metrics.contains("ramp_tps_phase{phase=\"cooldown\"} 1\n"));
+        assert!(metrics.contains("ramp_tps_phase{phase=\"gifting\"} 0\n"));
+        assert!(metrics.contains("ramp_tps_phase_remaining_seconds 200\n"));
+        assert!(metrics.contains("ramp_tps_survivors 2\n"));
+        assert!(metrics.contains("ramp_tps_achieved_tps 1250.5\n"));
+        assert!(metrics.contains("ramp_tps_next_gift_lamports 16\n"));
+        assert!(!metrics.contains("ramp_tps_bench_submitted"));
+    }
+
+    #[test]
+    fn test_status_server() {
+        // Find a free port
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let status = Status::serve(&addr).unwrap();
+        status.publish(self::status());
+
+        let client = reqwest::Client::new();
+        let mut body = String::new();
+        client
+            .get(&format!("http://{}/status", addr))
+            .send()
+            .unwrap()
+            .read_to_string(&mut body)
+            .unwrap();
+        let reply: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(reply["round"], 4);
+        assert_eq!(reply["phase"], "cooldown");
+        assert_eq!(reply["next_gift"]["lamports"], 16);
+        assert_eq!(reply["time_remaining_secs"], 0);
+
+        let mut metrics = client
+            .get(&format!("http://{}/metrics", addr))
+            .send()
+            .unwrap();
+        assert!(metrics.status().is_success());
+        body.clear();
+        metrics.read_to_string(&mut body).unwrap();
+        assert!(body.contains("ramp_tps_tx_count 5000"));
+        let missing = client
+            .get(&format!("http://{}/missing", addr))
+            .send()
+            .unwrap();
+        assert_eq!(missing.status(), reqwest::StatusCode::NOT_FOUND);
+    }
+}
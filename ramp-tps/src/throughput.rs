@@ -0,0 +1,110 @@
+//! Measures the load that the cluster actually processed during a round
+
+use crate::utils;
+use serde_derive::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Cluster progress at a point in time
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProgressSample {
+    /// Unix timestamp in milliseconds
+    pub timestamp_ms: u64,
+    pub slot: u64,
+    pub transaction_count: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Throughput {
+    /// Transactions processed per second, on average over the round
+    pub achieved_tps: f64,
+    /// Highest transactions per second between two consecutive samples
+    pub peak_tps: f64,
+    /// Slots per second
+    pub slot_rate: f64,
+    /// Share of the slots expected from the PoH target slot duration that the cluster did not
+    /// reach. This is not a skip rate: a skipped slot still advances the slot height
+    pub slot_rate_shortfall: f64,
+}
+
+fn tps_between(earlier: &ProgressSample, later: &ProgressSample) -> Option<f64> {
+    let elapsed_ms = later.timestamp_ms.checked_sub(earlier.timestamp_ms)?;
+    if elapsed_ms == 0 {
+        return None;
+    }
+    let transactions = later
+        .transaction_count
+        .saturating_sub(earlier.transaction_count);
+    Some(transactions as f64 * 1000.0 / elapsed_ms as f64)
+}
+
+/// Returns `None` unless there are at least two samples which are some time apart
+pub fn measure(samples: &[ProgressSample], target_slot_duration: Duration) -> Option<Throughput> {
+    let first = samples.first()?;
+    let last = samples.last()?;
+    let achieved_tps = tps_between(first, last)?;
+    let peak_tps = samples
+        .windows(2)
+        .filter_map(|pair| tps_between(&pair[0], &pair[1]))
+        .fold(0.0, f64::max);
+
+    let elapsed_secs = (last.timestamp_ms - first.timestamp_ms) as f64 / 1000.0;
+    let slots = last.slot.saturating_sub(first.slot) as f64;
+    let slot_rate = slots / elapsed_secs;
+    let expected_slots = elapsed_secs / utils::duration_as_secs_f64(target_slot_duration);
+    let slot_rate_shortfall = if expected_slots > 0.0 {
+        (1.0 - slots / expected_slots).max(0.0)
+    } else {
+        0.0
+    };
+
+    Some(Throughput {
+        achieved_tps,
+        peak_tps,
+        slot_rate,
+        slot_rate_shortfall,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample(secs: u64, slot: u64, transaction_count: u64) -> ProgressSample {
+        ProgressSample {
+            timestamp_ms: secs * 1000,
+            slot,
+            transaction_count,
+        }
+    }
+
+    #[test]
+    fn test_measure_throughput() {
+        let samples = vec![
+            sample(100, 1000, 50_000),
+            sample(110, 1020, 60_000),
+            sample(120, 1040, 90_000),
+        ];
+        let throughput = measure(&samples, Duration::from_millis(400)).unwrap();
+        assert_eq!(throughput.achieved_tps, 2000.0);
+        assert_eq!(throughput.peak_tps, 3000.0);
+        assert_eq!(throughput.slot_rate, 2.0);
+        // 50 slots were expected in 20 seconds at the target slot duration, but the slot height
+        // only advanced by 40
+        assert!((throughput.slot_rate_shortfall - 0.2).abs() < 1e-9);
+
+        // A cluster running at or above its target slot rate has no shortfall
+        let fast = measure(&samples, Duration::from_millis(500)).unwrap();
+        assert_eq!(fast.slot_rate_shortfall, 0.0);
+        let faster = measure(&samples, Duration::from_millis(600)).unwrap();
+        assert_eq!(faster.slot_rate_shortfall, 0.0);
+    }
+
+    #[test]
+    fn test_measure_without_enough_samples() {
+        assert_eq!(measure(&[], Duration::from_millis(400)), None);
+        assert_eq!(
+            measure(&[sample(100, 1000, 50_000)], Duration::from_millis(400)),
+            None
+        );
+    }
+}
@@ -1,121 +1,382 @@
+use crate::rpc::{CallCategory, RpcPool};
 use bzip2::bufread::BzDecoder;
 use log::*;
+use reqwest::{header, StatusCode};
+use serde::Serialize;
 use solana_netutil::parse_host;
-use solana_sdk::{genesis_block::GenesisBlock, timing::duration_as_ms};
+use solana_sdk::{genesis_block::GenesisBlock, hash::Hash};
 use std::{
-    fs::File,
-    io,
-    net::SocketAddr,
-    path::Path,
-    thread::sleep,
-    time::{Duration, Instant},
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Component, Path},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tar::Archive;
 
 const GENESIS_ARCHIVE_NAME: &str = "genesis.tar.bz2";
-
-/// Inspired by solana_local_cluster::cluster_tests
-fn slots_to_secs(num_slots: u64, genesis_block: &GenesisBlock) -> u64 {
-    let poh_config = &genesis_block.poh_config;
-    let ticks_per_slot = genesis_block.ticks_per_slot;
-    let num_ticks_to_sleep = num_slots as f64 * ticks_per_slot as f64;
-    let num_ticks_per_second = (1000 / duration_as_ms(&poh_config.target_tick_duration)) as f64;
-    ((num_ticks_to_sleep + num_ticks_per_second - 1.0) / num_ticks_per_second) as u64
-}
-
-pub fn sleep_n_slots(num_slots: u64, genesis_block: &GenesisBlock) {
-    let secs = slots_to_secs(num_slots, genesis_block);
-    let mins = secs / 60;
-    let hours = mins / 60;
-    if hours >= 5 {
-        debug!("Sleeping for {} slots ({} hours)", num_slots, hours);
-    } else if mins >= 5 {
-        debug!("Sleeping for {} slots ({} minutes)", num_slots, mins);
-    } else if secs > 0 {
-        debug!("Sleeping for {} slots ({} seconds)", num_slots, secs);
-    }
-    sleep(Duration::from_secs(secs));
-}
+/// Download progress is logged at most this often
+const DOWNLOAD_PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
 
 pub fn is_host(string: String) -> Result<(), String> {
     parse_host(&string)?;
     Ok(())
 }
 
-pub fn bail(notifier: &crate::notifier::Notifier, msg: &str) -> ! {
-    notifier.notify(msg);
-    sleep(Duration::from_secs(30)); // Wait for notifications to send
-    std::process::exit(1);
+/// Target duration of a slot, from the PoH configuration of the cluster
+pub fn slot_duration(genesis_block: &GenesisBlock) -> Duration {
+    genesis_block.poh_config.target_tick_duration * genesis_block.ticks_per_slot as u32
 }
 
-/// Inspired by solana_validator::download_tar_bz2
-pub fn download_genesis(rpc_addr: &SocketAddr, download_path: &Path) -> Result<(), String> {
-    let archive_name = GENESIS_ARCHIVE_NAME;
-    let archive_path = download_path.join(archive_name);
-    let url = format!("http://{}/{}", rpc_addr, archive_name);
-    let download_start = Instant::now();
-    debug!("Downloading genesis ({})...", url);
+/// Seconds in `duration`, including the fraction of a second
+pub fn duration_as_secs_f64(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9
+}
 
-    let client = reqwest::Client::new();
-    let mut response = client
-        .get(url.as_str())
-        .send()
-        .and_then(|response| response.error_for_status())
-        .map_err(|err| format!("Unable to get: {:?}", err))?;
-    let download_size = {
-        response
-            .headers()
-            .get(reqwest::header::CONTENT_LENGTH)
-            .and_then(|content_length| content_length.to_str().ok())
-            .and_then(|content_length| content_length.parse().ok())
-            .unwrap_or(0)
-    };
+pub fn unix_timestamp_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
 
-    let mut file = File::create(&archive_path)
-        .map_err(|err| format!("Unable to create {:?}: {:?}", archive_path, err))?;
-    io::copy(&mut response, &mut file)
-        .map_err(|err| format!("Unable to write {:?}: {:?}", archive_path, err))?;
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
 
-    debug!(
-        "Downloaded genesis ({} bytes) in {:?}",
-        download_size,
-        Instant::now().duration_since(download_start),
-    );
+/// Write `value` to a temporary file first so that a crash never leaves a partial file behind
+pub fn save_yaml<T: Serialize>(path: &Path, value: &T) -> Result<(), String> {
+    let tmp_path = path.with_extension("tmp");
+    let file = File::create(&tmp_path)
+        .map_err(|err| format!("Unable to create {:?}: {}", tmp_path, err))?;
+    serde_yaml::to_writer(&file, value)
+        .map_err(|err| format!("Unable to write {:?}: {}", tmp_path, err))?;
+    file.sync_all()
+        .map_err(|err| format!("Unable to sync {:?}: {}", tmp_path, err))?;
+    fs::rename(&tmp_path, path).map_err(|err| format!("Unable to rename {:?}: {}", tmp_path, err))
+}
+
+/// Download `url` to `path`. A partial download left at `path` by an earlier attempt is resumed
+/// if the server supports it. `timeout` bounds every read from the server
+fn download_file(url: &str, path: &Path, timeout: Duration) -> Result<(), String> {
+    let client = reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .map_err(|err| format!("Unable to create an HTTP client: {}", err))?;
+    let mut offset = fs::metadata(path)
+        .map(|metadata| metadata.len())
+        .unwrap_or(0);
+    let mut request = client.get(url);
+    if offset > 0 {
+        info!("Resuming the download of {} at byte {}", url, offset);
+        request = request.header(header::RANGE, format!("bytes={}-", offset));
+    }
+    let mut response = request
+        .send()
+        .map_err(|err| format!("Unable to get {}: {}", url, err))?;
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        // The partial download is not a prefix of the file on the server, start over
+        offset = 0;
+        response = client
+            .get(url)
+            .send()
+            .map_err(|err| format!("Unable to get {}: {}", url, err))?;
+    }
+    let mut response = response
+        .error_for_status()
+        .map_err(|err| format!("Unable to get {}: {}", url, err))?;
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+        offset = 0;
+    }
+    let total_size = response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|content_length| content_length.to_str().ok())
+        .and_then(|content_length| content_length.parse::<u64>().ok())
+        .map(|content_length| offset + content_length);
 
-    debug!("Extracting genesis ({})...", archive_name);
-    let extract_start = Instant::now();
-    let tar_bz2 = File::open(&archive_path)
-        .map_err(|err| format!("Unable to open {}: {:?}", archive_name, err))?;
-    let tar = BzDecoder::new(io::BufReader::new(tar_bz2));
-    let mut archive = Archive::new(tar);
-    archive
-        .unpack(download_path)
-        .map_err(|err| format!("Unable to unpack {}: {:?}", archive_name, err))?;
+    let mut file = if offset > 0 {
+        OpenOptions::new().append(true).open(path)
+    } else {
+        File::create(path)
+    }
+    .map_err(|err| format!("Unable to open {:?}: {}", path, err))?;
+    let download_start = Instant::now();
+    let mut last_progress = download_start;
+    let mut downloaded = offset;
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let len = response
+            .read(&mut buffer)
+            .map_err(|err| format!("Download of {} failed: {}", url, err))?;
+        if len == 0 {
+            break;
+        }
+        file.write_all(&buffer[..len])
+            .map_err(|err| format!("Unable to write {:?}: {}", path, err))?;
+        downloaded += len as u64;
+        if last_progress.elapsed() >= DOWNLOAD_PROGRESS_INTERVAL {
+            last_progress = Instant::now();
+            match total_size {
+                Some(total_size) => info!(
+                    "Downloaded {} of {} bytes ({}%)",
+                    downloaded,
+                    total_size,
+                    downloaded * 100 / total_size.max(1)
+                ),
+                None => info!("Downloaded {} bytes", downloaded),
+            }
+        }
+    }
+    file.sync_all()
+        .map_err(|err| format!("Unable to sync {:?}: {}", path, err))?;
+    if let Some(total_size) = total_size {
+        if downloaded < total_size {
+            return Err(format!(
+                "Download of {} ended after {} of {} bytes",
+                url, downloaded, total_size
+            ));
+        }
+    }
     debug!(
-        "Extracted {} in {:?}",
-        archive_name,
-        Instant::now().duration_since(extract_start)
+        "Downloaded {} ({} bytes) in {:?}",
+        url,
+        downloaded - offset,
+        download_start.elapsed()
     );
+    Ok(())
+}
 
+/// Unpack `archive_path` into `unpack_path`. Only plain files and directories are unpacked, and
+/// an entry whose path would escape `unpack_path` fails the whole archive
+fn unpack_archive(archive_path: &Path, unpack_path: &Path) -> Result<(), String> {
+    let tar_bz2 = File::open(archive_path)
+        .map_err(|err| format!("Unable to open {:?}: {}", archive_path, err))?;
+    let mut archive = Archive::new(BzDecoder::new(io::BufReader::new(tar_bz2)));
+    let entries = archive
+        .entries()
+        .map_err(|err| format!("Unable to read {:?}: {}", archive_path, err))?;
+    for entry in entries {
+        let mut entry =
+            entry.map_err(|err| format!("Unable to read {:?}: {}", archive_path, err))?;
+        let entry_path = entry
+            .path()
+            .map_err(|err| format!("Invalid entry in {:?}: {}", archive_path, err))?
+            .into_owned();
+        let entry_type = entry.header().entry_type();
+        let is_contained = entry_path.components().all(|component| match component {
+            Component::Normal(_) | Component::CurDir => true,
+            _ => false,
+        });
+        if !is_contained || !(entry_type.is_file() || entry_type.is_dir()) {
+            return Err(format!(
+                "Refusing to unpack {:?} ({:?}) from {:?}",
+                entry_path, entry_type, archive_path
+            ));
+        }
+        entry
+            .unpack_in(unpack_path)
+            .map_err(|err| format!("Unable to unpack {:?}: {}", entry_path, err))?;
+    }
     Ok(())
 }
 
+fn load_genesis(path: &Path, genesis_hash: &Hash) -> Result<GenesisBlock, String> {
+    let genesis_block =
+        GenesisBlock::load(path).map_err(|err| format!("Unable to load {:?}: {}", path, err))?;
+    if genesis_block.hash() != *genesis_hash {
+        return Err(format!(
+            "The genesis block in {:?} has hash {}, expected {}",
+            path,
+            genesis_block.hash(),
+            genesis_hash
+        ));
+    }
+    Ok(genesis_block)
+}
+
+/// Returns the genesis block of the cluster. Genesis blocks are cached in a subdirectory of
+/// `cache_dir` named after their hash, and only downloaded when the cache has no copy of the
+/// genesis block that the cluster reports. If `expected_hash` is set, any other genesis block is
+/// rejected
+///
+/// Inspired by solana_validator::download_tar_bz2
+pub fn fetch_genesis(
+    rpc: &RpcPool,
+    cache_dir: &Path,
+    expected_hash: Option<Hash>,
+    timeout: Duration,
+) -> Result<GenesisBlock, String> {
+    let cluster_hash = rpc
+        .call(CallCategory::Query, "get_genesis_blockhash", |rpc_client| {
+            rpc_client.get_genesis_blockhash()
+        })
+        .map_err(|err| warn!("Unable to fetch the genesis hash of the cluster: {}", err))
+        .ok();
+    if let (Some(expected_hash), Some(cluster_hash)) = (expected_hash, cluster_hash) {
+        if expected_hash != cluster_hash {
+            return Err(format!(
+                "The cluster is running genesis {}, expected {}",
+                cluster_hash, expected_hash
+            ));
+        }
+    }
+    let genesis_hash = expected_hash.or(cluster_hash);
+    if let Some(genesis_hash) = genesis_hash {
+        let cached_path = cache_dir.join(genesis_hash.to_string());
+        match load_genesis(&cached_path, &genesis_hash) {
+            Ok(genesis_block) => {
+                info!("Using the cached genesis block in {:?}", cached_path);
+                return Ok(genesis_block);
+            }
+            Err(err) => debug!("Genesis block {} is not cached: {}", genesis_hash, err),
+        }
+    }
+
+    let download_dir = cache_dir.join("download");
+    fs::create_dir_all(&download_dir)
+        .map_err(|err| format!("Unable to create {:?}: {}", download_dir, err))?;
+    let archive_path = download_dir.join(GENESIS_ARCHIVE_NAME);
+    let url = format!("http://{}/{}", rpc.current_addr(), GENESIS_ARCHIVE_NAME);
+    info!("Downloading the genesis block from {}...", url);
+    download_file(&url, &archive_path, timeout)?;
+
+    let unpack_path = download_dir.join("genesis");
+    let _ = fs::remove_dir_all(&unpack_path);
+    fs::create_dir_all(&unpack_path)
+        .map_err(|err| format!("Unable to create {:?}: {}", unpack_path, err))?;
+    let genesis_block =
+        match unpack_archive(&archive_path, &unpack_path).and_then(|()| match genesis_hash {
+            Some(genesis_hash) => load_genesis(&unpack_path, &genesis_hash),
+            None => GenesisBlock::load(&unpack_path)
+                .map_err(|err| format!("Unable to load {:?}: {}", unpack_path, err)),
+        }) {
+            Ok(genesis_block) => genesis_block,
+            Err(err) => {
+                // Do not resume from a bad archive next time
+                let _ = fs::remove_file(&archive_path);
+                return Err(err);
+            }
+        };
+
+    let cached_path = cache_dir.join(genesis_block.hash().to_string());
+    let _ = fs::remove_dir_all(&cached_path);
+    fs::rename(&unpack_path, &cached_path)
+        .map_err(|err| format!("Unable to rename {:?}: {}", unpack_path, err))?;
+    fs::remove_file(&archive_path)
+        .map_err(|err| format!("Unable to remove {:?}: {}", archive_path, err))?;
+    info!(
+        "Cached genesis block {} in {:?}",
+        genesis_block.hash(),
+        cached_path
+    );
+    Ok(genesis_block)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::{
+        mock_cluster::{self, MockCluster},
+        rpc::RetryPolicies,
+    };
+    use bzip2::{write::BzEncoder, Compression};
+    use std::net::{SocketAddr, TcpListener};
+    use tar::{EntryType, Header};
+
+    fn rpc_pool(addr: SocketAddr) -> RpcPool {
+        let mut policies = RetryPolicies::default();
+        policies.query.attempts = 1;
+        RpcPool::new(&[addr], Duration::from_secs(10), policies)
+    }
+
+    /// Writes a genesis archive with a single entry, bypassing the path checks of the tar crate
+    fn write_archive(path: &Path, entry_path: &[u8], entry_type: EntryType) {
+        let mut archive = tar::Builder::new(BzEncoder::new(
+            File::create(path).unwrap(),
+            Compression::Best,
+        ));
+        let mut header = Header::new_gnu();
+        header.as_gnu_mut().unwrap().name[..entry_path.len()].copy_from_slice(entry_path);
+        header.set_entry_type(entry_type);
+        header.set_mode(0o644);
+        header.set_size(4);
+        header.set_cksum();
+        archive.append(&header, &b"evil"[..]).unwrap();
+        archive.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[test]
+    fn test_fetch_genesis_from_mock_cluster() {
+        let cluster = MockCluster::start(vec![]);
+        let genesis_hash = cluster.genesis_block.hash();
+        let cache_dir = std::env::temp_dir().join("ramp-tps-test-fetch-genesis");
+        let _ = fs::remove_dir_all(&cache_dir);
+
+        // A partial download of something else is replaced
+        fs::create_dir_all(cache_dir.join("download")).unwrap();
+        fs::write(
+            cache_dir.join("download").join(GENESIS_ARCHIVE_NAME),
+            b"partial",
+        )
+        .unwrap();
+        let rpc = rpc_pool(cluster.rpc_addr());
+        let timeout = Duration::from_secs(10);
+        let genesis_block = fetch_genesis(&rpc, &cache_dir, Some(genesis_hash), timeout).unwrap();
+        assert_eq!(
+            genesis_block.epoch_schedule.slots_per_epoch,
+            cluster.genesis_block.epoch_schedule.slots_per_epoch
+        );
+        assert_eq!(slot_duration(&genesis_block), mock_cluster::SLOT_DURATION);
+        assert!(cache_dir.join(genesis_hash.to_string()).is_dir());
+        assert!(!cache_dir
+            .join("download")
+            .join(GENESIS_ARCHIVE_NAME)
+            .exists());
+
+        // The cluster's genesis block must be the expected one
+        let other_hash = solana_sdk::hash::hash(b"other");
+        assert!(fetch_genesis(&rpc, &cache_dir, Some(other_hash), timeout).is_err());
+
+        // The cached copy is used without contacting the cluster
+        let closed_addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let offline_rpc = rpc_pool(closed_addr);
+        assert_eq!(
+            fetch_genesis(&offline_rpc, &cache_dir, Some(genesis_hash), timeout)
+                .unwrap()
+                .hash(),
+            genesis_hash
+        );
+        assert!(fetch_genesis(&offline_rpc, &cache_dir, None, timeout).is_err());
+        fs::remove_dir_all(&cache_dir).unwrap();
+    }
 
     #[test]
-    fn test_slots_to_secs() {
-        let mut genesis_block = GenesisBlock::default();
-        genesis_block.poh_config.target_tick_duration = Duration::from_millis(500);
+    fn test_unpack_archive_rejects_escaping_entries() {
+        let dir = std::env::temp_dir().join("ramp-tps-test-unpack-archive");
+        let _ = fs::remove_dir_all(&dir);
+        let unpack_path = dir.join("genesis");
+        fs::create_dir_all(&unpack_path).unwrap();
+        let archive_path = dir.join(GENESIS_ARCHIVE_NAME);
+
+        write_archive(&archive_path, b"genesis.bin", EntryType::Regular);
+        assert_eq!(unpack_archive(&archive_path, &unpack_path), Ok(()));
+        assert_eq!(fs::read(unpack_path.join("genesis.bin")).unwrap(), b"evil");
 
-        genesis_block.ticks_per_slot = 10;
-        assert_eq!(slots_to_secs(2, &genesis_block), 10);
+        write_archive(&archive_path, b"../evil", EntryType::Regular);
+        assert!(unpack_archive(&archive_path, &unpack_path).is_err());
+        assert!(!dir.join("evil").exists());
 
-        genesis_block.ticks_per_slot = 1;
-        assert_eq!(slots_to_secs(1, &genesis_block), 1);
+        write_archive(&archive_path, b"/tmp/evil", EntryType::Regular);
+        assert!(unpack_archive(&archive_path, &unpack_path).is_err());
 
-        genesis_block.ticks_per_slot = 0;
-        assert_eq!(slots_to_secs(10, &genesis_block), 0);
+        write_archive(&archive_path, b"link", EntryType::Symlink);
+        assert!(unpack_archive(&archive_path, &unpack_path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -1,68 +1,296 @@
+use crate::rpc::{CallCategory, RpcPool};
 use log::*;
 use solana_client::rpc_client::RpcClient;
-use solana_sdk::native_token::sol_to_lamports;
+use solana_sdk::hash::Hash;
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::{Keypair, KeypairUtil};
 use solana_sdk::transaction::Transaction;
 use solana_stake_api::stake_instruction;
 use solana_stake_api::stake_state::Authorized as StakeAuthorized;
-use std::str::FromStr;
+use solana_vote_api::vote_state::VoteState;
+use std::{net::SocketAddr, str::FromStr, time::Duration};
 
-pub fn fetch_remaining_voters(rpc_client: &RpcClient) -> Vec<(Pubkey, Pubkey)> {
-    match rpc_client.get_vote_accounts() {
-        Err(err) => {
-            warn!("Failed to get_vote_accounts(): {}", err);
-            vec![]
-        }
-        Ok(vote_accounts) => vote_accounts
-            .current
-            .into_iter()
-            .filter_map(|info| {
-                if let (Ok(node_pubkey), Ok(vote_pubkey)) = (
-                    Pubkey::from_str(&info.node_pubkey),
-                    Pubkey::from_str(&info.vote_pubkey),
-                ) {
-                    Some((node_pubkey, vote_pubkey))
-                } else {
-                    None
+const NODE_VERSION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Thresholds that a validator must meet to remain in the ramp
+#[derive(Clone, Debug)]
+pub struct SurvivorCriteria {
+    /// A validator whose last vote is further behind the current slot is delinquent
+    pub max_vote_lag: u64,
+    /// A validator whose root slot is further behind the current slot is delinquent
+    pub max_root_lag: u64,
+    /// Minimum activated stake, in lamports. Validators without any activated stake never qualify
+    pub min_activated_stake: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoterStatus {
+    pub node_pubkey: Pubkey,
+    pub vote_pubkey: Pubkey,
+    pub activated_stake: u64,
+    pub last_vote: u64,
+    pub root_slot: Option<u64>,
+    /// Reported as delinquent by the cluster
+    pub delinquent: bool,
+    /// Slots in the tower of the vote account
+    pub voted_slots: Vec<u64>,
+}
+
+/// The status of every vote account, current or delinquent, at `slot`
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoterSample {
+    pub slot: u64,
+    pub statuses: Vec<VoterStatus>,
+}
+
+impl VoterSample {
+    pub fn status(&self, vote_pubkey: &Pubkey) -> Option<&VoterStatus> {
+        self.statuses
+            .iter()
+            .find(|status| status.vote_pubkey == *vote_pubkey)
+    }
+}
+
+impl SurvivorCriteria {
+    /// Returns why `status` does not meet the criteria, or `None` if the validator is healthy
+    pub fn unhealthy_reason(&self, status: &VoterStatus, current_slot: u64) -> Option<String> {
+        let vote_lag = current_slot.saturating_sub(status.last_vote);
+        if status.activated_stake == 0 || status.activated_stake < self.min_activated_stake {
+            Some(format!(
+                "activated stake of {} lamports is below the minimum",
+                status.activated_stake
+            ))
+        } else if vote_lag > self.max_vote_lag {
+            Some(format!("last vote is {} slots behind", vote_lag))
+        } else {
+            match status.root_slot {
+                None => Some("no root slot".to_string()),
+                Some(root_slot) if current_slot.saturating_sub(root_slot) > self.max_root_lag => {
+                    Some(format!(
+                        "root slot is {} slots behind",
+                        current_slot.saturating_sub(root_slot)
+                    ))
                 }
+                Some(_) => None,
+            }
+        }
+    }
+
+    pub fn is_healthy(&self, status: &VoterStatus, current_slot: u64) -> bool {
+        self.unhealthy_reason(status, current_slot).is_none()
+    }
+
+    /// Returns the (node pubkey, vote pubkey) of every validator in `sample` which meets the
+    /// criteria
+    pub fn survivors(&self, sample: &VoterSample) -> Vec<(Pubkey, Pubkey)> {
+        sample
+            .statuses
+            .iter()
+            .filter(|status| self.is_healthy(status, sample.slot))
+            .map(|status| (status.node_pubkey, status.vote_pubkey))
+            .collect()
+    }
+}
+
+/// Returns the status of every vote account. Every RPC call, including the fetch of each vote
+/// account, is retried on its own, and an error is only reported once every attempt has failed
+pub fn fetch_voters(rpc: &RpcPool) -> Result<VoterSample, String> {
+    let vote_accounts = rpc.call(CallCategory::Voters, "get_vote_accounts", |rpc_client| {
+        rpc_client.get_vote_accounts()
+    })?;
+    let current_slot = rpc.call(CallCategory::Voters, "get_slot", |rpc_client| {
+        rpc_client.get_slot()
+    })?;
+
+    let mut statuses = vec![];
+    let current = vote_accounts.current.into_iter().map(|info| (info, false));
+    let delinquent = vote_accounts
+        .delinquent
+        .into_iter()
+        .map(|info| (info, true));
+    for (info, delinquent) in current.chain(delinquent) {
+        let (node_pubkey, vote_pubkey) = match (
+            Pubkey::from_str(&info.node_pubkey),
+            Pubkey::from_str(&info.vote_pubkey),
+        ) {
+            (Ok(node_pubkey), Ok(vote_pubkey)) => (node_pubkey, vote_pubkey),
+            _ => continue,
+        };
+        let vote_account = rpc
+            .call(CallCategory::Voters, "get_account", |rpc_client| {
+                rpc_client.get_account(&vote_pubkey)
             })
-            .collect(),
+            .map_err(|err| format!("Unable to fetch vote account {}: {}", vote_pubkey, err))?;
+        let vote_state = VoteState::from(&vote_account);
+        statuses.push(VoterStatus {
+            node_pubkey,
+            vote_pubkey,
+            activated_stake: info.activated_stake,
+            last_vote: info.last_vote,
+            root_slot: vote_state
+                .as_ref()
+                .and_then(|vote_state| vote_state.root_slot),
+            delinquent,
+            voted_slots: vote_state.map_or_else(Vec::new, |vote_state| {
+                vote_state
+                    .votes
+                    .iter()
+                    .map(|lockout| lockout.slot)
+                    .collect()
+            }),
+        });
     }
+    Ok(VoterSample {
+        slot: current_slot,
+        statuses,
+    })
 }
 
+/// Returns the software version reported by the RPC service at `rpc_addr`
+pub fn fetch_node_version(rpc_addr: SocketAddr) -> Option<String> {
+    RpcClient::new_socket_with_timeout(rpc_addr, NODE_VERSION_TIMEOUT)
+        .get_version()
+        .map(|version| version.solana_core)
+        .map_err(|err| debug!("Failed to get_version() from {}: {}", rpc_addr, err))
+        .ok()
+}
+
+/// Returns the vote credits that `vote_account_pubkey` has earned so far
+pub fn fetch_vote_credits(rpc: &RpcPool, vote_account_pubkey: &Pubkey) -> Option<u64> {
+    match rpc.call(CallCategory::Query, "get_account", |rpc_client| {
+        rpc_client.get_account(vote_account_pubkey)
+    }) {
+        Err(err) => {
+            warn!(
+                "Unable to fetch the vote credits of {}: {}",
+                vote_account_pubkey, err
+            );
+            None
+        }
+        Ok(account) => VoteState::from(&account).map(|vote_state| vote_state.credits()),
+    }
+}
+
+/// Create the stake account of `stake_account_keypair`, funded by the mint, and delegate it to
+/// `vote_account_pubkey`. Returns the transaction signature
 pub fn award_stake(
     rpc_client: &RpcClient,
     mint_keypair: &Keypair,
-    voters: Vec<(String, Pubkey)>,
-    sol_gift: u64,
-    notifier: &mut crate::notifier::Notifier,
-) {
-    let recent_blockhash = rpc_client.get_recent_blockhash().unwrap().0;
-    for (node_pubkey, vote_account_pubkey) in voters {
-        let stake_account_keypair = Keypair::new();
-        let mut transaction = Transaction::new_signed_instructions(
-            &[mint_keypair, &mint_keypair],
-            stake_instruction::create_stake_account_and_delegate_stake(
-                &mint_keypair.pubkey(),
-                &stake_account_keypair.pubkey(),
-                &vote_account_pubkey,
-                &StakeAuthorized::auto(&mint_keypair.pubkey()),
-                sol_to_lamports(sol_gift as f64),
-            ),
-            recent_blockhash,
+    stake_account_keypair: &Keypair,
+    vote_account_pubkey: &Pubkey,
+    lamports: u64,
+    recent_blockhash: Hash,
+) -> Result<String, String> {
+    let mut transaction = Transaction::new_signed_instructions(
+        &[mint_keypair, stake_account_keypair],
+        stake_instruction::create_stake_account_and_delegate_stake(
+            &mint_keypair.pubkey(),
+            &stake_account_keypair.pubkey(),
+            vote_account_pubkey,
+            &StakeAuthorized::auto(&mint_keypair.pubkey()),
+            lamports,
+        ),
+        recent_blockhash,
+    );
+
+    rpc_client
+        .send_and_confirm_transaction(&mut transaction, &[mint_keypair, stake_account_keypair])
+        .map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock_cluster::{MockCluster, MockValidator};
+
+    #[test]
+    fn test_survivor_criteria() {
+        let criteria = SurvivorCriteria {
+            max_vote_lag: 100,
+            max_root_lag: 200,
+            min_activated_stake: 10,
+        };
+        let healthy = VoterStatus {
+            node_pubkey: Pubkey::default(),
+            vote_pubkey: Pubkey::default(),
+            activated_stake: 10,
+            last_vote: 900,
+            root_slot: Some(800),
+            delinquent: false,
+            voted_slots: (869..=900).collect(),
+        };
+        assert!(criteria.is_healthy(&healthy, 1000));
+
+        let lagging_vote = VoterStatus {
+            last_vote: 899,
+            ..healthy.clone()
+        };
+        assert!(!criteria.is_healthy(&lagging_vote, 1000));
+
+        let lagging_root = VoterStatus {
+            root_slot: Some(799),
+            ..healthy.clone()
+        };
+        assert!(!criteria.is_healthy(&lagging_root, 1000));
+
+        let no_root = VoterStatus {
+            root_slot: None,
+            ..healthy.clone()
+        };
+        assert!(!criteria.is_healthy(&no_root, 1000));
+
+        let low_stake = VoterStatus {
+            activated_stake: 9,
+            ..healthy.clone()
+        };
+        assert!(!criteria.is_healthy(&low_stake, 1000));
+
+        let sample = VoterSample {
+            slot: 1000,
+            statuses: vec![healthy.clone(), lagging_vote],
+        };
+        assert_eq!(
+            criteria.survivors(&sample),
+            vec![(healthy.node_pubkey, healthy.vote_pubkey)]
+        );
+        assert_eq!(
+            criteria.unhealthy_reason(&sample.statuses[1], sample.slot),
+            Some("last vote is 101 slots behind".to_string())
         );
+    }
 
-        if let Err(err) = rpc_client
-            .send_and_confirm_transaction(&mut transaction, &[mint_keypair, &stake_account_keypair])
-        {
-            notifier.buffer(format!(
-                "Failed to delegate {} SOL to {}: {}",
-                sol_gift, node_pubkey, err
-            ));
-        } else {
-            notifier.buffer(format!("Delegated {} SOL to {}", sol_gift, node_pubkey));
-        }
+    #[test]
+    fn test_fetch_voters_from_mock_cluster() {
+        let healthy = MockValidator::new(1, 100);
+        let unstaked = MockValidator::new(2, 0);
+        let dropped = MockValidator {
+            drop_out_slot: Some(40),
+            ..MockValidator::new(3, 100)
+        };
+        let cluster = MockCluster::start(vec![healthy.clone(), unstaked, dropped.clone()]);
+        let rpc = cluster.rpc_pool();
+        let criteria = SurvivorCriteria {
+            max_vote_lag: 20,
+            max_root_lag: 60,
+            min_activated_stake: 1,
+        };
+        cluster.wait_for_slot(35);
+
+        let sample = fetch_voters(&rpc).unwrap();
+        assert_eq!(sample.statuses.len(), 3);
+        // The vote account is fetched after the last vote, so the cluster may have advanced
+        let status = sample.status(&healthy.vote_pubkey).unwrap();
+        assert!(status.root_slot.unwrap() >= status.last_vote - 32);
+        assert_eq!(criteria.survivors(&sample).len(), 2);
+
+        cluster.wait_for_slot(70);
+        let sample = fetch_voters(&rpc).unwrap();
+        assert_eq!(
+            criteria.survivors(&sample),
+            vec![(healthy.node_pubkey, healthy.vote_pubkey)]
+        );
+        let status = sample.status(&dropped.vote_pubkey).unwrap();
+        assert_eq!(status.last_vote, 40);
+        assert!(!criteria.is_healthy(status, sample.slot));
     }
-    notifier.flush();
 }
@@ -0,0 +1,249 @@
+//! Waits for the cluster to reach a slot or epoch by polling its progress
+//!
+//! The slot rate is measured from the recent polls, and the cluster is polled more often as the
+//! target gets closer, so a wait ends soon after the target even when the cluster is running
+//! slower or faster than its target slot duration.
+
+use crate::{
+    rpc::{CallCategory, RpcPool},
+    shutdown::{Shutdown, Stop},
+    utils,
+};
+use log::*;
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Shortest and longest delay between polls
+const MIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(30);
+/// The slot rate is measured over this many of the most recent polls
+const RATE_SAMPLES: usize = 10;
+/// Progress is logged at most this often
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Slot rate measured from recent polls of the cluster
+struct SlotRate {
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl SlotRate {
+    fn new() -> Self {
+        SlotRate {
+            samples: VecDeque::with_capacity(RATE_SAMPLES),
+        }
+    }
+
+    fn add(&mut self, now: Instant, slot: u64) {
+        if self.samples.len() == RATE_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((now, slot));
+    }
+
+    /// Slots per second, once the slot has advanced between the recent polls
+    fn slots_per_sec(&self) -> Option<f64> {
+        let (first_time, first_slot) = self.samples.front()?;
+        let (last_time, last_slot) = self.samples.back()?;
+        let elapsed = utils::duration_as_secs_f64(last_time.duration_since(*first_time));
+        if last_slot <= first_slot || elapsed <= 0.0 {
+            return None;
+        }
+        Some((last_slot - first_slot) as f64 / elapsed)
+    }
+}
+
+/// Time until `remaining_slots` have passed at `slots_per_sec`
+fn eta(remaining_slots: u64, slots_per_sec: f64) -> Duration {
+    Duration::from_millis((remaining_slots as f64 / slots_per_sec * 1000.) as u64)
+}
+
+/// Wait half of the time left, so that a change in the slot rate is noticed before the target
+fn poll_interval(eta: Duration) -> Duration {
+    (eta / 2).max(MIN_POLL_INTERVAL).min(MAX_POLL_INTERVAL)
+}
+
+pub struct SlotWaiter<'a> {
+    rpc: &'a RpcPool,
+    shutdown: &'a Shutdown,
+    /// Assumed until the slot rate has been measured
+    target_slot_duration: Duration,
+    /// Give up once the slot has not advanced for this long
+    stall_timeout: Duration,
+}
+
+impl<'a> SlotWaiter<'a> {
+    pub fn new(
+        rpc: &'a RpcPool,
+        shutdown: &'a Shutdown,
+        target_slot_duration: Duration,
+        stall_timeout: Duration,
+    ) -> Self {
+        SlotWaiter {
+            rpc,
+            shutdown,
+            target_slot_duration,
+            stall_timeout,
+        }
+    }
+
+    /// Wait until the current slot is at least `target_slot`
+    pub fn wait_for_slot(&self, target_slot: u64) -> Result<(), Stop> {
+        self.wait_until(&format!("slot {}", target_slot), || {
+            let slot = self
+                .rpc
+                .call(CallCategory::Query, "get_slot", |rpc_client| {
+                    rpc_client.get_slot()
+                })?;
+            Ok((slot, target_slot.saturating_sub(slot)))
+        })
+    }
+
+    /// Wait until the current epoch is at least `target_epoch`
+    pub fn wait_for_epoch(&self, target_epoch: u64) -> Result<(), Stop> {
+        self.wait_until(&format!("epoch {}", target_epoch), || {
+            let epoch_info =
+                self.rpc
+                    .call(CallCategory::Query, "get_epoch_info", |rpc_client| {
+                        rpc_client.get_epoch_info()
+                    })?;
+            let remaining_slots = if epoch_info.epoch >= target_epoch {
+                0
+            } else {
+                // Assumes that the following epochs are as long as the current one
+                (epoch_info.slots_in_epoch - epoch_info.slot_index)
+                    + (target_epoch - epoch_info.epoch - 1) * epoch_info.slots_in_epoch
+            };
+            Ok((epoch_info.absolute_slot, remaining_slots))
+        })
+    }
+
+    /// Poll the current slot and the slots remaining until `target` with `poll`, until no slots
+    /// remain
+    fn wait_until<F>(&self, target: &str, mut poll: F) -> Result<(), Stop>
+    where
+        F: FnMut() -> Result<(u64, u64), Stop>,
+    {
+        let mut slot_rate = SlotRate::new();
+        let mut last_advance: Option<(Instant, u64)> = None;
+        let mut last_report: Option<Instant> = None;
+        loop {
+            self.shutdown.check()?;
+            let (slot, remaining_slots) = poll()?;
+            let now = Instant::now();
+            if remaining_slots == 0 {
+                info!("Reached {} at slot {}", target, slot);
+                return Ok(());
+            }
+
+            match last_advance {
+                Some((_, last_slot)) if slot > last_slot => last_advance = Some((now, slot)),
+                Some((advanced, last_slot)) => {
+                    if now.duration_since(advanced) >= self.stall_timeout {
+                        return Err(Stop::Failed(format!(
+                            "The cluster stalled at slot {} for {:?} while waiting for {}",
+                            last_slot, self.stall_timeout, target
+                        )));
+                    }
+                }
+                None => last_advance = Some((now, slot)),
+            }
+            slot_rate.add(now, slot);
+
+            let measured_slots_per_sec = slot_rate.slots_per_sec();
+            let slots_per_sec = measured_slots_per_sec
+                .unwrap_or_else(|| 1. / utils::duration_as_secs_f64(self.target_slot_duration));
+            let eta = eta(remaining_slots, slots_per_sec);
+            if last_report.map_or(true, |reported| {
+                now.duration_since(reported) >= REPORT_INTERVAL
+            }) {
+                match measured_slots_per_sec {
+                    Some(slots_per_sec) => info!(
+                        "Waiting for {}: at slot {}, {} slots left at {:.2} slots/s, ETA {}s",
+                        target,
+                        slot,
+                        remaining_slots,
+                        slots_per_sec,
+                        eta.as_secs()
+                    ),
+                    None => info!(
+                        "Waiting for {}: at slot {}, {} slots left, ETA {}s at the target slot rate",
+                        target,
+                        slot,
+                        remaining_slots,
+                        eta.as_secs()
+                    ),
+                }
+                last_report = Some(now);
+            }
+            self.shutdown
+                .sleep(poll_interval(eta).min(self.stall_timeout))?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock_cluster::{MockCluster, SLOTS_PER_EPOCH, SLOT_DURATION};
+
+    #[test]
+    fn test_slot_rate() {
+        let start = Instant::now();
+        let mut slot_rate = SlotRate::new();
+        slot_rate.add(start, 100);
+        assert_eq!(slot_rate.slots_per_sec(), None);
+        slot_rate.add(start + Duration::from_secs(2), 100);
+        assert_eq!(slot_rate.slots_per_sec(), None);
+        slot_rate.add(start + Duration::from_secs(4), 110);
+        assert_eq!(slot_rate.slots_per_sec(), Some(2.5));
+        for i in 0..RATE_SAMPLES as u64 {
+            slot_rate.add(start + Duration::from_secs(5 + i), 200 + 10 * i);
+        }
+        assert_eq!(slot_rate.slots_per_sec(), Some(10.));
+
+        assert_eq!(eta(25, 2.5), Duration::from_secs(10));
+        assert_eq!(
+            poll_interval(Duration::from_secs(10)),
+            Duration::from_secs(5)
+        );
+        assert_eq!(poll_interval(Duration::from_secs(3600)), MAX_POLL_INTERVAL);
+        assert_eq!(poll_interval(Duration::from_millis(10)), MIN_POLL_INTERVAL);
+    }
+
+    #[test]
+    fn test_wait_for_epoch() {
+        let cluster = MockCluster::start(vec![]);
+        let rpc = cluster.rpc_pool();
+        let shutdown = Shutdown::default();
+        let waiter = SlotWaiter::new(&rpc, &shutdown, SLOT_DURATION, Duration::from_secs(10));
+
+        waiter.wait_for_epoch(2).unwrap();
+        assert!(cluster.slot() >= 2 * SLOTS_PER_EPOCH);
+        waiter.wait_for_slot(3 * SLOTS_PER_EPOCH).unwrap();
+        assert!(cluster.slot() >= 3 * SLOTS_PER_EPOCH);
+
+        shutdown.request();
+        assert_eq!(waiter.wait_for_epoch(100), Err(Stop::Interrupted));
+    }
+
+    #[test]
+    fn test_stalled_cluster() {
+        let cluster = MockCluster::start(vec![]);
+        let rpc = cluster.rpc_pool();
+        let shutdown = Shutdown::default();
+        let waiter = SlotWaiter::new(&rpc, &shutdown, SLOT_DURATION, Duration::from_millis(500));
+        cluster.wait_for_slot(10);
+        cluster.stall();
+        let slot = cluster.slot();
+        assert_eq!(
+            waiter.wait_for_slot(slot + 1),
+            Err(Stop::Failed(format!(
+                "The cluster stalled at slot {} for 500ms while waiting for slot {}",
+                slot,
+                slot + 1
+            )))
+        );
+    }
+}